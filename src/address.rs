@@ -0,0 +1,209 @@
+use std::fmt;
+use std::net::{IpAddr, Ipv6Addr};
+use std::num::ParseIntError;
+use std::str::FromStr;
+
+/// The port Java Edition servers listen on unless told otherwise.
+pub const DEFAULT_PORT: u16 = 25565;
+
+/// A server address as typed into the game's server list: a hostname, IPv4
+/// address or IPv6 address, optionally followed by `:port`. IPv6 addresses
+/// need to be in brackets to have a port, like `[2001:db8::1]:25565`.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ServerAddress {
+    /// The hostname or IP address, without brackets.
+    pub host: String,
+    pub port: Option<u16>,
+}
+
+#[derive(Debug, thiserror::Error)]
+pub enum AddressError {
+    #[error("Could not parse port as integer")]
+    InvalidPort(#[source] ParseIntError),
+
+    #[error("Server address is empty")]
+    Empty,
+
+    #[error("Missing closing ']' in IPv6 address")]
+    UnclosedBracket,
+
+    #[error("Expected an IPv6 address in brackets")]
+    InvalidIpv6,
+
+    #[error("Unexpected characters after ']'; expected ':port'")]
+    TrailingCharacters,
+}
+
+/// Which kind of IP addresses to connect to, for hosts that have both.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum AddressFamily {
+    #[default]
+    Any,
+    V4,
+    V6,
+}
+
+impl ServerAddress {
+    pub fn new(host: impl Into<String>, port: Option<u16>) -> Self {
+        ServerAddress {
+            host: host.into(),
+            port,
+        }
+    }
+
+    /// The port to connect to, falling back to [`DEFAULT_PORT`].
+    pub fn port_or_default(&self) -> u16 {
+        self.port.unwrap_or(DEFAULT_PORT)
+    }
+
+    /// Whether the game would look up an SRV record to find the server,
+    /// which it does for a hostname without a port.
+    pub fn uses_srv(&self) -> bool {
+        self.port.is_none() && self.host.parse::<IpAddr>().is_err()
+    }
+}
+
+fn parse_port(port: &str) -> Result<u16, AddressError> {
+    port.parse().map_err(AddressError::InvalidPort)
+}
+
+impl FromStr for ServerAddress {
+    type Err = AddressError;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let s = s.trim();
+        if let Some(rest) = s.strip_prefix('[') {
+            let (host, rest) = rest.split_once(']').ok_or(AddressError::UnclosedBracket)?;
+            host.parse::<Ipv6Addr>()
+                .map_err(|_| AddressError::InvalidIpv6)?;
+            let port = match rest {
+                "" => None,
+                _ => {
+                    let port = rest
+                        .strip_prefix(':')
+                        .ok_or(AddressError::TrailingCharacters)?;
+                    Some(parse_port(port)?)
+                }
+            };
+            return Ok(ServerAddress::new(host, port));
+        }
+
+        // more than one colon can only be a bare IPv6 address, which can't
+        // have a port without brackets
+        if s.parse::<Ipv6Addr>().is_ok() {
+            return Ok(ServerAddress::new(s, None));
+        }
+
+        let (host, port) = match s.split_once(':') {
+            Some((host, port)) => (host, Some(parse_port(port)?)),
+            None => (s, None),
+        };
+        if host.is_empty() {
+            return Err(AddressError::Empty);
+        }
+        Ok(ServerAddress::new(host, port))
+    }
+}
+
+impl fmt::Display for ServerAddress {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let is_ipv6 = self.host.contains(':');
+        match (self.port, is_ipv6) {
+            (Some(port), true) => write!(f, "[{}]:{}", self.host, port),
+            (Some(port), false) => write!(f, "{}:{}", self.host, port),
+            (None, _) => f.write_str(&self.host),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn parses_addresses() {
+        for (s, host, port) in [
+            ("example.com", "example.com", None),
+            ("example.com:25566", "example.com", Some(25566)),
+            ("  example.com:1 \n", "example.com", Some(1)),
+            ("127.0.0.1", "127.0.0.1", None),
+            ("127.0.0.1:65535", "127.0.0.1", Some(65535)),
+            ("[::1]:25565", "::1", Some(25565)),
+            ("[::1]", "::1", None),
+            ("2001:db8::1", "2001:db8::1", None),
+            ("::ffff:127.0.0.1", "::ffff:127.0.0.1", None),
+        ] {
+            let address = s.parse::<ServerAddress>().unwrap();
+            assert_eq!(address, ServerAddress::new(host, port), "{s:?}");
+        }
+    }
+
+    #[test]
+    fn uses_srv_for_hostnames_without_a_port() {
+        for (s, srv) in [
+            ("example.com", true),
+            ("localhost", true),
+            ("example.com:25565", false),
+            ("127.0.0.1", false),
+            ("[::1]", false),
+            ("2001:db8::1", false),
+        ] {
+            assert_eq!(s.parse::<ServerAddress>().unwrap().uses_srv(), srv, "{s:?}");
+        }
+    }
+
+    #[test]
+    fn rejects_bad_addresses() {
+        let empty = AddressError::Empty.to_string();
+        let port = "Could not parse port as integer";
+        let unclosed = AddressError::UnclosedBracket.to_string();
+        let trailing = AddressError::TrailingCharacters.to_string();
+        let ipv6 = AddressError::InvalidIpv6.to_string();
+        for (s, expected) in [
+            ("", &*empty),
+            ("   ", &empty),
+            (":25565", &empty),
+            ("host:", port),
+            ("host:65536", port),
+            ("host:-1", port),
+            ("host:1:2", port),
+            ("[::1]:", port),
+            ("[::1", &unclosed),
+            ("[::1]x", &trailing),
+            ("[example.com]:1", &ipv6),
+            ("[127.0.0.1]", &ipv6),
+        ] {
+            let err = s.parse::<ServerAddress>().unwrap_err();
+            assert_eq!(err.to_string(), expected, "{s:?}");
+        }
+    }
+
+    #[test]
+    fn displays_addresses_that_parse_back() {
+        for (address, shown) in [
+            (ServerAddress::new("example.com", None), "example.com"),
+            (
+                ServerAddress::new("example.com", Some(25565)),
+                "example.com:25565",
+            ),
+            (ServerAddress::new("127.0.0.1", Some(1)), "127.0.0.1:1"),
+            (ServerAddress::new("::1", Some(25565)), "[::1]:25565"),
+            (ServerAddress::new("2001:db8::1", None), "2001:db8::1"),
+        ] {
+            assert_eq!(address.to_string(), shown);
+            assert_eq!(shown.parse::<ServerAddress>().unwrap(), address);
+        }
+    }
+
+    #[test]
+    fn defaults_the_port() {
+        assert_eq!(
+            ServerAddress::new("localhost", None).port_or_default(),
+            25565
+        );
+        assert_eq!(
+            ServerAddress::new("localhost", Some(1)).port_or_default(),
+            1
+        );
+    }
+}
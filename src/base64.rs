@@ -0,0 +1,105 @@
+//! Standard base64, as used for server icons in both `servers.dat` and the
+//! status response's `data:` URL.
+
+const ALPHABET: &[u8; 64] = b"ABCDEFGHIJKLMNOPQRSTUVWXYZabcdefghijklmnopqrstuvwxyz0123456789+/";
+
+/// Decodes base64, ignoring whitespace (some servers wrap the favicon at 76
+/// columns, as MIME does). Padding is optional.
+pub fn decode(s: &str) -> Option<Vec<u8>> {
+    let mut out = Vec::with_capacity(s.len() / 4 * 3);
+    let mut acc = 0u32;
+    let mut bits = 0;
+    let mut padding = 0;
+    for c in s.bytes().filter(|c| !c.is_ascii_whitespace()) {
+        if c == b'=' {
+            padding += 1;
+            continue;
+        }
+        // data after padding
+        if padding > 0 {
+            return None;
+        }
+        let value = ALPHABET.iter().position(|&a| a == c)? as u32;
+        acc = acc << 6 | value;
+        bits += 6;
+        if bits >= 8 {
+            bits -= 8;
+            out.push((acc >> bits) as u8);
+        }
+    }
+    // a lone leftover character can't encode a whole byte
+    (bits < 6 && padding <= 2).then_some(out)
+}
+
+pub fn encode(data: &[u8]) -> String {
+    let mut out = String::with_capacity(data.len().div_ceil(3) * 4);
+    for chunk in data.chunks(3) {
+        let b = [
+            chunk[0],
+            *chunk.get(1).unwrap_or(&0),
+            *chunk.get(2).unwrap_or(&0),
+        ];
+        let n = u32::from(b[0]) << 16 | u32::from(b[1]) << 8 | u32::from(b[2]);
+        for i in 0..4 {
+            if i <= chunk.len() {
+                out.push(ALPHABET[(n >> (18 - 6 * i) & 0x3f) as usize] as char);
+            } else {
+                out.push('=');
+            }
+        }
+    }
+    out
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn encodes_with_padding() {
+        for (data, encoded) in [
+            (&b""[..], ""),
+            (b"f", "Zg=="),
+            (b"fo", "Zm8="),
+            (b"foo", "Zm9v"),
+            (b"foob", "Zm9vYg=="),
+            (b"fooba", "Zm9vYmE="),
+            (b"foobar", "Zm9vYmFy"),
+        ] {
+            assert_eq!(encode(data), encoded);
+            assert_eq!(decode(encoded).as_deref(), Some(data), "{encoded:?}");
+        }
+    }
+
+    #[test]
+    fn round_trips_every_byte_and_length() {
+        let data = (0..=255).collect::<Vec<u8>>();
+        for len in 0..data.len() {
+            let encoded = encode(&data[..len]);
+            assert_eq!(decode(&encoded).as_deref(), Some(&data[..len]));
+            let unpadded = encoded.trim_end_matches('=');
+            assert_eq!(decode(unpadded).as_deref(), Some(&data[..len]));
+        }
+    }
+
+    #[test]
+    fn ignores_whitespace() {
+        let data = (0..=255).collect::<Vec<u8>>();
+        let encoded = encode(&data);
+        let wrapped = encoded
+            .as_bytes()
+            .chunks(76)
+            .map(|line| std::str::from_utf8(line).unwrap())
+            .collect::<Vec<_>>()
+            .join("\r\n");
+        assert_eq!(decode(&wrapped), Some(data));
+        assert_eq!(decode(" Zm9v\tYmE =\n").as_deref(), Some(&b"fooba"[..]));
+    }
+
+    #[test]
+    fn rejects_invalid_base64() {
+        for s in ["Zm9v!", "Zm9v-_", "Z", "Zm9vY", "Zg=a", "Zg===", "Zm=9v"] {
+            assert_eq!(decode(s), None, "{s:?}");
+        }
+    }
+}
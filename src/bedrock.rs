@@ -0,0 +1,245 @@
+//! Bedrock Edition status, through the RakNet
+//! [unconnected ping](https://wiki.vg/Raknet_Protocol#Unconnected_Ping).
+
+use std::net::SocketAddr;
+use std::time::{Duration, Instant};
+
+use serde::Serialize;
+
+use crate::net::{connect_udp, random_u64, with_timeout};
+use crate::{Error, Result};
+
+/// The port Bedrock Edition servers listen on unless told otherwise.
+pub const DEFAULT_BEDROCK_PORT: u16 = 19132;
+
+const UNCONNECTED_PING: u8 = 0x01;
+const UNCONNECTED_PONG: u8 = 0x1c;
+/// RakNet's "offline message" magic, which every unconnected packet carries.
+const MAGIC: [u8; 16] = [
+    0x00, 0xff, 0xff, 0x00, 0xfe, 0xfe, 0xfe, 0xfe, 0xfd, 0xfd, 0xfd, 0xfd, 0x12, 0x34, 0x56, 0x78,
+];
+
+/// The server info from an unconnected pong, which is a `;`-separated
+/// string like `MCPE;motd;protocol;version;online;max;server id;level
+/// name;gamemode;gamemode id;port v4;port v6;`. Servers may leave off
+/// everything after the player counts.
+#[derive(Debug, Clone, Serialize)]
+pub struct BedrockStatus {
+    /// `MCPE` for Bedrock Edition, `MCEE` for Education Edition.
+    pub edition: String,
+    pub motd: String,
+    pub protocol: i32,
+    pub version: String,
+    pub online: u32,
+    pub max: u32,
+    pub server_id: Option<String>,
+    /// Shown as the second line of the MOTD in the server list.
+    pub level_name: Option<String>,
+    pub game_mode: Option<String>,
+    pub port_v4: Option<u16>,
+    pub port_v6: Option<u16>,
+}
+
+/// Sends an unconnected ping and returns the server's status along with the
+/// round-trip time.
+pub async fn ping(addr: SocketAddr, timeout: Duration) -> Result<(BedrockStatus, Duration)> {
+    let socket = connect_udp(addr).await?;
+
+    // the "time" field is only echoed back by the server, so a random value
+    // doubles as a check that the pong is the answer to our ping
+    let token = random_u64();
+    let mut packet = Vec::with_capacity(33);
+    packet.push(UNCONNECTED_PING);
+    packet.extend_from_slice(&token.to_be_bytes());
+    packet.extend_from_slice(&MAGIC);
+    packet.extend_from_slice(&random_u64().to_be_bytes());
+    let start = Instant::now();
+    socket.send(&packet).await?;
+
+    let mut buf = vec![0; 2048];
+    let len = with_timeout(timeout, async {
+        loop {
+            let len = socket.recv(&mut buf).await?;
+            let echoed = buf[..len]
+                .get(1..9)
+                .map(|b| u64::from_be_bytes(b.try_into().unwrap()));
+            if buf[..len].first() == Some(&UNCONNECTED_PONG) && echoed == Some(token) {
+                return Ok(len);
+            }
+        }
+    })
+    .await?;
+    let rtt = start.elapsed();
+
+    // id, time, server guid, magic, string length
+    let data = buf[..len]
+        .get(1 + 8 + 8 + 16..)
+        .ok_or(Error::InvalidPacket("pong is too short"))?;
+    let (str_len, data) = data.split_at(2.min(data.len()));
+    let str_len = match str_len {
+        [a, b] => usize::from(u16::from_be_bytes([*a, *b])),
+        _ => return Err(Error::InvalidPacket("pong is too short")),
+    };
+    let info = data
+        .get(..str_len)
+        .ok_or(Error::InvalidPacket("pong is too short"))?;
+    let info = std::str::from_utf8(info)
+        .map_err(|_| Error::InvalidPacket("server info is not valid UTF-8"))?;
+
+    let status = parse_server_info(info).ok_or(Error::InvalidPacket("malformed server info"))?;
+    Ok((status, rtt))
+}
+
+fn parse_server_info(info: &str) -> Option<BedrockStatus> {
+    let mut fields = info.split(';');
+    let mut next = || fields.next().filter(|s| !s.is_empty()).map(str::to_owned);
+    Some(BedrockStatus {
+        edition: next()?,
+        motd: next().unwrap_or_default(),
+        protocol: next()?.parse().ok()?,
+        version: next()?,
+        online: next()?.parse().ok()?,
+        max: next()?.parse().ok()?,
+        server_id: next(),
+        level_name: next(),
+        game_mode: next(),
+        port_v4: {
+            // skip the numeric game mode
+            next();
+            next().and_then(|s| s.parse().ok())
+        },
+        port_v6: next().and_then(|s| s.parse().ok()),
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const FULL: &str =
+        "MCPE;Dedicated Server;594;1.20.10;3;10;13253860892328930865;Bedrock level;Survival;1;19132;19133;";
+
+    #[test]
+    fn parses_full_server_info() {
+        let status = parse_server_info(FULL).unwrap();
+        assert_eq!(status.edition, "MCPE");
+        assert_eq!(status.motd, "Dedicated Server");
+        assert_eq!(status.protocol, 594);
+        assert_eq!(status.version, "1.20.10");
+        assert_eq!((status.online, status.max), (3, 10));
+        assert_eq!(status.server_id.as_deref(), Some("13253860892328930865"));
+        assert_eq!(status.level_name.as_deref(), Some("Bedrock level"));
+        assert_eq!(status.game_mode.as_deref(), Some("Survival"));
+        assert_eq!((status.port_v4, status.port_v6), (Some(19132), Some(19133)));
+    }
+
+    #[test]
+    fn parses_server_info_without_the_optional_fields() {
+        let status = parse_server_info("MCEE;Classroom;390;1.14.60;0;30").unwrap();
+        assert_eq!(status.edition, "MCEE");
+        assert_eq!((status.online, status.max), (0, 30));
+        assert_eq!(status.server_id, None);
+        assert_eq!(status.level_name, None);
+        assert_eq!(status.game_mode, None);
+        assert_eq!((status.port_v4, status.port_v6), (None, None));
+    }
+
+    #[test]
+    fn rejects_malformed_server_info() {
+        for info in [
+            "",
+            "MCPE",
+            "MCPE;motd;594;1.20.10;3",
+            "MCPE;motd;new;1.20.10;3;10",
+            "MCPE;motd;594;1.20.10;-1;10",
+            "MCPE;motd;594;;3;10",
+        ] {
+            assert!(parse_server_info(info).is_none(), "{info:?}");
+        }
+    }
+
+    fn pong(token: u64, info: &str) -> Vec<u8> {
+        let mut packet = vec![UNCONNECTED_PONG];
+        packet.extend_from_slice(&token.to_be_bytes());
+        packet.extend_from_slice(&random_u64().to_be_bytes());
+        packet.extend_from_slice(&MAGIC);
+        packet.extend_from_slice(&(info.len() as u16).to_be_bytes());
+        packet.extend_from_slice(info.as_bytes());
+        packet
+    }
+
+    /// Starts a responder on localhost that answers a ping with the packets
+    /// `respond` makes from the ping's token.
+    async fn responder(respond: impl FnOnce(u64) -> Vec<Vec<u8>> + Send + 'static) -> SocketAddr {
+        let socket = tokio::net::UdpSocket::bind("127.0.0.1:0").await.unwrap();
+        let addr = socket.local_addr().unwrap();
+        tokio::spawn(async move {
+            let mut buf = [0; 64];
+            let (len, from) = socket.recv_from(&mut buf).await.unwrap();
+            assert_eq!(len, 33);
+            assert_eq!(buf[0], UNCONNECTED_PING);
+            assert_eq!(buf[9..25], MAGIC);
+            let token = u64::from_be_bytes(buf[1..9].try_into().unwrap());
+            for packet in respond(token) {
+                socket.send_to(&packet, from).await.unwrap();
+            }
+        });
+        addr
+    }
+
+    #[tokio::test]
+    async fn pings_a_local_responder() {
+        let addr = responder(|token| vec![pong(token, FULL)]).await;
+        let (status, _) = ping(addr, Duration::from_secs(5)).await.unwrap();
+        assert_eq!(status.motd, "Dedicated Server");
+        assert_eq!(status.port_v6, Some(19133));
+    }
+
+    #[tokio::test]
+    async fn ignores_pongs_with_the_wrong_token() {
+        let addr = responder(|token| {
+            vec![
+                pong(token ^ 1, "MCPE;someone else;1;1;1;1"),
+                pong(token, "MCPE;ours;594;1.20.10;3;10;"),
+            ]
+        })
+        .await;
+        let (status, _) = ping(addr, Duration::from_secs(5)).await.unwrap();
+        assert_eq!(status.motd, "ours");
+
+        // nor is a packet too short to have a token, even when the last
+        // packet's bytes are still in the buffer
+        let addr = responder(|token| {
+            vec![
+                pong(token ^ 1, FULL),
+                vec![UNCONNECTED_PONG],
+                pong(token.wrapping_add(1), FULL),
+            ]
+        })
+        .await;
+        let res = ping(addr, Duration::from_millis(100)).await;
+        assert!(matches!(res, Err(Error::Timeout)), "{res:?}");
+    }
+
+    #[tokio::test]
+    async fn rejects_malformed_pongs() {
+        // cut off in the middle of the server info
+        let addr = responder(|token| {
+            let mut packet = pong(token, FULL);
+            packet.truncate(packet.len() - 10);
+            vec![packet]
+        })
+        .await;
+        let res = ping(addr, Duration::from_secs(5)).await;
+        assert!(matches!(res, Err(Error::InvalidPacket(_))), "{res:?}");
+
+        // too short to get to the server info
+        let addr = responder(|token| vec![pong(token, FULL)[..20].to_vec()]).await;
+        let res = ping(addr, Duration::from_secs(5)).await;
+        assert!(matches!(res, Err(Error::InvalidPacket(_))), "{res:?}");
+
+        let addr = responder(|token| vec![pong(token, "MCPE;no counts")]).await;
+        let res = ping(addr, Duration::from_secs(5)).await;
+        assert!(matches!(res, Err(Error::InvalidPacket(_))), "{res:?}");
+    }
+}
@@ -0,0 +1,351 @@
+//! The `check` subcommand, which follows the Nagios plugin conventions so
+//! that Nagios, Icinga, Zabbix and friends can run it directly: a one-line
+//! summary with perfdata after a `|`, and the state in the exit code.
+
+use std::fmt::{self, Write};
+use std::process::ExitCode;
+
+use mcserverstatus::{QueryOptions, ServerStatus};
+
+use crate::exit::ErrorKind;
+use crate::{query, Millis};
+
+#[derive(clap::Args)]
+pub struct CheckArgs {
+    /// A server alias from the config file, or a server address
+    #[clap(value_name = "ALIAS|SERVER")]
+    pub server: String,
+
+    /// Warn when the latency is over MS milliseconds
+    #[clap(long, value_name = "MS")]
+    warn_latency: Option<f64>,
+
+    /// Go critical when the latency is over MS milliseconds
+    #[clap(long, value_name = "MS")]
+    crit_latency: Option<f64>,
+
+    /// Warn when fewer than N players are online
+    #[clap(long, value_name = "N")]
+    min_players: Option<u32>,
+
+    /// Warn when more than RATIO of the slots are taken, from 0 to 1
+    #[clap(long, value_name = "RATIO", value_parser = parse_ratio)]
+    max_fill_ratio: Option<f64>,
+
+    /// Go critical when the version name doesn't contain VERSION, e.g.
+    /// after an update that players haven't caught up with
+    #[clap(long, value_name = "VERSION")]
+    expect_version: Option<String>,
+}
+
+fn parse_ratio(s: &str) -> Result<f64, String> {
+    s.parse::<f64>()
+        .ok()
+        .filter(|ratio| (0.0..=1.0).contains(ratio))
+        .ok_or_else(|| format!("{s:?} is not a number from 0 to 1"))
+}
+
+/// The plugin states, which double as exit codes.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+enum State {
+    Ok = 0,
+    Warning = 1,
+    Critical = 2,
+    Unknown = 3,
+}
+
+impl fmt::Display for State {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.write_str(match self {
+            State::Ok => "OK",
+            State::Warning => "WARNING",
+            State::Critical => "CRITICAL",
+            State::Unknown => "UNKNOWN",
+        })
+    }
+}
+
+pub async fn check(args: &CheckArgs, server_str: &str, options: &QueryOptions) -> ExitCode {
+    let hidden = indicatif::ProgressBar::hidden();
+    let (state, line) = match query(server_str, options, &hidden).await {
+        Ok(status) => evaluate(args, &status),
+        Err(e) => {
+            // a server that can't be reached is down, but anything else is
+            // more likely our problem or a confused server
+            let state = match ErrorKind::of(&e).is_unreachable() {
+                true => State::Critical,
+                false => State::Unknown,
+            };
+            (state, format!("{e:#}"))
+        }
+    };
+    report(state, &line)
+}
+
+/// Reports an error that kept the check from querying the server at all,
+/// like a broken config file.
+pub fn unknown(err: &anyhow::Error) -> ExitCode {
+    report(State::Unknown, &format!("{err:#}"))
+}
+
+fn report(state: State, line: &str) -> ExitCode {
+    println!("MINECRAFT {state} - {line}");
+    ExitCode::from(state as u8)
+}
+
+fn evaluate(args: &CheckArgs, status: &ServerStatus) -> (State, String) {
+    let latency = status.latency.avg_ms;
+    let fill = match status.max {
+        0 => 0.0,
+        max => f64::from(status.online) / f64::from(max),
+    };
+
+    let mut state = State::Ok;
+    let mut problems = Vec::new();
+    let mut problem = |level: State, msg: String| {
+        state = state.max(level);
+        problems.push(msg);
+    };
+    if let Some(crit) = args.crit_latency.filter(|&crit| latency > crit) {
+        problem(
+            State::Critical,
+            format!("latency {}ms is over {crit}ms", Millis(latency)),
+        );
+    } else if let Some(warn) = args.warn_latency.filter(|&warn| latency > warn) {
+        problem(
+            State::Warning,
+            format!("latency {}ms is over {warn}ms", Millis(latency)),
+        );
+    }
+    if let Some(min) = args.min_players.filter(|&min| status.online < min) {
+        problem(
+            State::Warning,
+            format!("{} players online, expected at least {min}", status.online),
+        );
+    }
+    if args.max_fill_ratio.is_some_and(|ratio| fill > ratio) {
+        problem(
+            State::Warning,
+            format!("server is {:.0}% full", fill * 100.0),
+        );
+    }
+    if let Some(expected) = &args.expect_version {
+        if !status.version.name.contains(&**expected) {
+            problem(
+                State::Critical,
+                format!(
+                    "version is {:?}, expected {expected:?}",
+                    status.version.name
+                ),
+            );
+        }
+    }
+
+    let mut line = String::new();
+    for problem in &problems {
+        write!(line, "{problem}; ").unwrap();
+    }
+    write!(
+        line,
+        "{}/{} players online, {}ms latency",
+        status.online,
+        status.max,
+        Millis(latency)
+    )
+    .unwrap();
+    if !status.version.name.is_empty() {
+        write!(line, ", version {}", status.version.name).unwrap();
+    }
+
+    // perfdata is label=value[unit];warn;crit;min;max, where a threshold of
+    // "N:" alerts below N and a plain "N" alerts above it
+    let threshold = |value: Option<f64>| value.map(|v| v.to_string()).unwrap_or_default();
+    write!(
+        line,
+        " | players={};{};;0;{} latency={:.3}ms;{};{};0 fill={:.4};{};;0;1",
+        status.online,
+        args.min_players
+            .map(|min| format!("{min}:"))
+            .unwrap_or_default(),
+        status.max,
+        latency,
+        threshold(args.warn_latency),
+        threshold(args.crit_latency),
+        fill,
+        threshold(args.max_fill_ratio),
+    )
+    .unwrap();
+    (state, line)
+}
+
+#[cfg(test)]
+mod tests {
+    use std::time::Duration;
+
+    use mcserverstatus::motd::Text;
+    use mcserverstatus::{Latency, Version};
+
+    use super::*;
+
+    fn status(online: u32, max: u32, latency_ms: u64) -> ServerStatus {
+        ServerStatus {
+            online,
+            max,
+            players: Vec::new(),
+            version: Version {
+                name: "Paper 1.20.1".to_owned(),
+                protocol: 763,
+            },
+            motd: Text::default(),
+            favicon: None,
+            icon: None,
+            latency: Latency::from_samples(&[Duration::from_millis(latency_ms)]),
+            bedrock: None,
+            query: None,
+        }
+    }
+
+    fn args() -> CheckArgs {
+        CheckArgs {
+            server: "localhost".to_owned(),
+            warn_latency: Some(100.0),
+            crit_latency: Some(500.0),
+            min_players: Some(2),
+            max_fill_ratio: Some(0.9),
+            expect_version: None,
+        }
+    }
+
+    #[test]
+    fn checks_latency() {
+        for (latency, state) in [
+            (20, State::Ok),
+            // the thresholds themselves are still fine
+            (100, State::Ok),
+            (101, State::Warning),
+            (500, State::Warning),
+            (501, State::Critical),
+        ] {
+            let (got, _) = evaluate(&args(), &status(5, 20, latency));
+            assert_eq!(got, state, "{latency}ms");
+        }
+        let (state, _) = evaluate(
+            &CheckArgs {
+                crit_latency: None,
+                ..args()
+            },
+            &status(5, 20, 900),
+        );
+        assert_eq!(state, State::Warning);
+        let (state, _) = evaluate(
+            &CheckArgs {
+                warn_latency: None,
+                crit_latency: None,
+                ..args()
+            },
+            &status(5, 20, 900),
+        );
+        assert_eq!(state, State::Ok);
+    }
+
+    #[test]
+    fn checks_players() {
+        for (online, max, state) in [
+            (5, 20, State::Ok),
+            (2, 20, State::Ok),
+            (1, 20, State::Warning),
+            (0, 20, State::Warning),
+            (18, 20, State::Ok),
+            (19, 20, State::Warning),
+            (20, 20, State::Warning),
+            // servers can report 0 slots
+            (2, 0, State::Ok),
+        ] {
+            let (got, _) = evaluate(&args(), &status(online, max, 20));
+            assert_eq!(got, state, "{online}/{max}");
+        }
+    }
+
+    #[test]
+    fn checks_the_version() {
+        let expect = |version: &str| CheckArgs {
+            expect_version: Some(version.to_owned()),
+            ..args()
+        };
+        assert_eq!(evaluate(&expect("1.20.1"), &status(5, 20, 20)).0, State::Ok);
+        let (state, line) = evaluate(&expect("1.20.2"), &status(5, 20, 20));
+        assert_eq!(state, State::Critical);
+        assert!(line.starts_with(r#"version is "Paper 1.20.1", expected "1.20.2"; "#));
+    }
+
+    #[test]
+    fn reports_the_worst_problem() {
+        let (state, line) = evaluate(&args(), &status(1, 1, 501));
+        assert_eq!(state, State::Critical);
+        assert_eq!(
+            line,
+            "latency 501ms is over 500ms; 1 players online, expected at least 2; \
+             server is 100% full; 1/1 players online, 501ms latency, version Paper 1.20.1 \
+             | players=1;2:;;0;1 latency=501.000ms;100;500;0 fill=1.0000;0.9;;0;1"
+        );
+    }
+
+    #[test]
+    fn writes_perfdata() {
+        let (state, line) = evaluate(&args(), &status(5, 20, 42));
+        assert_eq!(state, State::Ok);
+        assert_eq!(
+            line,
+            "5/20 players online, 42ms latency, version Paper 1.20.1 \
+             | players=5;2:;;0;20 latency=42.000ms;100;500;0 fill=0.2500;0.9;;0;1"
+        );
+
+        let no_thresholds = CheckArgs {
+            warn_latency: None,
+            crit_latency: None,
+            min_players: None,
+            max_fill_ratio: None,
+            ..args()
+        };
+        let mut legacy = status(0, 0, 5);
+        legacy.version.name.clear();
+        let (_, line) = evaluate(&no_thresholds, &legacy);
+        assert_eq!(
+            line,
+            "0/0 players online, 5.0ms latency \
+             | players=0;;;0;0 latency=5.000ms;;;0 fill=0.0000;;;0;1"
+        );
+    }
+
+    #[test]
+    fn parses_ratios() {
+        assert_eq!(parse_ratio("0"), Ok(0.0));
+        assert_eq!(parse_ratio("0.75"), Ok(0.75));
+        assert_eq!(parse_ratio("1"), Ok(1.0));
+        for bad in ["1.5", "-0.1", "half", "NaN"] {
+            assert_eq!(
+                parse_ratio(bad),
+                Err(format!("{bad:?} is not a number from 0 to 1"))
+            );
+        }
+    }
+
+    #[test]
+    fn states_are_exit_codes() {
+        for (state, code, shown) in [
+            (State::Ok, 0, "OK"),
+            (State::Warning, 1, "WARNING"),
+            (State::Critical, 2, "CRITICAL"),
+            (State::Unknown, 3, "UNKNOWN"),
+        ] {
+            assert_eq!(state as u8, code);
+            assert_eq!(state.to_string(), shown);
+        }
+    }
+
+    #[test]
+    fn errors_before_the_query_are_unknown() {
+        let err = anyhow::anyhow!("line 1: unknown key `colour`").context("Invalid config");
+        assert_eq!(unknown(&err), ExitCode::from(3));
+    }
+}
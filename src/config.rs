@@ -0,0 +1,413 @@
+//! The config file, `mcserverstatus/config.toml` in the config directory:
+//! `~/.config` on Linux, `~/Library/Application Support` on macOS and
+//! `%APPDATA%` on Windows.
+//!
+//! ```toml
+//! timeout = 5.0
+//! format = "json"
+//! instance = "All the Mods 9"
+//!
+//! [aliases]
+//! survival = "mc.example.net:25566"
+//! ```
+//!
+//! Only the bits of TOML that this needs are supported: comments, `[table]`
+//! headers, and keys with string, number or boolean values.
+
+use std::collections::BTreeMap;
+use std::fs;
+use std::io;
+use std::path::{Path, PathBuf};
+use std::time::Duration;
+
+use anyhow::{bail, Context};
+
+use crate::OutputFormat;
+
+#[derive(Debug, Clone, Default)]
+pub struct Config {
+    pub timeout: Option<Duration>,
+    pub format: Option<OutputFormat>,
+    pub instance: Option<PathBuf>,
+    pub aliases: BTreeMap<String, String>,
+}
+
+/// A config file that couldn't be read or parsed.
+#[derive(Debug, thiserror::Error)]
+#[error("{0:#}")]
+pub struct ConfigError(anyhow::Error);
+
+#[derive(Debug, Clone, PartialEq)]
+enum Value {
+    String(String),
+    Number(f64),
+    Bool(bool),
+}
+
+impl Value {
+    fn type_name(&self) -> &'static str {
+        match self {
+            Value::String(_) => "a string",
+            Value::Number(_) => "a number",
+            Value::Bool(_) => "a boolean",
+        }
+    }
+
+    fn string(self, key: &str) -> anyhow::Result<String> {
+        match self {
+            Value::String(s) => Ok(s),
+            value => bail!("`{key}` should be a string, not {}", value.type_name()),
+        }
+    }
+
+    fn number(self, key: &str) -> anyhow::Result<f64> {
+        match self {
+            Value::Number(n) => Ok(n),
+            value => bail!("`{key}` should be a number, not {}", value.type_name()),
+        }
+    }
+}
+
+/// Where the config file lives if `--config` isn't given.
+pub fn default_path() -> Option<PathBuf> {
+    dirs_next::config_dir().map(|dir| dir.join("mcserverstatus").join("config.toml"))
+}
+
+impl Config {
+    /// Loads the config from `path`, or from the default location if that's
+    /// `None`. A missing file is only an error if the path was given
+    /// explicitly.
+    pub fn load(path: Option<&Path>) -> Result<Self, ConfigError> {
+        Config::load_inner(path).map_err(ConfigError)
+    }
+
+    fn load_inner(path: Option<&Path>) -> anyhow::Result<Self> {
+        let (path, explicit) = match path {
+            Some(path) => (path.to_owned(), true),
+            None => match default_path() {
+                Some(path) => (path, false),
+                None => return Ok(Config::default()),
+            },
+        };
+        let source = match fs::read_to_string(&path) {
+            Err(e) if e.kind() == io::ErrorKind::NotFound && !explicit => {
+                return Ok(Config::default())
+            }
+            res => res.with_context(|| format!("Couldn't read {}", path.display()))?,
+        };
+        Config::parse(&source).with_context(|| format!("Invalid config in {}", path.display()))
+    }
+
+    fn parse(source: &str) -> anyhow::Result<Self> {
+        let mut config = Config::default();
+        let mut table = String::new();
+        for (i, line) in source.lines().enumerate() {
+            let line_no = i + 1;
+            let line = strip_comment(line).trim();
+            if line.is_empty() {
+                continue;
+            }
+            if let Some(header) = line.strip_prefix('[') {
+                let name = header
+                    .strip_suffix(']')
+                    .with_context(|| format!("line {line_no}: missing ']' in table header"))?;
+                table = name.trim().to_owned();
+                if table != "aliases" {
+                    bail!("line {line_no}: unknown table [{table}]");
+                }
+                continue;
+            }
+
+            let (key, value) = line
+                .split_once('=')
+                .with_context(|| format!("line {line_no}: expected `key = value`"))?;
+            let key = parse_key(key.trim())
+                .with_context(|| format!("line {line_no}: invalid key {:?}", key.trim()))?;
+            let value = parse_value(value.trim())
+                .with_context(|| format!("line {line_no}: invalid value for `{key}`"))?;
+            if let Err(e) = config.set(&table, &key, value) {
+                bail!("line {line_no}: {e}");
+            }
+        }
+        Ok(config)
+    }
+
+    fn set(&mut self, table: &str, key: &str, value: Value) -> anyhow::Result<()> {
+        match (table, key) {
+            ("aliases", _) => {
+                self.aliases.insert(key.to_owned(), value.string(key)?);
+            }
+            ("", "timeout") => {
+                let secs = value.number(key)?;
+                match crate::secs_to_duration(secs) {
+                    Some(timeout) => self.timeout = Some(timeout),
+                    None => bail!("`timeout` must be a positive number of seconds"),
+                }
+            }
+            ("", "format") => {
+                self.format = Some(match &*value.string(key)? {
+                    "text" => OutputFormat::Text,
+                    "json" => OutputFormat::Json,
+                    _ => bail!("`format` must be \"text\" or \"json\""),
+                });
+            }
+            ("", "instance") => self.instance = Some(value.string(key)?.into()),
+            _ => bail!("unknown key `{key}`"),
+        }
+        Ok(())
+    }
+
+    /// Looks up `name` in the aliases, and otherwise takes it to be an
+    /// address already.
+    pub fn resolve_alias<'a>(&'a self, name: &'a str) -> &'a str {
+        self.aliases.get(name).map_or(name, |addr| addr)
+    }
+}
+
+/// Cuts off a `#` comment, leaving any `#` inside a string alone.
+fn strip_comment(line: &str) -> &str {
+    let mut quote = None;
+    let mut escaped = false;
+    for (i, c) in line.char_indices() {
+        match (quote, c) {
+            (Some('"'), '\\') if !escaped => {
+                escaped = true;
+                continue;
+            }
+            (Some(q), c) if c == q && !escaped => quote = None,
+            (None, '"' | '\'') => quote = Some(c),
+            (None, '#') => return &line[..i],
+            _ => {}
+        }
+        escaped = false;
+    }
+    line
+}
+
+fn parse_key(key: &str) -> Option<String> {
+    if let Ok(Value::String(key)) = parse_value(key) {
+        return Some(key);
+    }
+    let bare = !key.is_empty()
+        && key
+            .chars()
+            .all(|c| c.is_ascii_alphanumeric() || c == '_' || c == '-');
+    bare.then(|| key.to_owned())
+}
+
+fn parse_value(value: &str) -> anyhow::Result<Value> {
+    if let Some(rest) = value.strip_prefix('\'') {
+        let s = rest.strip_suffix('\'').context("unterminated string")?;
+        if s.contains('\'') {
+            bail!("unexpected characters after string");
+        }
+        return Ok(Value::String(s.to_owned()));
+    }
+    if let Some(rest) = value.strip_prefix('"') {
+        let s = rest.strip_suffix('"').context("unterminated string")?;
+        let mut out = String::with_capacity(s.len());
+        let mut chars = s.chars();
+        while let Some(c) = chars.next() {
+            if c == '"' {
+                bail!("unexpected characters after string");
+            }
+            if c != '\\' {
+                out.push(c);
+                continue;
+            }
+            match chars.next() {
+                Some('"') => out.push('"'),
+                Some('\\') => out.push('\\'),
+                Some('n') => out.push('\n'),
+                Some('t') => out.push('\t'),
+                _ => bail!("unsupported escape sequence"),
+            }
+        }
+        return Ok(Value::String(out));
+    }
+    match value {
+        "true" => return Ok(Value::Bool(true)),
+        "false" => return Ok(Value::Bool(false)),
+        _ => {}
+    }
+    value
+        .replace('_', "")
+        .parse()
+        .map(Value::Number)
+        .map_err(|_| anyhow::anyhow!("expected a string, number or boolean"))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn parses_a_config() {
+        let config = Config::parse(
+            r##"
+# a comment
+timeout = 2.5   # seconds
+format = "json"
+instance = 'All the Mods 9'
+
+[aliases]
+survival = "mc.example.net:25566"
+"creative # 2" = "[::1]:25565"
+'raw' = '127.0.0.1'
+  [ aliases ]
+hub-1 = "hub.example.net"
+"##,
+        )
+        .unwrap();
+        assert_eq!(config.timeout, Some(Duration::from_millis(2500)));
+        assert_eq!(config.format, Some(OutputFormat::Json));
+        assert_eq!(
+            config.instance.as_deref(),
+            Some(Path::new("All the Mods 9"))
+        );
+        assert_eq!(
+            config.aliases.into_iter().collect::<Vec<_>>(),
+            [
+                ("creative # 2", "[::1]:25565"),
+                ("hub-1", "hub.example.net"),
+                ("raw", "127.0.0.1"),
+                ("survival", "mc.example.net:25566"),
+            ]
+            .map(|(k, v)| (k.to_owned(), v.to_owned()))
+        );
+    }
+
+    #[test]
+    fn parses_values() {
+        for (source, value) in [
+            (r#""plain""#, Value::String("plain".into())),
+            (r#""""#, Value::String("".into())),
+            (
+                r#""a \"b\" \\ \n\t""#,
+                Value::String("a \"b\" \\ \n\t".into()),
+            ),
+            (r"'no \escapes'", Value::String(r"no \escapes".into())),
+            (r#"'"quoted"'"#, Value::String(r#""quoted""#.into())),
+            ("5", Value::Number(5.0)),
+            ("-0.5", Value::Number(-0.5)),
+            ("1_000", Value::Number(1000.0)),
+            ("1e3", Value::Number(1000.0)),
+            ("true", Value::Bool(true)),
+            ("false", Value::Bool(false)),
+        ] {
+            assert_eq!(parse_value(source).unwrap(), value, "{source}");
+        }
+    }
+
+    #[test]
+    fn rejects_bad_values() {
+        for (source, err) in [
+            (r#""open"#, "unterminated string"),
+            ("'open", "unterminated string"),
+            (r#"""#, "unterminated string"),
+            (r#""a" "b""#, "unexpected characters after string"),
+            ("'a' 'b'", "unexpected characters after string"),
+            (r#""trailing \""#, "unsupported escape sequence"),
+            (r#""\x41""#, "unsupported escape sequence"),
+            ("yes", "expected a string, number or boolean"),
+            ("", "expected a string, number or boolean"),
+        ] {
+            assert_eq!(
+                parse_value(source).unwrap_err().to_string(),
+                err,
+                "{source}"
+            );
+        }
+    }
+
+    #[test]
+    fn strips_comments_outside_strings() {
+        for (line, stripped) in [
+            ("a = 1 # comment", "a = 1 "),
+            ("# whole line", ""),
+            (r##"a = "# not a comment""##, r##"a = "# not a comment""##),
+            ("a = '# nor this' # but this", "a = '# nor this' "),
+            (r##"a = "\"#" # b"##, r##"a = "\"#" "##),
+            (r#"a = "\\" # b"#, r#"a = "\\" "#),
+            (r#"a = 'it"s' # b"#, r#"a = 'it"s' "#),
+        ] {
+            assert_eq!(strip_comment(line), stripped, "{line}");
+        }
+    }
+
+    #[test]
+    fn rejects_bad_configs() {
+        for (source, err) in [
+            ("[servers]", "line 1: unknown table [servers]"),
+            ("[aliases", "line 1: missing ']' in table header"),
+            ("\ntimeout", "line 2: expected `key = value`"),
+            ("bad key = 1", "line 1: invalid key \"bad key\""),
+            ("= 1", "line 1: invalid key \"\""),
+            (
+                "format = 'yaml'",
+                "line 1: `format` must be \"text\" or \"json\"",
+            ),
+            ("format = json", "line 1: invalid value for `format`"),
+            (
+                "instance = 1",
+                "line 1: `instance` should be a string, not a number",
+            ),
+            ("colour = true", "line 1: unknown key `colour`"),
+            (
+                "[aliases]\ntimeout = 5",
+                "line 2: `timeout` should be a string, not a number",
+            ),
+            (
+                "timeout = '5'",
+                "line 1: `timeout` should be a number, not a string",
+            ),
+        ] {
+            let e = Config::parse(source).unwrap_err();
+            assert_eq!(e.to_string(), err, "{source}");
+        }
+    }
+
+    #[test]
+    fn rejects_timeouts_that_are_not_a_positive_duration() {
+        for timeout in ["0", "-1", "nan", "NaN", "inf", "-inf", "infinity", "1e30"] {
+            let e = Config::parse(&format!("timeout = {timeout}")).unwrap_err();
+            assert_eq!(
+                e.to_string(),
+                "line 1: `timeout` must be a positive number of seconds",
+                "{timeout}"
+            );
+        }
+        assert!(Config::parse("timeout = 0.001").is_ok());
+    }
+
+    #[test]
+    fn resolves_aliases() {
+        let config = Config::parse("[aliases]\nsurvival = \"mc.example.net:25566\"").unwrap();
+        assert_eq!(config.resolve_alias("survival"), "mc.example.net:25566");
+        assert_eq!(config.resolve_alias("Survival"), "Survival");
+        assert_eq!(config.resolve_alias("localhost"), "localhost");
+    }
+
+    #[test]
+    fn loads_only_an_existing_explicit_path() {
+        let missing = Path::new("/nonexistent/mcserverstatus.toml");
+        let e = Config::load(Some(missing)).unwrap_err();
+        assert!(
+            e.to_string()
+                .starts_with("Couldn't read /nonexistent/mcserverstatus.toml: "),
+            "{e}"
+        );
+
+        let path = std::env::temp_dir().join(format!("config-{}.toml", std::process::id()));
+        fs::write(&path, "timeout = 3\ncolor = 1\n").unwrap();
+        let res = Config::load(Some(&path));
+        fs::remove_file(&path).unwrap();
+        assert_eq!(
+            res.unwrap_err().to_string(),
+            format!(
+                "Invalid config in {}: line 2: unknown key `color`",
+                path.display()
+            )
+        );
+    }
+}
@@ -0,0 +1,366 @@
+//! Going through a query one step at a time and timing each step, to find
+//! out where it goes wrong when a server seems to be down.
+
+use std::fmt;
+use std::future::Future;
+use std::net::SocketAddr;
+use std::time::{Duration, Instant};
+
+use itertools::Itertools;
+use serde::Serialize;
+
+use crate::bedrock::{self, DEFAULT_BEDROCK_PORT};
+use crate::dns::{Resolver, SrvRecord};
+use crate::net::{self, random_u64};
+use crate::ping::StatusResponse;
+use crate::query;
+use crate::status::{connect_first, connect_legacy, connect_modern};
+use crate::{Error, Protocol, QueryOptions, Result, ServerAddress};
+
+/// A step of a query.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum StepKind {
+    /// Looking up the `_minecraft._tcp` SRV record.
+    SrvLookup,
+    /// Looking up the host's IP addresses.
+    Resolve,
+    Connect,
+    /// Sending the handshake that asks for the server's status.
+    Handshake,
+    /// Waiting for the status response.
+    Status,
+    Ping,
+    /// The GameSpy4 query.
+    Query,
+}
+
+impl fmt::Display for StepKind {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.write_str(match self {
+            StepKind::SrvLookup => "SRV lookup",
+            StepKind::Resolve => "DNS lookup",
+            StepKind::Connect => "Connect",
+            StepKind::Handshake => "Handshake",
+            StepKind::Status => "Status",
+            StepKind::Ping => "Ping",
+            StepKind::Query => "Query",
+        })
+    }
+}
+
+/// How one step of a query went.
+#[derive(Debug)]
+pub struct Step {
+    pub kind: StepKind,
+    pub duration: Duration,
+    /// What the step found out, like the addresses a host resolved to.
+    pub detail: String,
+    /// Why the step failed. Only the last step can fail, since there's no
+    /// going on after that.
+    pub error: Option<Error>,
+}
+
+/// Queries a server like [`query_status`](crate::query_status), but stops
+/// to time each step and note what it found. [`Protocol::Auto`] speaks the
+/// modern ping only, since falling back to the legacy one would hide where
+/// the modern one failed, and for the same reason there are no retries. The
+/// deadline isn't enforced either, as the steps up to it are the point.
+pub async fn diagnose(addr: &ServerAddress, options: &QueryOptions) -> Vec<Step> {
+    let mut steps = Steps(Vec::new());
+    // the steps stop at the first failure, which is already in `steps`
+    let _ = match options.protocol {
+        Protocol::Bedrock => diagnose_bedrock(addr, options, &mut steps).await,
+        protocol => diagnose_java(protocol, addr, options, &mut steps).await,
+    };
+    steps.0
+}
+
+struct Steps(Vec<Step>);
+
+impl Steps {
+    /// Runs and times one step, describing its result with `detail`.
+    async fn run<T>(
+        &mut self,
+        kind: StepKind,
+        fut: impl Future<Output = Result<T>>,
+        detail: impl FnOnce(&T) -> String,
+    ) -> Option<T> {
+        let start = Instant::now();
+        let res = fut.await;
+        let duration = start.elapsed();
+        let (detail, error, value) = match res {
+            Ok(value) => (detail(&value), None, Some(value)),
+            Err(e) => (String::new(), Some(e), None),
+        };
+        self.0.push(Step {
+            kind,
+            duration,
+            detail,
+            error,
+        });
+        value
+    }
+}
+
+async fn diagnose_java(
+    protocol: Protocol,
+    addr: &ServerAddress,
+    options: &QueryOptions,
+    steps: &mut Steps,
+) -> Option<()> {
+    let (host, port) = srv_target(addr, options, steps).await;
+
+    let addrs = resolve(&host, port, options, steps).await?;
+
+    if protocol == Protocol::Legacy {
+        let (conn, server_addr) = steps
+            .run(
+                StepKind::Connect,
+                connect_first(&addrs, |addr| connect_legacy(addr, &host, options)),
+                |(_, addr)| addr.to_string(),
+            )
+            .await?;
+        steps
+            .run(StepKind::Status, conn.status(), |status| {
+                let version = match &status.version {
+                    Some((_, name)) => name.as_str(),
+                    None => "no version",
+                };
+                format!("{version}, {}/{} players", status.online, status.max)
+            })
+            .await?;
+        return full_query(server_addr, options, steps).await;
+    }
+
+    let (mut conn, server_addr) = steps
+        .run(
+            StepKind::Connect,
+            connect_first(&addrs, |addr| connect_modern(addr, &host, options)),
+            |(_, addr)| addr.to_string(),
+        )
+        .await?;
+    steps
+        .run(StepKind::Handshake, conn.handshake(), |_| {
+            format!("asked for {host}:{}", server_addr.port())
+        })
+        .await?;
+    let status = async {
+        let json = conn.status_json().await?;
+        let status = serde_json::from_str::<StatusResponse>(&json)?;
+        Ok((json.len(), status))
+    };
+    steps
+        .run(StepKind::Status, status, |(len, status)| {
+            format!(
+                "{len} bytes, {} (protocol {}), {}/{} players",
+                status.version.name,
+                status.version.protocol,
+                status.players.online,
+                status.players.max
+            )
+        })
+        .await?;
+    steps
+        .run(StepKind::Ping, conn.ping(random_u64()), |_| {
+            "got the pong back".to_owned()
+        })
+        .await?;
+    full_query(server_addr, options, steps).await
+}
+
+/// Looks for an SRV record like [`query_status`](crate::query_status) does,
+/// returning the host and port to connect to. A failed lookup isn't the end
+/// of the query, since the game falls back to the plain host.
+async fn srv_target(
+    addr: &ServerAddress,
+    options: &QueryOptions,
+    steps: &mut Steps,
+) -> (String, u16) {
+    let fallback = (addr.host.clone(), addr.port_or_default());
+    if !addr.uses_srv() {
+        return fallback;
+    }
+    let resolver = match options.dns_server {
+        Some(server) => Resolver::new(server),
+        None => match Resolver::system() {
+            Some(resolver) => resolver,
+            None => {
+                steps.0.push(Step {
+                    kind: StepKind::SrvLookup,
+                    duration: Duration::ZERO,
+                    detail: "skipped, couldn't find the system's DNS server".to_owned(),
+                    error: None,
+                });
+                return fallback;
+            }
+        },
+    };
+
+    let name = format!("_minecraft._tcp.{}", addr.host);
+    let lookup = async { Ok(resolver.lookup_srv(&name, options.read_timeout).await) };
+    let records = steps
+        .run(StepKind::SrvLookup, lookup, |res| {
+            let (host, port) = &fallback;
+            match res {
+                Ok(records) if !records.is_empty() => {
+                    let targets = records
+                        .iter()
+                        .map(|record| format!("{}:{}", record.target, record.port))
+                        .join(", ");
+                    format!("{name} -> {targets}")
+                }
+                Ok(_) => format!("no record for {name}, using {host}:{port}"),
+                Err(e) => format!("lookup failed ({e}), using {host}:{port}"),
+            }
+        })
+        .await;
+    // only the first target, which is the one the game tries first
+    match records.and_then(|res| res.ok()).unwrap_or_default().first() {
+        Some(SrvRecord { target, port, .. }) => (target.clone(), *port),
+        None => fallback,
+    }
+}
+
+async fn resolve(
+    host: &str,
+    port: u16,
+    options: &QueryOptions,
+    steps: &mut Steps,
+) -> Option<Vec<SocketAddr>> {
+    let lookup = net::resolve(host, port, options.family);
+    steps
+        .run(StepKind::Resolve, lookup, |addrs| {
+            let ips = addrs.iter().map(|addr| addr.ip()).join(", ");
+            format!("{host} -> {ips}")
+        })
+        .await
+}
+
+async fn diagnose_bedrock(
+    addr: &ServerAddress,
+    options: &QueryOptions,
+    steps: &mut Steps,
+) -> Option<()> {
+    let port = addr.port.unwrap_or(DEFAULT_BEDROCK_PORT);
+    let addrs = resolve(&addr.host, port, options, steps).await?;
+    // like query_status, only the first address, as there's no connection
+    // to tell us whether it works
+    let server_addr = addrs[0];
+    steps
+        .run(
+            StepKind::Ping,
+            bedrock::ping(server_addr, options.read_timeout),
+            |(status, _)| {
+                format!(
+                    "{server_addr}: {} (protocol {}), {}/{} players",
+                    status.version, status.protocol, status.online, status.max
+                )
+            },
+        )
+        .await?;
+    full_query(server_addr, options, steps).await
+}
+
+async fn full_query(
+    server_addr: SocketAddr,
+    options: &QueryOptions,
+    steps: &mut Steps,
+) -> Option<()> {
+    if !options.full_query {
+        return Some(());
+    }
+    let port = options.query_port.unwrap_or(server_addr.port());
+    let query_addr = SocketAddr::new(server_addr.ip(), port);
+    let stat = async {
+        query::full_stat(query_addr, options.read_timeout)
+            .await
+            .map_err(|e| Error::Query(Box::new(e)))
+    };
+    steps
+        .run(StepKind::Query, stat, |stat| {
+            format!(
+                "{query_addr}: {} players, map {}",
+                stat.players.len(),
+                stat.map
+            )
+        })
+        .await?;
+    Some(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn kinds(steps: &[Step]) -> Vec<StepKind> {
+        steps.iter().map(|step| step.kind).collect()
+    }
+
+    fn options() -> QueryOptions {
+        QueryOptions {
+            connect_timeout: Duration::from_millis(500),
+            read_timeout: Duration::from_millis(500),
+            ..QueryOptions::default()
+        }
+    }
+
+    #[tokio::test]
+    async fn stops_at_the_failed_connection() {
+        let closed = std::net::TcpListener::bind("127.0.0.1:0")
+            .unwrap()
+            .local_addr()
+            .unwrap();
+        let addr = ServerAddress::new("127.0.0.1", Some(closed.port()));
+        let steps = diagnose(&addr, &options()).await;
+        // an IP and port leave nothing to look up an SRV record for
+        assert_eq!(kinds(&steps), [StepKind::Resolve, StepKind::Connect]);
+        assert_eq!(steps[0].detail, "127.0.0.1 -> 127.0.0.1");
+        assert!(steps[0].error.is_none());
+        assert!(
+            matches!(steps[1].error, Some(Error::Connect(_))),
+            "{steps:?}"
+        );
+    }
+
+    #[tokio::test]
+    async fn stops_when_the_server_hangs_up() {
+        let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let port = listener.local_addr().unwrap().port();
+        tokio::spawn(async move {
+            let (stream, _) = listener.accept().await.unwrap();
+            drop(stream);
+        });
+        let addr = ServerAddress::new("127.0.0.1", Some(port));
+        let steps = diagnose(&addr, &options()).await;
+        let failed = steps.last().unwrap();
+        assert!(failed.error.is_some(), "{steps:?}");
+        assert_eq!(steps[1].detail, format!("127.0.0.1:{port}"));
+        assert!(
+            matches!(failed.kind, StepKind::Handshake | StepKind::Status),
+            "{steps:?}"
+        );
+        assert!(steps[..steps.len() - 1]
+            .iter()
+            .all(|step| step.error.is_none()));
+    }
+
+    #[tokio::test]
+    async fn falls_back_when_the_srv_lookup_fails() {
+        // a DNS server that never answers
+        let stub = tokio::net::UdpSocket::bind("127.0.0.1:0").await.unwrap();
+        let options = QueryOptions {
+            read_timeout: Duration::from_millis(100),
+            dns_server: Some(stub.local_addr().unwrap()),
+            ..QueryOptions::default()
+        };
+        let addr = ServerAddress::new("localhost", None);
+        let steps = diagnose(&addr, &options).await;
+        assert_eq!(kinds(&steps[..2]), [StepKind::SrvLookup, StepKind::Resolve]);
+        assert_eq!(
+            steps[0].detail,
+            "lookup failed (timed out waiting for the server), using localhost:25565"
+        );
+        assert!(steps[0].error.is_none());
+    }
+}
@@ -0,0 +1,525 @@
+//! Just enough of a DNS client to look up the `_minecraft._tcp` SRV records
+//! that the game uses to find a server's real host and port.
+
+use std::net::{IpAddr, SocketAddr};
+use std::process::Command;
+use std::sync::OnceLock;
+use std::time::Duration;
+
+use crate::net::{connect_udp, random_u64, with_timeout};
+use crate::{Error, Result};
+
+const TYPE_SRV: u16 = 33;
+const CLASS_IN: u16 = 1;
+const RCODE_NXDOMAIN: u16 = 3;
+
+/// A DNS server to send SRV queries to.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Resolver {
+    pub server: SocketAddr,
+}
+
+/// A record from an SRV lookup.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct SrvRecord {
+    pub priority: u16,
+    pub weight: u16,
+    pub port: u16,
+    pub target: String,
+}
+
+impl Resolver {
+    pub fn new(server: SocketAddr) -> Self {
+        Resolver { server }
+    }
+
+    /// The system's DNS server: the first nameserver from `/etc/resolv.conf`
+    /// on Unix, or from the network interfaces' settings in the registry on
+    /// Windows. Other platforms need it passed explicitly.
+    pub fn system() -> Option<Self> {
+        let ip = if cfg!(windows) {
+            // asking `reg` takes a moment, and the answer won't change while
+            // we run
+            static NAMESERVER: OnceLock<Option<IpAddr>> = OnceLock::new();
+            *NAMESERVER.get_or_init(|| {
+                let output = Command::new("reg")
+                    .args(["query", INTERFACES_KEY, "/s"])
+                    .output()
+                    .ok()?;
+                registry_nameserver(&String::from_utf8_lossy(&output.stdout))
+            })
+        } else if cfg!(unix) {
+            let conf = std::fs::read_to_string("/etc/resolv.conf").ok()?;
+            resolv_conf_nameserver(&conf)
+        } else {
+            None
+        }?;
+        Some(Resolver::new((ip, 53).into()))
+    }
+
+    /// Looks up the SRV records for `name`, returning them in the order they
+    /// should be tried: by priority, and randomly weighted within a priority
+    /// as described in RFC 2782. A name with no records gives an empty list.
+    pub async fn lookup_srv(&self, name: &str, timeout: Duration) -> Result<Vec<SrvRecord>> {
+        let id = random_u64() as u16;
+        let query = build_query(id, name)?;
+
+        let socket = connect_udp(self.server).await?;
+        socket.send(&query).await?;
+        let mut buf = vec![0; 4096];
+        let response = with_timeout(timeout, async {
+            loop {
+                let len = socket.recv(&mut buf).await?;
+                if len >= 2 && buf[..2] == id.to_be_bytes() {
+                    return Ok(&buf[..len]);
+                }
+            }
+        })
+        .await?;
+
+        let mut records = parse_response(response)?;
+        order_records(&mut records);
+        Ok(records)
+    }
+}
+
+/// Where Windows keeps each network interface's DNS settings.
+const INTERFACES_KEY: &str = r"HKLM\SYSTEM\CurrentControlSet\Services\Tcpip\Parameters\Interfaces";
+
+fn resolv_conf_nameserver(conf: &str) -> Option<IpAddr> {
+    conf.lines()
+        .filter_map(|line| line.trim().strip_prefix("nameserver"))
+        .map(str::trim)
+        // a link-local IPv6 server has a %scope naming the interface it's
+        // on, and without that it can't be reached
+        .filter(|ip| !ip.contains('%'))
+        .find_map(|ip| ip.parse().ok())
+}
+
+/// Finds the first DNS server in the output of `reg query` for the
+/// interfaces key, preferring a manually set `NameServer` over one from
+/// DHCP. The values are lists of IPs, separated by commas or spaces.
+fn registry_nameserver(output: &str) -> Option<IpAddr> {
+    let values = |name: &'static str| {
+        output.lines().filter_map(move |line| {
+            let mut fields = line.split_whitespace();
+            (fields.next()? == name && fields.next()?.starts_with("REG_"))
+                .then(|| fields.collect::<Vec<_>>().join(" "))
+        })
+    };
+    values("NameServer")
+        .chain(values("DhcpNameServer"))
+        .flat_map(|value| {
+            value
+                .split([',', ' '])
+                .filter_map(|ip| ip.parse().ok())
+                .collect::<Vec<_>>()
+        })
+        .next()
+}
+
+fn build_query(id: u16, name: &str) -> Result<Vec<u8>> {
+    let mut msg = Vec::with_capacity(name.len() + 18);
+    msg.extend_from_slice(&id.to_be_bytes());
+    // flags: recursion desired
+    msg.extend_from_slice(&0x0100u16.to_be_bytes());
+    // one question, no answer/authority/additional records
+    msg.extend_from_slice(&[0, 1, 0, 0, 0, 0, 0, 0]);
+    for label in name.trim_end_matches('.').split('.') {
+        if label.is_empty() || label.len() > 63 {
+            return Err(Error::Dns("invalid domain name"));
+        }
+        msg.push(label.len() as u8);
+        msg.extend_from_slice(label.as_bytes());
+    }
+    msg.push(0);
+    msg.extend_from_slice(&TYPE_SRV.to_be_bytes());
+    msg.extend_from_slice(&CLASS_IN.to_be_bytes());
+    Ok(msg)
+}
+
+fn parse_response(msg: &[u8]) -> Result<Vec<SrvRecord>> {
+    let truncated = || Error::Dns("truncated response");
+    let header = msg.get(..12).ok_or_else(truncated)?;
+    let u16_at = |i: usize| u16::from_be_bytes([header[i], header[i + 1]]);
+    let flags = u16_at(2);
+    match flags & 0xf {
+        0 => {}
+        RCODE_NXDOMAIN => return Ok(Vec::new()),
+        _ => return Err(Error::Dns("server returned an error")),
+    }
+    let (questions, answers) = (u16_at(4), u16_at(6));
+
+    let mut pos = 12;
+    for _ in 0..questions {
+        read_name(msg, &mut pos)?;
+        // type and class
+        pos += 4;
+    }
+
+    let mut records = Vec::new();
+    for _ in 0..answers {
+        read_name(msg, &mut pos)?;
+        let fixed = msg.get(pos..pos + 10).ok_or_else(truncated)?;
+        let rtype = u16::from_be_bytes([fixed[0], fixed[1]]);
+        let rdlen = usize::from(u16::from_be_bytes([fixed[8], fixed[9]]));
+        pos += 10;
+        let rdata_end = pos + rdlen;
+        if rdata_end > msg.len() {
+            return Err(truncated());
+        }
+        // there may be CNAMEs along the way, which we don't care about
+        if rtype == TYPE_SRV && rdlen >= 6 {
+            let rdata = &msg[pos..rdata_end];
+            let mut target_pos = pos + 6;
+            let target = read_name(msg, &mut target_pos)?;
+            records.push(SrvRecord {
+                priority: u16::from_be_bytes([rdata[0], rdata[1]]),
+                weight: u16::from_be_bytes([rdata[2], rdata[3]]),
+                port: u16::from_be_bytes([rdata[4], rdata[5]]),
+                target,
+            });
+        }
+        pos = rdata_end;
+    }
+
+    // a target of "." means the service is explicitly not available
+    records.retain(|r| !r.target.is_empty());
+    Ok(records)
+}
+
+/// Reads a possibly-compressed domain name starting at `pos`, and moves
+/// `pos` past it.
+fn read_name(msg: &[u8], pos: &mut usize) -> Result<String> {
+    let truncated = || Error::Dns("truncated response");
+    let mut labels = Vec::new();
+    let mut cursor = *pos;
+    let mut jumped = false;
+    // bounds the number of compression pointers we follow, so a malicious
+    // response can't loop forever
+    for _ in 0..128 {
+        let len = *msg.get(cursor).ok_or_else(truncated)?;
+        match len {
+            0 => {
+                if !jumped {
+                    *pos = cursor + 1;
+                }
+                return Ok(labels.join("."));
+            }
+            len if len & 0xc0 == 0xc0 => {
+                let low = *msg.get(cursor + 1).ok_or_else(truncated)?;
+                if !jumped {
+                    *pos = cursor + 2;
+                    jumped = true;
+                }
+                cursor = usize::from(u16::from_be_bytes([len & 0x3f, low]));
+            }
+            len => {
+                let start = cursor + 1;
+                let label = msg
+                    .get(start..start + usize::from(len))
+                    .ok_or_else(truncated)?;
+                labels.push(String::from_utf8_lossy(label).into_owned());
+                cursor = start + usize::from(len);
+            }
+        }
+    }
+    Err(Error::Dns("too many compression pointers"))
+}
+
+/// Sorts records by priority, shuffling each priority's records by weight.
+fn order_records(records: &mut Vec<SrvRecord>) {
+    records.sort_by_key(|r| r.priority);
+    let mut ordered = Vec::with_capacity(records.len());
+    let mut rest = std::mem::take(records);
+    while !rest.is_empty() {
+        let priority = rest[0].priority;
+        let same = rest.iter().take_while(|r| r.priority == priority).count();
+        let mut group = rest.drain(..same).collect::<Vec<_>>();
+        while !group.is_empty() {
+            let total = group.iter().map(|r| u64::from(r.weight)).sum::<u64>();
+            let mut pick = random_u64() % (total + 1);
+            let index = group
+                .iter()
+                .position(|r| {
+                    let w = u64::from(r.weight);
+                    if pick <= w {
+                        true
+                    } else {
+                        pick -= w;
+                        false
+                    }
+                })
+                .unwrap_or(0);
+            ordered.push(group.remove(index));
+        }
+    }
+    *records = ordered;
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// The offset of `example.com` in the question for
+    /// `_minecraft._tcp.example.com`, for compression pointers to point at.
+    const EXAMPLE_COM: u8 = 12 + 11 + 5;
+
+    fn header(id: u16, rcode: u16, questions: u16, answers: u16) -> Vec<u8> {
+        let mut msg = id.to_be_bytes().to_vec();
+        msg.extend_from_slice(&(0x8180 | rcode).to_be_bytes());
+        msg.extend_from_slice(&questions.to_be_bytes());
+        msg.extend_from_slice(&answers.to_be_bytes());
+        msg.extend_from_slice(&[0, 0, 0, 0]);
+        msg
+    }
+
+    fn name(name: &str) -> Vec<u8> {
+        let mut out = Vec::new();
+        for label in name.split('.').filter(|label| !label.is_empty()) {
+            out.push(label.len() as u8);
+            out.extend_from_slice(label.as_bytes());
+        }
+        out.push(0);
+        out
+    }
+
+    fn question() -> Vec<u8> {
+        let mut out = name("_minecraft._tcp.example.com");
+        out.extend_from_slice(&TYPE_SRV.to_be_bytes());
+        out.extend_from_slice(&CLASS_IN.to_be_bytes());
+        out
+    }
+
+    fn answer(rtype: u16, rdata: &[u8]) -> Vec<u8> {
+        // the owner name points back at the question
+        let mut out = vec![0xc0, 12];
+        out.extend_from_slice(&rtype.to_be_bytes());
+        out.extend_from_slice(&CLASS_IN.to_be_bytes());
+        out.extend_from_slice(&300u32.to_be_bytes());
+        out.extend_from_slice(&(rdata.len() as u16).to_be_bytes());
+        out.extend_from_slice(rdata);
+        out
+    }
+
+    fn srv(priority: u16, weight: u16, port: u16, target: &[u8]) -> Vec<u8> {
+        let mut rdata = Vec::new();
+        rdata.extend_from_slice(&priority.to_be_bytes());
+        rdata.extend_from_slice(&weight.to_be_bytes());
+        rdata.extend_from_slice(&port.to_be_bytes());
+        rdata.extend_from_slice(target);
+        answer(TYPE_SRV, &rdata)
+    }
+
+    fn record(priority: u16, weight: u16, port: u16, target: &str) -> SrvRecord {
+        SrvRecord {
+            priority,
+            weight,
+            port,
+            target: target.to_owned(),
+        }
+    }
+
+    fn response(id: u16) -> Vec<u8> {
+        let mut msg = header(id, 0, 1, 3);
+        msg.extend(question());
+        // a CNAME along the way, which is skipped
+        msg.extend(answer(5, &name("alias.example.com")));
+        // "mc" followed by a pointer to "example.com"
+        msg.extend(srv(10, 5, 25566, &[2, b'm', b'c', 0xc0, EXAMPLE_COM]));
+        msg.extend(srv(20, 0, 25565, &name("backup.example.net")));
+        msg
+    }
+
+    #[test]
+    fn parses_srv_records() {
+        assert_eq!(
+            parse_response(&response(0)).unwrap(),
+            [
+                record(10, 5, 25566, "mc.example.com"),
+                record(20, 0, 25565, "backup.example.net"),
+            ]
+        );
+    }
+
+    #[test]
+    fn nxdomain_is_no_records() {
+        let mut msg = header(0, RCODE_NXDOMAIN, 1, 0);
+        msg.extend(question());
+        assert_eq!(parse_response(&msg).unwrap(), []);
+    }
+
+    #[test]
+    fn other_errors_fail() {
+        // SERVFAIL
+        let msg = header(0, 2, 0, 0);
+        assert!(matches!(parse_response(&msg), Err(Error::Dns(_))));
+    }
+
+    #[test]
+    fn dot_target_means_no_service() {
+        let mut msg = header(0, 0, 1, 1);
+        msg.extend(question());
+        msg.extend(srv(0, 0, 0, &[0]));
+        assert_eq!(parse_response(&msg).unwrap(), []);
+    }
+
+    #[test]
+    fn truncated_responses_fail() {
+        let msg = response(0);
+        for len in [0, 5, 11, 20, msg.len() - 30, msg.len() - 1] {
+            assert!(
+                matches!(parse_response(&msg[..len]), Err(Error::Dns(_))),
+                "cut to {len} bytes"
+            );
+        }
+    }
+
+    #[test]
+    fn reads_names() {
+        let msg = name("mc.example.com");
+        let mut pos = 0;
+        assert_eq!(read_name(&msg, &mut pos).unwrap(), "mc.example.com");
+        assert_eq!(pos, msg.len());
+
+        let mut pos = 0;
+        assert_eq!(read_name(&[0], &mut pos).unwrap(), "");
+        assert_eq!(pos, 1);
+    }
+
+    #[test]
+    fn follows_compression_pointers() {
+        // "example.com" at 0, then "mc" and a pointer to it at 13
+        let mut msg = name("example.com");
+        msg.extend_from_slice(&[2, b'm', b'c', 0xc0, 0]);
+        msg.extend_from_slice(&[0xff; 4]);
+        let mut pos = 13;
+        assert_eq!(read_name(&msg, &mut pos).unwrap(), "mc.example.com");
+        // past the pointer, not past where it pointed to
+        assert_eq!(pos, 18);
+    }
+
+    #[test]
+    fn pointer_loops_fail() {
+        let mut pos = 0;
+        assert!(read_name(&[0xc0, 0], &mut pos).is_err());
+        let mut pos = 0;
+        assert!(read_name(&[1, b'a', 0xc0, 0], &mut pos).is_err());
+    }
+
+    #[test]
+    fn truncated_names_fail() {
+        for msg in [&[][..], &[3, b'a', b'b'], &[1, b'a'], &[0xc0]] {
+            let mut pos = 0;
+            assert!(read_name(msg, &mut pos).is_err(), "{msg:?}");
+        }
+    }
+
+    #[test]
+    fn orders_by_priority() {
+        for _ in 0..20 {
+            let mut records = vec![
+                record(20, 1, 1, "c"),
+                record(10, 100, 2, "b1"),
+                record(30, 0, 3, "d"),
+                record(10, 1, 4, "b2"),
+                record(5, 0, 5, "a"),
+            ];
+            order_records(&mut records);
+            let priorities = records.iter().map(|r| r.priority).collect::<Vec<_>>();
+            assert_eq!(priorities, [5, 10, 10, 20, 30]);
+            let mut middle = [&*records[1].target, &*records[2].target];
+            middle.sort();
+            assert_eq!(middle, ["b1", "b2"]);
+        }
+    }
+
+    #[test]
+    fn weights_favor_heavier_records() {
+        let heavy_first = (0..200)
+            .filter(|_| {
+                let mut records = vec![record(0, 1, 1, "light"), record(0, 99, 2, "heavy")];
+                order_records(&mut records);
+                records[0].target == "heavy"
+            })
+            .count();
+        assert!(heavy_first > 150, "heavy first {heavy_first}/200 times");
+    }
+
+    #[test]
+    fn finds_resolv_conf_nameserver() {
+        let conf = "# generated\nsearch lan\nnameserver fe80::1%eth0\nnameserver 1.1.1.1\n";
+        assert_eq!(
+            resolv_conf_nameserver(conf),
+            Some("1.1.1.1".parse().unwrap())
+        );
+        assert_eq!(
+            resolv_conf_nameserver("nameserver  2001:db8::53 \n"),
+            Some("2001:db8::53".parse().unwrap())
+        );
+        assert_eq!(resolv_conf_nameserver("nameserver fe80::1%2\n"), None);
+        assert_eq!(resolv_conf_nameserver("search lan\n"), None);
+    }
+
+    #[test]
+    fn finds_registry_nameserver() {
+        let output = r"
+HKEY_LOCAL_MACHINE\SYSTEM\CurrentControlSet\Services\Tcpip\Parameters\Interfaces\{1}
+    EnableDHCP    REG_DWORD    0x1
+    NameServer    REG_SZ
+    DhcpNameServer    REG_SZ    192.168.1.1 192.168.1.2
+
+HKEY_LOCAL_MACHINE\SYSTEM\CurrentControlSet\Services\Tcpip\Parameters\Interfaces\{2}
+    NameServer    REG_SZ    9.9.9.9,1.1.1.1
+";
+        assert_eq!(
+            registry_nameserver(output),
+            Some("9.9.9.9".parse().unwrap())
+        );
+        let dhcp_only = output.replace("9.9.9.9,1.1.1.1", "");
+        assert_eq!(
+            registry_nameserver(&dhcp_only),
+            Some("192.168.1.1".parse().unwrap())
+        );
+        assert_eq!(registry_nameserver(""), None);
+    }
+
+    /// Looks up against a stub DNS server on localhost, which first sends a
+    /// response for some other query that should be ignored.
+    #[tokio::test]
+    async fn looks_up_against_a_stub_server() {
+        let stub = tokio::net::UdpSocket::bind("127.0.0.1:0").await.unwrap();
+        let resolver = Resolver::new(stub.local_addr().unwrap());
+        tokio::spawn(async move {
+            let mut buf = [0; 512];
+            let (len, from) = stub.recv_from(&mut buf).await.unwrap();
+            assert!(buf[..len].ends_with(&question()));
+            let id = u16::from_be_bytes([buf[0], buf[1]]);
+            let other = response(id.wrapping_add(1));
+            stub.send_to(&other, from).await.unwrap();
+            stub.send_to(&response(id), from).await.unwrap();
+        });
+
+        let records = resolver
+            .lookup_srv("_minecraft._tcp.example.com", Duration::from_secs(5))
+            .await
+            .unwrap();
+        assert_eq!(
+            records,
+            [
+                record(10, 5, 25566, "mc.example.com"),
+                record(20, 0, 25565, "backup.example.net"),
+            ]
+        );
+    }
+
+    #[tokio::test]
+    async fn times_out_without_an_answer() {
+        let stub = tokio::net::UdpSocket::bind("127.0.0.1:0").await.unwrap();
+        let resolver = Resolver::new(stub.local_addr().unwrap());
+        let res = resolver
+            .lookup_srv("_minecraft._tcp.example.com", Duration::from_millis(100))
+            .await;
+        assert!(matches!(res, Err(Error::Timeout)), "{res:?}");
+    }
+}
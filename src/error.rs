@@ -0,0 +1,38 @@
+use std::io;
+use std::time::Duration;
+
+pub type Result<T, E = Error> = std::result::Result<T, E>;
+
+/// An error that happened while querying a server.
+#[derive(Debug, thiserror::Error)]
+pub enum Error {
+    #[error("failed to resolve server address")]
+    Resolve(#[source] io::Error),
+
+    #[error("failed to connect to server")]
+    Connect(#[source] io::Error),
+
+    #[error("timed out waiting for the server")]
+    Timeout,
+
+    #[error("gave up after the {0:?} deadline")]
+    Deadline(Duration),
+
+    #[error("error reading or writing data")]
+    Io(#[from] io::Error),
+
+    #[error("invalid packet from server: {0}")]
+    InvalidPacket(&'static str),
+
+    #[error("invalid status JSON from server")]
+    InvalidJson(#[from] serde_json::Error),
+
+    #[error("DNS lookup failed: {0}")]
+    Dns(&'static str),
+
+    #[error("full query failed, is enable-query on?")]
+    Query(#[source] Box<Error>),
+
+    #[error("mismatched pong payload (expected {expected}, got {actual})")]
+    MismatchedPayload { expected: u64, actual: u64 },
+}
@@ -0,0 +1,245 @@
+//! Sorting failures into categories, so that scripts can tell a server
+//! that's down from a typo in their config without parsing messages.
+
+use std::io;
+
+use mcserverstatus::{AddressError, Error};
+use serde::Serialize;
+
+use crate::config::ConfigError;
+use crate::{CtrlC, ServersDatError};
+
+/// Printed as part of `--help`.
+pub const EXIT_CODES_HELP: &str = "\
+EXIT CODES:
+    0    Success
+    1    Any other error
+    2    Invalid command line
+    3    DNS lookup failed (dns)
+    4    Couldn't connect, e.g. connection refused (connect)
+    5    Timed out (timeout)
+    6    The server's response didn't make sense (protocol)
+    7    Couldn't find or read servers.dat (servers_dat)
+    8    Invalid server address (address)
+    9    Invalid config file (config)
+    130  Cancelled with ctrl-c (cancelled)
+
+The `check` subcommand uses 0-3 for OK/WARNING/CRITICAL/UNKNOWN instead.";
+
+/// What kind of failure an error is. With `--format json`, this is the
+/// `error_kind` field next to `error`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum ErrorKind {
+    Other,
+    Dns,
+    Connect,
+    Timeout,
+    Protocol,
+    ServersDat,
+    Address,
+    Config,
+    Cancelled,
+}
+
+impl ErrorKind {
+    pub fn of(err: &anyhow::Error) -> Self {
+        err.chain()
+            .find_map(|cause| {
+                if let Some(e) = cause.downcast_ref::<Error>() {
+                    Some(ErrorKind::of_query(e))
+                } else if cause.is::<AddressError>() {
+                    Some(ErrorKind::Address)
+                } else if cause.is::<ServersDatError>() {
+                    Some(ErrorKind::ServersDat)
+                } else if cause.is::<ConfigError>() {
+                    Some(ErrorKind::Config)
+                } else if cause.is::<CtrlC>() {
+                    Some(ErrorKind::Cancelled)
+                } else {
+                    None
+                }
+            })
+            .unwrap_or(ErrorKind::Other)
+    }
+
+    fn of_query(err: &Error) -> Self {
+        match err {
+            Error::Resolve(_) | Error::Dns(_) => ErrorKind::Dns,
+            Error::Connect(e) if e.kind() == io::ErrorKind::TimedOut => ErrorKind::Timeout,
+            Error::Connect(_) => ErrorKind::Connect,
+            Error::Timeout | Error::Deadline(_) => ErrorKind::Timeout,
+            Error::Io(e) if e.kind() == io::ErrorKind::TimedOut => ErrorKind::Timeout,
+            Error::Query(e) => ErrorKind::of_query(e),
+            // a connection that drops halfway through is usually something
+            // that isn't a Minecraft server hanging up on us
+            Error::Io(_)
+            | Error::InvalidPacket(_)
+            | Error::InvalidJson(_)
+            | Error::MismatchedPayload { .. } => ErrorKind::Protocol,
+        }
+    }
+
+    pub fn exit_code(self) -> u8 {
+        match self {
+            ErrorKind::Other => 1,
+            ErrorKind::Dns => 3,
+            ErrorKind::Connect => 4,
+            ErrorKind::Timeout => 5,
+            ErrorKind::Protocol => 6,
+            ErrorKind::ServersDat => 7,
+            ErrorKind::Address => 8,
+            ErrorKind::Config => 9,
+            ErrorKind::Cancelled => 130,
+        }
+    }
+
+    /// Whether the server looks to be down or unreachable, as opposed to
+    /// us being asked the wrong thing.
+    pub fn is_unreachable(self) -> bool {
+        matches!(
+            self,
+            ErrorKind::Dns | ErrorKind::Connect | ErrorKind::Timeout
+        )
+    }
+}
+
+/// A failure, for JSON output.
+#[derive(Serialize)]
+pub struct ErrorOutput {
+    pub error: String,
+    pub error_kind: ErrorKind,
+}
+
+impl ErrorOutput {
+    pub fn new(err: &anyhow::Error) -> Self {
+        ErrorOutput {
+            error: format!("{err:#}"),
+            error_kind: ErrorKind::of(err),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::path::Path;
+    use std::time::Duration;
+
+    use anyhow::Context;
+
+    use super::*;
+    use crate::config::Config;
+
+    fn io(kind: io::ErrorKind) -> io::Error {
+        io::Error::new(kind, "oops")
+    }
+
+    #[test]
+    fn sorts_query_errors() {
+        let json = serde_json::from_str::<serde_json::Value>("{").unwrap_err();
+        for (err, kind) in [
+            (Error::Resolve(io(io::ErrorKind::NotFound)), ErrorKind::Dns),
+            (Error::Dns("truncated response"), ErrorKind::Dns),
+            (
+                Error::Connect(io(io::ErrorKind::ConnectionRefused)),
+                ErrorKind::Connect,
+            ),
+            (
+                Error::Connect(io(io::ErrorKind::TimedOut)),
+                ErrorKind::Timeout,
+            ),
+            (Error::Timeout, ErrorKind::Timeout),
+            (Error::Deadline(Duration::from_secs(5)), ErrorKind::Timeout),
+            (Error::Io(io(io::ErrorKind::TimedOut)), ErrorKind::Timeout),
+            (
+                Error::Io(io(io::ErrorKind::UnexpectedEof)),
+                ErrorKind::Protocol,
+            ),
+            (Error::InvalidPacket("bad"), ErrorKind::Protocol),
+            (Error::InvalidJson(json), ErrorKind::Protocol),
+            (
+                Error::MismatchedPayload {
+                    expected: 1,
+                    actual: 2,
+                },
+                ErrorKind::Protocol,
+            ),
+            (Error::Query(Box::new(Error::Timeout)), ErrorKind::Timeout),
+            (
+                Error::Query(Box::new(Error::Connect(io(
+                    io::ErrorKind::ConnectionRefused,
+                )))),
+                ErrorKind::Connect,
+            ),
+        ] {
+            let shown = format!("{err:?}");
+            assert_eq!(ErrorKind::of(&err.into()), kind, "{shown}");
+        }
+    }
+
+    #[test]
+    fn sorts_other_errors() {
+        let config = Config::load(Some(Path::new("/nonexistent/config.toml"))).unwrap_err();
+        for (err, kind) in [
+            (anyhow::Error::new(AddressError::Empty), ErrorKind::Address),
+            (
+                ServersDatError("no servers.dat".to_owned()).into(),
+                ErrorKind::ServersDat,
+            ),
+            (config.into(), ErrorKind::Config),
+            (CtrlC.into(), ErrorKind::Cancelled),
+            (anyhow::anyhow!("something else"), ErrorKind::Other),
+            (io(io::ErrorKind::TimedOut).into(), ErrorKind::Other),
+        ] {
+            let shown = format!("{err:#}");
+            assert_eq!(ErrorKind::of(&err), kind, "{shown}");
+        }
+    }
+
+    #[test]
+    fn looks_through_context() {
+        let err = Err::<(), _>(Error::Timeout)
+            .context("Couldn't query mc.example.net")
+            .context("Couldn't ping everything")
+            .unwrap_err();
+        assert_eq!(ErrorKind::of(&err), ErrorKind::Timeout);
+        let output = ErrorOutput::new(&err);
+        assert_eq!(
+            output.error,
+            "Couldn't ping everything: Couldn't query mc.example.net: timed out waiting for the server"
+        );
+        assert_eq!(
+            serde_json::to_value(output).unwrap()["error_kind"],
+            "timeout"
+        );
+    }
+
+    #[test]
+    fn maps_kinds_to_exit_codes() {
+        for (kind, code, unreachable) in [
+            (ErrorKind::Other, 1, false),
+            (ErrorKind::Dns, 3, true),
+            (ErrorKind::Connect, 4, true),
+            (ErrorKind::Timeout, 5, true),
+            (ErrorKind::Protocol, 6, false),
+            (ErrorKind::ServersDat, 7, false),
+            (ErrorKind::Address, 8, false),
+            (ErrorKind::Config, 9, false),
+            (ErrorKind::Cancelled, 130, false),
+        ] {
+            assert_eq!(kind.exit_code(), code, "{kind:?}");
+            assert_eq!(kind.is_unreachable(), unreachable, "{kind:?}");
+            let name = serde_json::to_value(kind).unwrap();
+            assert!(
+                EXIT_CODES_HELP.contains(&format!("{code:<5}")),
+                "{kind:?} isn't in the help"
+            );
+            if kind != ErrorKind::Other {
+                assert!(
+                    EXIT_CODES_HELP.contains(&format!("({})", name.as_str().unwrap())),
+                    "{kind:?} isn't in the help"
+                );
+            }
+        }
+    }
+}
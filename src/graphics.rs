@@ -0,0 +1,337 @@
+//! Drawing server icons in the terminal, with whichever image protocol it
+//! speaks, or with colored half blocks when it doesn't speak any.
+
+use std::env;
+use std::fmt::Write;
+
+use clap::ArgEnum;
+use console::{Term, TermTarget};
+use mcserverstatus::base64;
+use mcserverstatus::icon::Icon;
+use mcserverstatus::motd::Rgb;
+
+/// Icons are drawn this many rows tall, and twice as many columns wide.
+pub const ICON_ROWS: u32 = 8;
+
+/// How big sixel icons are, since we can't ask the terminal how large its
+/// cells are. This is about 8 rows in most fonts.
+const SIXEL_SIZE: u32 = 128;
+
+/// Alpha below which a pixel counts as transparent, for the outputs that
+/// can't blend.
+const ALPHA_THRESHOLD: u8 = 128;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, ArgEnum)]
+pub enum IconMode {
+    /// Pick based on the terminal, and don't draw icons when the output
+    /// isn't one
+    Auto,
+    /// The kitty graphics protocol (kitty, Ghostty, WezTerm)
+    Kitty,
+    /// iTerm2 inline images (iTerm2, WezTerm, mintty)
+    Iterm,
+    /// Sixel graphics (foot, mlterm, Konsole, xterm -ti vt340)
+    Sixel,
+    /// Unicode half blocks, which work anywhere with colors
+    Blocks,
+    /// Don't draw icons
+    Never,
+}
+
+impl IconMode {
+    /// Works out what `Auto` means for drawing on `term`. Returns `None` if
+    /// icons shouldn't be drawn at all.
+    pub fn resolve(self, term: &Term) -> Option<IconMode> {
+        match self {
+            IconMode::Never => None,
+            IconMode::Auto => {
+                let colors = match term.target() {
+                    TermTarget::Stderr => console::colors_enabled_stderr(),
+                    _ => console::colors_enabled(),
+                };
+                if !term.is_term() || !colors {
+                    return None;
+                }
+                Some(detect())
+            }
+            mode => Some(mode),
+        }
+    }
+}
+
+/// Guesses the best protocol from the environment. Querying the terminal
+/// would be more reliable, but needs raw mode and a timeout for terminals
+/// that never answer.
+fn detect() -> IconMode {
+    let var = |name: &str| env::var(name).unwrap_or_default();
+    let term = var("TERM");
+    let program = var("TERM_PROGRAM");
+    if term == "xterm-kitty" || term == "xterm-ghostty" || env::var_os("KITTY_WINDOW_ID").is_some()
+    {
+        IconMode::Kitty
+    } else if matches!(&*program, "iTerm.app" | "WezTerm" | "mintty") {
+        IconMode::Iterm
+    } else if term.starts_with("foot")
+        || term.starts_with("mlterm")
+        || term.contains("sixel")
+        || env::var_os("KONSOLE_VERSION").is_some()
+    {
+        IconMode::Sixel
+    } else {
+        IconMode::Blocks
+    }
+}
+
+/// Renders `icon` as [`ICON_ROWS`] lines of terminal output, ending in a
+/// newline.
+pub fn render(icon: &Icon, mode: IconMode) -> String {
+    match mode {
+        IconMode::Kitty => kitty(icon),
+        IconMode::Iterm => iterm(icon),
+        IconMode::Sixel => sixel(&icon.resize(SIXEL_SIZE, SIXEL_SIZE)),
+        _ => blocks(&icon.resize(ICON_ROWS * 2, ICON_ROWS * 2)),
+    }
+}
+
+/// A one-line, four-column thumbnail of the icon, for lists.
+pub fn thumbnail(icon: &Icon) -> String {
+    let mut out = blocks(&icon.resize(4, 2));
+    out.pop();
+    out
+}
+
+fn kitty(icon: &Icon) -> String {
+    let data = base64::encode(&icon.png);
+    let mut out = String::new();
+    // the payload has to be sent in chunks of at most 4096 bytes
+    let chunks = data.as_bytes().chunks(4096).collect::<Vec<_>>();
+    for (i, chunk) in chunks.iter().enumerate() {
+        let more = u8::from(i + 1 < chunks.len());
+        let chunk = std::str::from_utf8(chunk).unwrap();
+        if i == 0 {
+            let (cols, rows) = (ICON_ROWS * 2, ICON_ROWS);
+            write!(
+                out,
+                "\x1b_Ga=T,f=100,q=2,c={cols},r={rows},m={more};{chunk}\x1b\\"
+            )
+            .unwrap();
+        } else {
+            write!(out, "\x1b_Gm={more};{chunk}\x1b\\").unwrap();
+        }
+    }
+    out.push('\n');
+    out
+}
+
+fn iterm(icon: &Icon) -> String {
+    format!(
+        "\x1b]1337;File=inline=1;size={};width={};height={};preserveAspectRatio=1:{}\x07\n",
+        icon.png.len(),
+        ICON_ROWS * 2,
+        ICON_ROWS,
+        base64::encode(&icon.png)
+    )
+}
+
+fn sixel(icon: &Icon) -> String {
+    // quantize to the 6x6x6 color cube, which is plenty for a thumbnail
+    let color_index = |[r, g, b, a]: [u8; 4]| {
+        (a >= ALPHA_THRESHOLD).then(|| {
+            let level = |c: u8| (u16::from(c) * 5 + 127) / 255;
+            level(r) * 36 + level(g) * 6 + level(b)
+        })
+    };
+
+    // P2=1 leaves transparent pixels alone instead of filling them in
+    let mut out = format!("\x1bP0;1;0q\"1;1;{};{}", icon.width, icon.height);
+    for i in 0..216u16 {
+        let percent = |level: u16| level * 100 / 5;
+        write!(
+            out,
+            "#{i};2;{};{};{}",
+            percent(i / 36),
+            percent(i / 6 % 6),
+            percent(i % 6)
+        )
+        .unwrap();
+    }
+
+    for band in (0..icon.height).step_by(6) {
+        let rows = band..(band + 6).min(icon.height);
+        let mut colors = rows
+            .clone()
+            .flat_map(|y| (0..icon.width).filter_map(move |x| color_index(icon.pixel(x, y))))
+            .collect::<Vec<_>>();
+        colors.sort_unstable();
+        colors.dedup();
+
+        for color in colors {
+            write!(out, "#{color}").unwrap();
+            let sixels = (0..icon.width).map(|x| {
+                let bits = rows
+                    .clone()
+                    .filter(|&y| color_index(icon.pixel(x, y)) == Some(color))
+                    .fold(0, |bits, y| bits | 1 << (y - band));
+                char::from(63 + bits as u8)
+            });
+            push_rle(&mut out, sixels);
+            // back to the start of the band for the next color
+            out.push('$');
+        }
+        out.push('-');
+    }
+    out.push_str("\x1b\\\n");
+    out
+}
+
+/// Writes sixel data, using `!` repeats for runs.
+fn push_rle(out: &mut String, sixels: impl Iterator<Item = char>) {
+    let flush = |out: &mut String, c: char, n: usize| match n {
+        0 => {}
+        1..=3 => out.extend(std::iter::repeat_n(c, n)),
+        _ => write!(out, "!{n}{c}").unwrap(),
+    };
+    let mut run = (' ', 0);
+    for c in sixels {
+        if c == run.0 {
+            run.1 += 1;
+        } else {
+            flush(out, run.0, run.1);
+            run = (c, 1);
+        }
+    }
+    flush(out, run.0, run.1);
+}
+
+/// Draws two pixels per cell with `▀`, the top pixel as the foreground and
+/// the bottom one as the background.
+fn blocks(icon: &Icon) -> String {
+    let truecolor = matches!(
+        &*env::var("COLORTERM").unwrap_or_default(),
+        "truecolor" | "24bit"
+    );
+    let color = |out: &mut String, layer: u8, [r, g, b, _]: [u8; 4]| {
+        if truecolor {
+            write!(out, "\x1b[{layer}8;2;{r};{g};{b}m").unwrap();
+        } else {
+            write!(out, "\x1b[{layer}8;5;{}m", Rgb(r, g, b).to_ansi256()).unwrap();
+        }
+    };
+
+    let mut out = String::new();
+    for y in (0..icon.height).step_by(2) {
+        for x in 0..icon.width {
+            let top = icon.pixel(x, y);
+            let bottom = if y + 1 < icon.height {
+                icon.pixel(x, y + 1)
+            } else {
+                [0; 4]
+            };
+            match (top[3] >= ALPHA_THRESHOLD, bottom[3] >= ALPHA_THRESHOLD) {
+                (false, false) => out.push(' '),
+                (true, false) => {
+                    color(&mut out, 3, top);
+                    out.push_str("▀\x1b[0m");
+                }
+                (false, true) => {
+                    color(&mut out, 3, bottom);
+                    out.push_str("▄\x1b[0m");
+                }
+                (true, true) => {
+                    color(&mut out, 3, top);
+                    color(&mut out, 4, bottom);
+                    out.push_str("▀\x1b[0m");
+                }
+            }
+        }
+        out.push('\n');
+    }
+    out
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// A 2x2 icon with an opaque red top row and a transparent bottom row.
+    fn icon() -> Icon {
+        Icon {
+            width: 2,
+            height: 2,
+            rgba: [[255, 0, 0, 255], [255, 0, 0, 255], [0; 4], [0; 4]].concat(),
+            png: b"not really a png".to_vec(),
+        }
+    }
+
+    #[test]
+    fn run_length_encodes_sixels() {
+        let mut out = String::new();
+        push_rle(&mut out, "aabbbbcccd".chars());
+        assert_eq!(out, "aa!4bcccd");
+        let mut out = String::new();
+        push_rle(&mut out, std::iter::empty());
+        assert_eq!(out, "");
+    }
+
+    #[test]
+    fn draws_sixels() {
+        // red is color 180 in the cube, and only the top row of the band
+        // is set
+        let out = sixel(&icon());
+        assert!(out.starts_with("\x1bP0;1;0q\"1;1;2;2#0;2;0;0;0#1;2;0;0;20"));
+        assert!(out.contains("#180;2;100;0;0"));
+        assert!(out.ends_with("#180@@$-\x1b\\\n"), "{out:?}");
+    }
+
+    #[test]
+    fn draws_half_blocks() {
+        let out = blocks(&icon());
+        assert_eq!(out.matches('▀').count(), 2);
+        assert_eq!(out.lines().count(), 1);
+        assert!(out.ends_with("▀\x1b[0m\n"));
+        // the transparent bottom row doesn't get a background
+        assert!(!out.contains("\x1b[48;"));
+
+        let transparent = Icon {
+            rgba: vec![0; 16],
+            ..icon()
+        };
+        assert_eq!(blocks(&transparent), "  \n");
+    }
+
+    #[test]
+    fn sends_the_png_inline() {
+        let data = base64::encode(b"not really a png");
+        assert_eq!(
+            kitty(&icon()),
+            format!("\x1b_Ga=T,f=100,q=2,c=16,r=8,m=0;{data}\x1b\\\n")
+        );
+        assert_eq!(
+            iterm(&icon()),
+            format!(
+                "\x1b]1337;File=inline=1;size=16;width=16;height=8;preserveAspectRatio=1:{data}\x07\n"
+            )
+        );
+    }
+
+    #[test]
+    fn chunks_large_kitty_payloads() {
+        let big = Icon {
+            png: vec![0; 4000],
+            ..icon()
+        };
+        let out = kitty(&big);
+        // 4000 bytes is 5336 base64 characters, so two chunks
+        assert_eq!(out.matches("\x1b_G").count(), 2);
+        assert!(out.contains(",m=1;"));
+        assert!(out.contains("\x1b_Gm=0;"));
+    }
+
+    #[test]
+    fn resolves_explicit_modes() {
+        let term = Term::stderr();
+        assert_eq!(IconMode::Never.resolve(&term), None);
+        assert_eq!(IconMode::Sixel.resolve(&term), Some(IconMode::Sixel));
+        assert_eq!(IconMode::Blocks.resolve(&term), Some(IconMode::Blocks));
+    }
+}
@@ -0,0 +1,661 @@
+//! The `record` and `history` subcommands: polling a server and keeping
+//! every result in a SQLite database, then summarizing them to see when the
+//! server is busiest. The database is `history.db` in the data directory,
+//! with a row in `polls` for each result and a row in `players` for each
+//! player a poll listed.
+
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::time::{Duration, SystemTime};
+
+use anyhow::Context;
+use mcserverstatus::{QueryOptions, ServerStatus};
+use serde::Serialize;
+
+use crate::exit::ErrorKind;
+use crate::sqlite::{Connection, Value};
+use crate::{print_json, query, unix_timestamp, Millis, OutputFormat};
+
+#[derive(clap::Args)]
+pub struct RecordArgs {
+    /// A server alias from the config file, or a server address
+    #[clap(value_name = "ALIAS|SERVER")]
+    pub server: String,
+
+    /// Seconds between polls
+    #[clap(long, value_name = "SECS", default_value = "60", value_parser = crate::parse_secs)]
+    interval: Duration,
+
+    /// Poll once and exit, for running from cron
+    #[clap(long, conflicts_with = "interval")]
+    once: bool,
+
+    /// The SQLite database to add the results to [default: history.db in
+    /// the data directory]
+    #[clap(long, value_name = "PATH", parse(from_os_str))]
+    db: Option<PathBuf>,
+}
+
+#[derive(clap::Args)]
+pub struct HistoryArgs {
+    /// A server alias from the config file, or a server address, to
+    /// summarize [default: every server that has been recorded]
+    #[clap(value_name = "ALIAS|SERVER")]
+    pub server: Option<String>,
+
+    /// Only look at results from the last AGE, like 90m, 24h or 7d
+    #[clap(long, value_name = "AGE", value_parser = parse_age)]
+    since: Option<u64>,
+
+    /// Only look at results from before AGE ago
+    #[clap(long, value_name = "AGE", value_parser = parse_age)]
+    until: Option<u64>,
+
+    /// The SQLite database the results were recorded to [default:
+    /// history.db in the data directory]
+    #[clap(long, value_name = "PATH", parse(from_os_str))]
+    db: Option<PathBuf>,
+}
+
+const SCHEMA: &str = "
+    CREATE TABLE IF NOT EXISTS polls (
+        id INTEGER PRIMARY KEY,
+        server TEXT NOT NULL,
+        -- seconds since the Unix epoch
+        timestamp INTEGER NOT NULL,
+        -- all NULL if the server couldn't be reached
+        online INTEGER,
+        max INTEGER,
+        latency_ms REAL,
+        version TEXT,
+        motd_hash TEXT,
+        -- only set if the server couldn't be reached
+        error TEXT,
+        error_kind TEXT
+    );
+    CREATE INDEX IF NOT EXISTS polls_by_server ON polls (server, timestamp);
+    CREATE TABLE IF NOT EXISTS players (
+        poll_id INTEGER NOT NULL REFERENCES polls (id),
+        name TEXT NOT NULL
+    );
+    CREATE INDEX IF NOT EXISTS players_by_poll ON players (poll_id)
+";
+
+/// The result of one poll, as it's printed with `--format json`.
+#[derive(Serialize)]
+struct Record {
+    server: String,
+    timestamp: u64,
+    /// The status, or `None` if the server couldn't be reached.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    status: Option<RecordedStatus>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    error: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    error_kind: Option<ErrorKind>,
+}
+
+#[derive(Serialize)]
+struct RecordedStatus {
+    online: u32,
+    max: u32,
+    latency_ms: f64,
+    version: String,
+    /// A hash of the MOTD's text, to see when it changed without storing
+    /// it every time.
+    motd_hash: String,
+    /// The players the server listed, which is only a sample of them on
+    /// big servers.
+    players: Vec<String>,
+}
+
+impl Record {
+    fn new(server: &str, timestamp: u64, res: &anyhow::Result<ServerStatus>) -> Self {
+        let (status, error, error_kind) = match res {
+            Ok(status) => {
+                let status = RecordedStatus {
+                    online: status.online,
+                    max: status.max,
+                    latency_ms: status.latency.avg_ms,
+                    version: status.version.name.clone(),
+                    motd_hash: format!("{:016x}", fnv1a(status.motd.plain().as_bytes())),
+                    players: status.players.iter().map(|p| p.name.clone()).collect(),
+                };
+                (Some(status), None, None)
+            }
+            Err(e) => (None, Some(format!("{e:#}")), Some(ErrorKind::of(e))),
+        };
+        Record {
+            server: server.to_owned(),
+            timestamp,
+            status,
+            error,
+            error_kind,
+        }
+    }
+
+    /// Adds the record to the database, along with its players.
+    fn insert(&self, db: &Connection) -> anyhow::Result<()> {
+        let status = self.status.as_ref();
+        // the same name as in JSON
+        let error_kind = self
+            .error_kind
+            .and_then(|kind| serde_json::to_value(kind).ok())
+            .and_then(|kind| kind.as_str().map(str::to_owned));
+
+        let tx = db.transaction()?;
+        db.execute(
+            "INSERT INTO polls (server, timestamp, online, max, latency_ms, version, motd_hash, \
+             error, error_kind) VALUES (?, ?, ?, ?, ?, ?, ?, ?, ?)",
+            &[
+                Value::Text(&self.server),
+                Value::Integer(self.timestamp as i64),
+                status.map(|status| i64::from(status.online)).into(),
+                status.map(|status| i64::from(status.max)).into(),
+                status.map(|status| status.latency_ms).into(),
+                status.map(|status| &*status.version).into(),
+                status.map(|status| &*status.motd_hash).into(),
+                self.error.as_deref().into(),
+                error_kind.as_deref().into(),
+            ],
+        )?;
+        let poll_id = db.last_insert_rowid();
+        let mut insert_player = db.prepare("INSERT INTO players (poll_id, name) VALUES (?, ?)")?;
+        for player in status.iter().flat_map(|status| &status.players) {
+            insert_player.execute(&[Value::Integer(poll_id), Value::Text(player)])?;
+        }
+        tx.commit()?;
+        Ok(())
+    }
+}
+
+/// The 64-bit FNV-1a hash, which unlike std's hasher stays the same across
+/// Rust versions, so hashes recorded by different builds can be compared.
+fn fnv1a(bytes: &[u8]) -> u64 {
+    bytes.iter().fold(0xcbf29ce484222325, |hash, &byte| {
+        (hash ^ u64::from(byte)).wrapping_mul(0x100000001b3)
+    })
+}
+
+fn db_path(db: &Option<PathBuf>) -> anyhow::Result<PathBuf> {
+    match db {
+        Some(db) => Ok(db.clone()),
+        None => dirs_next::data_dir()
+            .map(|dir| dir.join("mcserverstatus").join("history.db"))
+            .context("Couldn't find the data directory, please pass --db"),
+    }
+}
+
+/// Opens the database to record to, creating it if it isn't there yet.
+fn open_for_recording(path: &Path) -> anyhow::Result<Connection> {
+    if let Some(dir) = path.parent() {
+        fs::create_dir_all(dir)?;
+    }
+    let db = Connection::open(path, true)
+        .with_context(|| format!("Couldn't open {}", path.display()))?;
+    db.execute_batch(SCHEMA)
+        .with_context(|| format!("Couldn't set up {}", path.display()))?;
+    Ok(db)
+}
+
+/// Polls the server every `--interval` seconds (or once, with `--once`),
+/// adding each result to the database and printing a line about it.
+pub async fn record(
+    args: &RecordArgs,
+    server_str: &str,
+    options: &QueryOptions,
+    format: OutputFormat,
+) -> anyhow::Result<()> {
+    let path = db_path(&args.db)?;
+    let db = open_for_recording(&path)?;
+
+    let mut ticker = tokio::time::interval(args.interval);
+    ticker.set_missed_tick_behavior(tokio::time::MissedTickBehavior::Delay);
+    loop {
+        ticker.tick().await;
+        let res = query(server_str, options, &indicatif::ProgressBar::hidden()).await;
+        let now = SystemTime::now();
+        let record = Record::new(server_str, unix_timestamp(now), &res);
+        record
+            .insert(&db)
+            .with_context(|| format!("Couldn't write to {}", path.display()))?;
+
+        match format {
+            OutputFormat::Text => {
+                let time = crate::format_time_of_day(now);
+                match (&record.status, &record.error) {
+                    (Some(status), _) => println!(
+                        "[{time}] {}/{} online, {}ms",
+                        status.online,
+                        status.max,
+                        Millis(status.latency_ms)
+                    ),
+                    (None, error) => println!("[{time}] down: {}", error.as_deref().unwrap_or("")),
+                }
+            }
+            OutputFormat::Json => print_json(&record)?,
+        }
+        if args.once {
+            return Ok(());
+        }
+    }
+}
+
+#[derive(Serialize)]
+struct Summary {
+    server: String,
+    polls: u64,
+    first: u64,
+    last: u64,
+    uptime_percent: f64,
+    /// The rest are over the polls the server answered, so they're `None`
+    /// if it never did.
+    avg_online: Option<f64>,
+    peak: Option<Peak>,
+    avg_latency_ms: Option<f64>,
+    busiest_hour: Option<HourAverage>,
+    quietest_hour: Option<HourAverage>,
+}
+
+#[derive(Serialize)]
+struct Peak {
+    online: u32,
+    max: u32,
+    timestamp: u64,
+}
+
+/// The average number of players online during an hour of the day, in UTC.
+#[derive(Clone, Copy, Serialize)]
+struct HourAverage {
+    hour: u64,
+    avg_online: f64,
+}
+
+/// The polls for a server between two timestamps, for the queries below.
+const RANGE: &str = "server = ?1 AND timestamp BETWEEN ?2 AND ?3";
+
+/// Sums up the polls for each server between `since` and `until`, or just
+/// for `server`.
+fn summarize(
+    db: &Connection,
+    server: Option<&str>,
+    since: i64,
+    until: i64,
+) -> anyhow::Result<Vec<Summary>> {
+    let mut summaries = db
+        .prepare(
+            "SELECT server, COUNT(*), MIN(timestamp), MAX(timestamp), \
+             100.0 * COUNT(online) / COUNT(*), AVG(online), AVG(latency_ms) \
+             FROM polls WHERE (?1 IS NULL OR server = ?1) AND timestamp BETWEEN ?2 AND ?3 \
+             GROUP BY server ORDER BY server",
+        )?
+        .query(
+            &[server.into(), Value::Integer(since), Value::Integer(until)],
+            |row| Summary {
+                server: row.text(0).unwrap_or_default(),
+                polls: row.i64(1).unwrap_or(0) as u64,
+                first: row.i64(2).unwrap_or(0) as u64,
+                last: row.i64(3).unwrap_or(0) as u64,
+                uptime_percent: row.f64(4).unwrap_or(0.0),
+                avg_online: row.f64(5),
+                peak: None,
+                avg_latency_ms: row.f64(6),
+                busiest_hour: None,
+                quietest_hour: None,
+            },
+        )?;
+
+    // the first time the peak was reached
+    let mut peak = db.prepare(&format!(
+        "SELECT online, max, timestamp FROM polls WHERE {RANGE} AND online IS NOT NULL \
+         ORDER BY online DESC, timestamp LIMIT 1"
+    ))?;
+    let mut hours = db.prepare(&format!(
+        "SELECT timestamp % 86400 / 3600 AS hour, AVG(online) AS avg FROM polls \
+         WHERE {RANGE} AND online IS NOT NULL GROUP BY hour ORDER BY avg, hour"
+    ))?;
+    for summary in &mut summaries {
+        let params = [
+            Value::Text(&summary.server),
+            Value::Integer(since),
+            Value::Integer(until),
+        ];
+        summary.peak = peak
+            .query(&params, |row| Peak {
+                online: row.i64(0).unwrap_or(0) as u32,
+                max: row.i64(1).unwrap_or(0) as u32,
+                timestamp: row.i64(2).unwrap_or(0) as u64,
+            })?
+            .pop();
+        let hours = hours.query(&params, |row| HourAverage {
+            hour: row.i64(0).unwrap_or(0) as u64,
+            avg_online: row.f64(1).unwrap_or(0.0),
+        })?;
+        summary.quietest_hour = hours.first().copied();
+        summary.busiest_hour = hours.last().copied();
+    }
+    Ok(summaries)
+}
+
+/// Prints a summary of the recorded results for each server, or just the
+/// one asked for.
+pub fn history(
+    args: &HistoryArgs,
+    server_str: Option<&str>,
+    format: OutputFormat,
+) -> anyhow::Result<()> {
+    let path = db_path(&args.db)?;
+    if !path.exists() {
+        anyhow::bail!(
+            "There's no {}, has anything been recorded yet?",
+            path.display()
+        );
+    }
+    let db = Connection::open(&path, false)
+        .with_context(|| format!("Couldn't open {}", path.display()))?;
+    let now = unix_timestamp(SystemTime::now());
+    let since = args.since.map_or(0, |age| now.saturating_sub(age));
+    let until = args.until.map_or(u64::MAX, |age| now.saturating_sub(age));
+    let clamp = |timestamp: u64| i64::try_from(timestamp).unwrap_or(i64::MAX);
+
+    let summaries = summarize(&db, server_str, clamp(since), clamp(until))
+        .with_context(|| format!("Couldn't read {}", path.display()))?;
+    if summaries.is_empty() {
+        match server_str {
+            Some(server) => anyhow::bail!("Nothing has been recorded for {server} in that time"),
+            None => anyhow::bail!("Nothing has been recorded in that time"),
+        }
+    }
+
+    match format {
+        OutputFormat::Text => {
+            for (n, summary) in summaries.iter().enumerate() {
+                if n > 0 {
+                    println!();
+                }
+                print_summary(summary);
+            }
+        }
+        OutputFormat::Json => print_json(&summaries)?,
+    }
+    Ok(())
+}
+
+fn print_summary(summary: &Summary) {
+    println!("{}", console::style(&summary.server).bold());
+    println!(
+        "{} polls from {} to {} UTC",
+        summary.polls,
+        format_datetime(summary.first),
+        format_datetime(summary.last)
+    );
+    println!("Uptime: {:.1}%", summary.uptime_percent);
+    let (Some(avg_online), Some(peak), Some(avg_latency)) =
+        (summary.avg_online, &summary.peak, summary.avg_latency_ms)
+    else {
+        println!("Never answered");
+        return;
+    };
+    println!(
+        "Players: {avg_online:.1} on average, peak {}/{} at {} UTC",
+        peak.online,
+        peak.max,
+        format_datetime(peak.timestamp)
+    );
+    println!("Latency: {}ms on average", Millis(avg_latency));
+    if let (Some(busiest), Some(quietest)) = (summary.busiest_hour, summary.quietest_hour) {
+        let mut line = format!(
+            "Busiest: {} UTC ({:.1} players on average)",
+            format_hour(busiest.hour),
+            busiest.avg_online
+        );
+        // with results from only one hour, that's the quietest too
+        if quietest.hour != busiest.hour {
+            line += &format!(
+                ", quietest {} UTC ({:.1})",
+                format_hour(quietest.hour),
+                quietest.avg_online
+            );
+        }
+        println!("{line}");
+    }
+}
+
+fn format_hour(hour: u64) -> String {
+    format!("{hour:02}:00-{:02}:00", (hour + 1) % 24)
+}
+
+/// Formats a Unix timestamp as `YYYY-MM-DD HH:MM` in UTC.
+fn format_datetime(timestamp: u64) -> String {
+    // Howard Hinnant's days_from_civil, the other way around
+    let days = (timestamp / 86400) as i64 + 719468;
+    let era = days.div_euclid(146097);
+    let day_of_era = days.rem_euclid(146097);
+    let year_of_era =
+        (day_of_era - day_of_era / 1460 + day_of_era / 36524 - day_of_era / 146096) / 365;
+    let day_of_year = day_of_era - (365 * year_of_era + year_of_era / 4 - year_of_era / 100);
+    let mp = (5 * day_of_year + 2) / 153;
+    let day = day_of_year - (153 * mp + 2) / 5 + 1;
+    let month = if mp < 10 { mp + 3 } else { mp - 9 };
+    let year = year_of_era + era * 400 + i64::from(month <= 2);
+    let secs = timestamp % 86400;
+    format!(
+        "{year}-{month:02}-{day:02} {:02}:{:02}",
+        secs / 3600,
+        secs / 60 % 60
+    )
+}
+
+/// Parses an age like `90m`, `24h` or `7d` into seconds.
+fn parse_age(s: &str) -> Result<u64, String> {
+    let invalid = || "expected a number followed by s, m, h, d or w, like 24h".to_owned();
+    let unit = match s.chars().last() {
+        Some('s') => 1,
+        Some('m') => 60,
+        Some('h') => 3600,
+        Some('d') => 86400,
+        Some('w') => 7 * 86400,
+        _ => return Err(invalid()),
+    };
+    let n = s[..s.len() - 1].parse::<u64>().map_err(|_| invalid())?;
+    n.checked_mul(unit)
+        .ok_or_else(|| format!("{s:?} is too long ago"))
+}
+
+#[cfg(test)]
+mod tests {
+    use mcserverstatus::motd::Text;
+    use mcserverstatus::{Error, Latency, Player, Version};
+
+    use super::*;
+
+    fn poll(server: &str, timestamp: u64, online: Option<u32>) -> Record {
+        Record {
+            server: server.to_owned(),
+            timestamp,
+            status: online.map(|online| RecordedStatus {
+                online,
+                max: 20,
+                latency_ms: f64::from(online) * 10.0,
+                version: "1.20.1".to_owned(),
+                motd_hash: format!("{:016x}", fnv1a(b"A Minecraft Server")),
+                players: vec!["Alice".to_owned(); online.min(2) as usize],
+            }),
+            error: online.is_none().then(|| "timed out".to_owned()),
+            error_kind: online.is_none().then_some(ErrorKind::Timeout),
+        }
+    }
+
+    fn database(polls: &[Record]) -> Connection {
+        let db = Connection::open(Path::new(":memory:"), true).unwrap();
+        db.execute_batch(SCHEMA).unwrap();
+        for poll in polls {
+            poll.insert(&db).unwrap();
+        }
+        db
+    }
+
+    /// 2000-02-29 00:00 UTC
+    const DAY: u64 = 951782400;
+
+    #[test]
+    fn records_statuses_and_errors() {
+        let status = ServerStatus {
+            online: 2,
+            max: 20,
+            players: ["Alice", "Bob"]
+                .map(|name| Player {
+                    name: name.to_owned(),
+                    id: None,
+                })
+                .to_vec(),
+            version: Version {
+                name: "1.20.1".to_owned(),
+                protocol: 763,
+            },
+            motd: Text::from_legacy("§aA Minecraft Server"),
+            favicon: None,
+            icon: None,
+            latency: Latency::from_samples(&[Duration::from_millis(40)]),
+            bedrock: None,
+            query: None,
+        };
+        let record = Record::new("mc.example.net", 1000, &Ok(status));
+        assert_eq!(
+            serde_json::to_string(&record).unwrap(),
+            format!(
+                r#"{{"server":"mc.example.net","timestamp":1000,"status":{{"online":2,"max":20,"latency_ms":40.0,"version":"1.20.1","motd_hash":"{:016x}","players":["Alice","Bob"]}}}}"#,
+                fnv1a(b"A Minecraft Server")
+            )
+        );
+
+        let record = Record::new("mc.example.net", 1000, &Err(Error::Timeout.into()));
+        assert_eq!(
+            serde_json::to_string(&record).unwrap(),
+            r#"{"server":"mc.example.net","timestamp":1000,"error":"timed out waiting for the server","error_kind":"timeout"}"#
+        );
+    }
+
+    #[test]
+    fn hashes_with_fnv1a() {
+        assert_eq!(fnv1a(b""), 0xcbf29ce484222325);
+        assert_eq!(fnv1a(b"a"), 0xaf63dc4c8601ec8c);
+        assert_eq!(fnv1a(b"foobar"), 0x85944171f73967e8);
+    }
+
+    #[test]
+    fn summarizes_each_server() {
+        let db = database(&[
+            poll("a", DAY + 3600, Some(1)),
+            poll("a", DAY, Some(9)),
+            poll("b", DAY, None),
+            poll("a", DAY + 86400, Some(9)),
+            poll("a", DAY + 2 * 3600, None),
+            poll("a", DAY + 86400 + 3600, Some(3)),
+        ]);
+        let summaries = summarize(&db, None, 0, i64::MAX).unwrap();
+        assert_eq!(summaries.len(), 2);
+
+        let a = &summaries[0];
+        assert_eq!(a.server, "a");
+        assert_eq!((a.polls, a.first, a.last), (5, DAY, DAY + 86400 + 3600));
+        assert_eq!(a.uptime_percent, 80.0);
+        assert_eq!(a.avg_online, Some(5.5));
+        assert_eq!(a.avg_latency_ms, Some(55.0));
+        // the first of the two times 9 were online
+        let peak = a.peak.as_ref().unwrap();
+        assert_eq!((peak.online, peak.max, peak.timestamp), (9, 20, DAY));
+        let busiest = a.busiest_hour.unwrap();
+        assert_eq!((busiest.hour, busiest.avg_online), (0, 9.0));
+        let quietest = a.quietest_hour.unwrap();
+        assert_eq!((quietest.hour, quietest.avg_online), (1, 2.0));
+
+        let b = &summaries[1];
+        assert_eq!((b.server.as_str(), b.polls), ("b", 1));
+        assert_eq!(b.uptime_percent, 0.0);
+        assert!(b.avg_online.is_none() && b.peak.is_none() && b.busiest_hour.is_none());
+    }
+
+    #[test]
+    fn summarizes_one_server_in_a_time_range() {
+        let db = database(&[
+            poll("a", DAY, Some(9)),
+            poll("a", DAY + 3600, Some(1)),
+            poll("b", DAY + 3600, Some(5)),
+            poll("a", DAY + 7200, Some(4)),
+        ]);
+        let since = (DAY + 3600) as i64;
+        let summaries = summarize(&db, Some("a"), since, i64::MAX).unwrap();
+        assert_eq!(summaries.len(), 1);
+        assert_eq!(summaries[0].polls, 2);
+        assert_eq!(summaries[0].peak.as_ref().unwrap().online, 4);
+
+        let summaries = summarize(&db, Some("a"), 0, since - 1).unwrap();
+        assert_eq!(summaries[0].polls, 1);
+        assert!(summarize(&db, Some("c"), 0, i64::MAX).unwrap().is_empty());
+    }
+
+    #[test]
+    fn stores_players_and_errors() {
+        let db = database(&[poll("a", DAY, Some(3)), poll("a", DAY + 60, None)]);
+        let players = db
+            .prepare("SELECT poll_id, name FROM players")
+            .unwrap()
+            .query(&[], |row| (row.i64(0), row.text(1)))
+            .unwrap();
+        let alice = (Some(1), Some("Alice".to_owned()));
+        assert_eq!(players, [alice.clone(), alice]);
+        let errors = db
+            .prepare("SELECT error, error_kind FROM polls ORDER BY id")
+            .unwrap()
+            .query(&[], |row| (row.text(0), row.text(1)))
+            .unwrap();
+        assert_eq!(
+            errors,
+            [
+                (None, None),
+                (Some("timed out".to_owned()), Some("timeout".to_owned()))
+            ]
+        );
+    }
+
+    #[test]
+    fn leaves_nothing_behind_when_an_insert_fails() {
+        let db = database(&[poll("a", DAY, Some(3))]);
+        db.execute("DROP TABLE players", &[]).unwrap();
+        assert!(poll("a", DAY + 60, Some(3)).insert(&db).is_err());
+        let polls = db
+            .prepare("SELECT timestamp FROM polls")
+            .unwrap()
+            .query(&[], |row| row.i64(0))
+            .unwrap();
+        assert_eq!(polls, [Some(DAY as i64)]);
+        // and the failed transaction isn't left open
+        db.transaction().unwrap();
+    }
+
+    #[test]
+    fn formats_dates() {
+        assert_eq!(format_datetime(0), "1970-01-01 00:00");
+        assert_eq!(format_datetime(951_782_400), "2000-02-29 00:00");
+        assert_eq!(format_datetime(1_699_999_999), "2023-11-14 22:13");
+        assert_eq!(format_hour(0), "00:00-01:00");
+        assert_eq!(format_hour(23), "23:00-00:00");
+    }
+
+    #[test]
+    fn parses_ages() {
+        assert_eq!(parse_age("30s"), Ok(30));
+        assert_eq!(parse_age("90m"), Ok(5400));
+        assert_eq!(parse_age("24h"), Ok(86400));
+        assert_eq!(parse_age("7d"), Ok(604_800));
+        assert_eq!(parse_age("2w"), Ok(1_209_600));
+        for bad in ["", "24", "h", "-1d", "1.5h", "1y"] {
+            assert!(parse_age(bad).is_err(), "{bad}");
+        }
+        assert_eq!(
+            parse_age("99999999999999999w"),
+            Err("\"99999999999999999w\" is too long ago".to_owned())
+        );
+    }
+}
@@ -0,0 +1,769 @@
+//! Server icons: decoding the base64 PNGs that servers send as their
+//! favicon and that the game caches in `servers.dat`.
+//!
+//! The PNG decoder here only needs to handle 64x64 icons, but it supports
+//! every color type, bit depth and interlacing so that whatever a server
+//! owner exported from their image editor works.
+
+use std::io::Read;
+
+use flate2::read::ZlibDecoder;
+
+use crate::base64;
+
+const PNG_SIGNATURE: [u8; 8] = [0x89, b'P', b'N', b'G', b'\r', b'\n', 0x1a, b'\n'];
+
+/// Icons are meant to be 64x64, so anything much larger is probably an
+/// attempt to make us allocate a lot of memory.
+const MAX_DIMENSION: u32 = 1024;
+
+/// The `(x, y, dx, dy)` of each Adam7 pass.
+const ADAM7: [(usize, usize, usize, usize); 7] = [
+    (0, 0, 8, 8),
+    (4, 0, 8, 8),
+    (0, 4, 4, 8),
+    (2, 0, 4, 4),
+    (0, 2, 2, 4),
+    (1, 0, 2, 2),
+    (0, 1, 1, 2),
+];
+
+#[derive(Debug, thiserror::Error)]
+pub enum PngError {
+    #[error("not a PNG file")]
+    NotPng,
+    #[error("PNG is truncated")]
+    Truncated,
+    #[error("invalid PNG: {0}")]
+    Invalid(&'static str),
+    #[error("unsupported PNG: {0}")]
+    Unsupported(&'static str),
+    #[error("icon is {0}x{1}, which is too large")]
+    TooLarge(u32, u32),
+}
+
+/// A decoded icon, along with the PNG it came from.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Icon {
+    pub width: u32,
+    pub height: u32,
+    /// The pixels, as 8-bit RGBA in rows from the top.
+    pub rgba: Vec<u8>,
+    /// The original PNG file.
+    pub png: Vec<u8>,
+}
+
+/// The bytes in a base64 `data:` URL like the status response's `favicon`,
+/// whether or not they're the PNG the URL claims they are.
+pub fn decode_data_url(url: &str) -> Option<Vec<u8>> {
+    let (_, data) = url.strip_prefix("data:")?.split_once(";base64,")?;
+    base64::decode(data)
+}
+
+impl Icon {
+    /// Decodes a `data:image/png;base64,...` URL, as sent in the status
+    /// response's `favicon`.
+    pub fn from_data_url(url: &str) -> Result<Self, PngError> {
+        let data = url
+            .strip_prefix("data:image/png;base64,")
+            .ok_or(PngError::NotPng)?;
+        Icon::from_base64(data)
+    }
+
+    /// Decodes a base64 PNG, as stored in `servers.dat`.
+    pub fn from_base64(data: &str) -> Result<Self, PngError> {
+        let png = base64::decode(data).ok_or(PngError::NotPng)?;
+        Icon::from_png(png)
+    }
+
+    pub fn from_png(png: Vec<u8>) -> Result<Self, PngError> {
+        let image = decode_png(&png)?;
+        Ok(Icon {
+            width: image.width,
+            height: image.height,
+            rgba: image.rgba,
+            png,
+        })
+    }
+
+    /// The color of the pixel at `(x, y)`.
+    pub fn pixel(&self, x: u32, y: u32) -> [u8; 4] {
+        let i = (y as usize * self.width as usize + x as usize) * 4;
+        self.rgba[i..i + 4].try_into().unwrap()
+    }
+
+    /// Scales the icon to `width` by `height` by averaging each block of
+    /// pixels, weighting colors by their alpha so that transparent pixels
+    /// don't darken the edges.
+    pub fn resize(&self, width: u32, height: u32) -> Icon {
+        let mut rgba = Vec::with_capacity(width as usize * height as usize * 4);
+        for y in 0..height {
+            let (y0, y1) = span(y, height, self.height);
+            for x in 0..width {
+                let (x0, x1) = span(x, width, self.width);
+                let mut sum = [0u64; 4];
+                for sy in y0..y1 {
+                    for sx in x0..x1 {
+                        let [r, g, b, a] = self.pixel(sx, sy).map(u64::from);
+                        sum[0] += r * a;
+                        sum[1] += g * a;
+                        sum[2] += b * a;
+                        sum[3] += a;
+                    }
+                }
+                let count = u64::from((x1 - x0) * (y1 - y0));
+                let alpha = sum[3];
+                // fully transparent blocks come out as transparent black
+                let avg = |c: u64| c.checked_div(alpha).unwrap_or(0) as u8;
+                rgba.extend_from_slice(&[
+                    avg(sum[0]),
+                    avg(sum[1]),
+                    avg(sum[2]),
+                    (alpha / count) as u8,
+                ]);
+            }
+        }
+        Icon {
+            width,
+            height,
+            rgba,
+            png: Vec::new(),
+        }
+    }
+}
+
+/// The range of source pixels that destination pixel `i` covers when
+/// scaling `from` pixels to `to`. Always covers at least one pixel, so this
+/// works for scaling up too.
+fn span(i: u32, to: u32, from: u32) -> (u32, u32) {
+    let start = i * from / to;
+    let end = ((i + 1) * from / to).max(start + 1);
+    (start, end.min(from))
+}
+
+struct Image {
+    width: u32,
+    height: u32,
+    rgba: Vec<u8>,
+}
+
+struct Header {
+    width: usize,
+    height: usize,
+    bit_depth: u8,
+    color_type: u8,
+    interlaced: bool,
+}
+
+impl Header {
+    fn channels(&self) -> usize {
+        match self.color_type {
+            0 | 3 => 1,
+            4 => 2,
+            2 => 3,
+            _ => 4,
+        }
+    }
+
+    fn bits_per_pixel(&self) -> usize {
+        self.channels() * usize::from(self.bit_depth)
+    }
+
+    /// Bytes in a scanline `width` pixels wide, not counting the filter
+    /// type byte.
+    fn stride(&self, width: usize) -> usize {
+        (width * self.bits_per_pixel()).div_ceil(8)
+    }
+}
+
+fn decode_png(png: &[u8]) -> Result<Image, PngError> {
+    let mut rest = png.strip_prefix(&PNG_SIGNATURE).ok_or(PngError::NotPng)?;
+
+    let mut header = None;
+    let mut palette = Vec::new();
+    let mut trns = Vec::new();
+    let mut idat = Vec::new();
+    loop {
+        if rest.len() < 12 {
+            return Err(PngError::Truncated);
+        }
+        let len = u32::from_be_bytes(rest[..4].try_into().unwrap()) as usize;
+        let kind = &rest[4..8];
+        let data = rest.get(8..8 + len).ok_or(PngError::Truncated)?;
+        // skip the CRC; the zlib stream has its own checksum for the part
+        // that matters
+        rest = rest.get(12 + len..).ok_or(PngError::Truncated)?;
+
+        match kind {
+            b"IHDR" => header = Some(parse_header(data)?),
+            b"PLTE" => palette = data.to_vec(),
+            b"tRNS" => trns = data.to_vec(),
+            b"IDAT" => idat.extend_from_slice(data),
+            b"IEND" => break,
+            // ancillary chunks (lowercase first letter) are safe to ignore
+            _ if kind[0].is_ascii_lowercase() => {}
+            _ => return Err(PngError::Unsupported("unknown critical chunk")),
+        }
+    }
+    let header = header.ok_or(PngError::Invalid("missing IHDR"))?;
+    if header.color_type == 3 && palette.is_empty() {
+        return Err(PngError::Invalid("missing palette"));
+    }
+
+    let passes = if header.interlaced {
+        ADAM7.to_vec()
+    } else {
+        vec![(0, 0, 1, 1)]
+    };
+    let pass_size = |&(x0, y0, dx, dy): &(usize, usize, usize, usize)| {
+        let w = (header.width + dx - 1 - x0) / dx;
+        let h = (header.height + dy - 1 - y0) / dy;
+        (w, h)
+    };
+    let expected_len = passes
+        .iter()
+        .map(|pass| match pass_size(pass) {
+            (0, _) | (_, 0) => 0,
+            (w, h) => (header.stride(w) + 1) * h,
+        })
+        .sum::<usize>();
+
+    let mut raw = Vec::with_capacity(expected_len);
+    ZlibDecoder::new(&idat[..])
+        .take(expected_len as u64 + 1)
+        .read_to_end(&mut raw)
+        .map_err(|_| PngError::Invalid("bad compressed data"))?;
+    if raw.len() < expected_len {
+        return Err(PngError::Truncated);
+    }
+
+    let mut rgba = vec![0; header.width * header.height * 4];
+    let mut raw = &raw[..];
+    for pass in &passes {
+        let (w, h) = pass_size(pass);
+        if w == 0 || h == 0 {
+            continue;
+        }
+        let stride = header.stride(w);
+        let (data, rest) = raw.split_at((stride + 1) * h);
+        raw = rest;
+        let scanlines = unfilter(data, stride, h, header.bits_per_pixel().div_ceil(8))?;
+
+        let (x0, y0, dx, dy) = *pass;
+        for (row, line) in scanlines.chunks(stride).enumerate() {
+            for col in 0..w {
+                let pixel = read_pixel(&header, line, col, &palette, &trns);
+                let (x, y) = (x0 + col * dx, y0 + row * dy);
+                let i = (y * header.width + x) * 4;
+                rgba[i..i + 4].copy_from_slice(&pixel);
+            }
+        }
+    }
+
+    Ok(Image {
+        width: header.width as u32,
+        height: header.height as u32,
+        rgba,
+    })
+}
+
+fn parse_header(data: &[u8]) -> Result<Header, PngError> {
+    if data.len() < 13 {
+        return Err(PngError::Truncated);
+    }
+    let width = u32::from_be_bytes(data[0..4].try_into().unwrap());
+    let height = u32::from_be_bytes(data[4..8].try_into().unwrap());
+    let (bit_depth, color_type) = (data[8], data[9]);
+    if width == 0 || height == 0 {
+        return Err(PngError::Invalid("empty image"));
+    }
+    if width > MAX_DIMENSION || height > MAX_DIMENSION {
+        return Err(PngError::TooLarge(width, height));
+    }
+    let valid_depth = match color_type {
+        0 => matches!(bit_depth, 1 | 2 | 4 | 8 | 16),
+        3 => matches!(bit_depth, 1 | 2 | 4 | 8),
+        2 | 4 | 6 => matches!(bit_depth, 8 | 16),
+        _ => return Err(PngError::Invalid("bad color type")),
+    };
+    if !valid_depth {
+        return Err(PngError::Invalid("bad bit depth"));
+    }
+    if data[10] != 0 || data[11] != 0 {
+        return Err(PngError::Unsupported(
+            "unknown compression or filter method",
+        ));
+    }
+    Ok(Header {
+        width: width as usize,
+        height: height as usize,
+        bit_depth,
+        color_type,
+        interlaced: match data[12] {
+            0 => false,
+            1 => true,
+            _ => return Err(PngError::Invalid("bad interlace method")),
+        },
+    })
+}
+
+/// Undoes the per-scanline filters, returning the scanlines without their
+/// filter type bytes.
+fn unfilter(data: &[u8], stride: usize, height: usize, bpp: usize) -> Result<Vec<u8>, PngError> {
+    let mut out = vec![0u8; stride * height];
+    for y in 0..height {
+        let line = &data[y * (stride + 1)..(y + 1) * (stride + 1)];
+        let (filter, line) = (line[0], &line[1..]);
+        let (prev, cur) = out.split_at_mut(y * stride);
+        let prev = if y == 0 {
+            None
+        } else {
+            Some(&prev[(y - 1) * stride..])
+        };
+        let cur = &mut cur[..stride];
+        for i in 0..stride {
+            let a = if i >= bpp { cur[i - bpp] } else { 0 };
+            let b = prev.map_or(0, |prev| prev[i]);
+            let c = match prev {
+                Some(prev) if i >= bpp => prev[i - bpp],
+                _ => 0,
+            };
+            cur[i] = line[i].wrapping_add(match filter {
+                0 => 0,
+                1 => a,
+                2 => b,
+                3 => ((u16::from(a) + u16::from(b)) / 2) as u8,
+                4 => paeth(a, b, c),
+                _ => return Err(PngError::Invalid("bad filter type")),
+            });
+        }
+    }
+    Ok(out)
+}
+
+fn paeth(a: u8, b: u8, c: u8) -> u8 {
+    let p = i16::from(a) + i16::from(b) - i16::from(c);
+    let (pa, pb, pc) = (
+        (p - i16::from(a)).abs(),
+        (p - i16::from(b)).abs(),
+        (p - i16::from(c)).abs(),
+    );
+    if pa <= pb && pa <= pc {
+        a
+    } else if pb <= pc {
+        b
+    } else {
+        c
+    }
+}
+
+/// Reads sample `index` of a scanline, scaled to 8 bits. 16-bit samples
+/// are cut down to their high byte.
+fn sample(line: &[u8], index: usize, bit_depth: u8) -> (u8, u16) {
+    match bit_depth {
+        16 => {
+            let raw = u16::from_be_bytes([line[index * 2], line[index * 2 + 1]]);
+            ((raw >> 8) as u8, raw)
+        }
+        8 => (line[index], u16::from(line[index])),
+        depth => {
+            let per_byte = 8 / usize::from(depth);
+            let byte = line[index / per_byte];
+            let shift = 8 - usize::from(depth) * (index % per_byte + 1);
+            let max = (1u16 << depth) - 1;
+            let raw = u16::from(byte >> shift) & max;
+            ((raw * 255 / max) as u8, raw)
+        }
+    }
+}
+
+fn read_pixel(header: &Header, line: &[u8], x: usize, palette: &[u8], trns: &[u8]) -> [u8; 4] {
+    let depth = header.bit_depth;
+    let channels = header.channels();
+    let s = |c: usize| sample(line, x * channels + c, depth);
+    // tRNS gives the one color that's fully transparent for images without
+    // an alpha channel, compared at the original bit depth
+    let trns_value = |i: usize| {
+        trns.get(i * 2..i * 2 + 2)
+            .map(|v| u16::from_be_bytes([v[0], v[1]]))
+    };
+    match header.color_type {
+        0 => {
+            let (gray, raw) = s(0);
+            let alpha = if trns_value(0) == Some(raw) { 0 } else { 255 };
+            [gray, gray, gray, alpha]
+        }
+        2 => {
+            let ((r, rr), (g, rg), (b, rb)) = (s(0), s(1), s(2));
+            let transparent = (0..3).map(trns_value).eq([Some(rr), Some(rg), Some(rb)]);
+            [r, g, b, if transparent { 0 } else { 255 }]
+        }
+        3 => {
+            let index = usize::from(s(0).1);
+            match palette.get(index * 3..index * 3 + 3) {
+                Some(rgb) => [rgb[0], rgb[1], rgb[2], *trns.get(index).unwrap_or(&255)],
+                None => [0; 4],
+            }
+        }
+        4 => {
+            let (gray, alpha) = (s(0).0, s(1).0);
+            [gray, gray, gray, alpha]
+        }
+        _ => [s(0).0, s(1).0, s(2).0, s(3).0],
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::io::Write;
+
+    use flate2::{write::ZlibEncoder, Compression};
+
+    use super::*;
+
+    const GRAY: u8 = 0;
+    const RGB: u8 = 2;
+    const PALETTE: u8 = 3;
+    const RGBA: u8 = 6;
+
+    /// Builds a PNG from scanlines that already have their filter type
+    /// bytes, splitting the compressed data across two IDAT chunks. The
+    /// CRCs are left as zero since the decoder doesn't check them.
+    fn png(
+        (width, height): (u32, u32),
+        bit_depth: u8,
+        color_type: u8,
+        interlaced: bool,
+        chunks: &[(&[u8; 4], &[u8])],
+        scanlines: &[u8],
+    ) -> Vec<u8> {
+        fn chunk(png: &mut Vec<u8>, kind: &[u8; 4], data: &[u8]) {
+            png.extend_from_slice(&(data.len() as u32).to_be_bytes());
+            png.extend_from_slice(kind);
+            png.extend_from_slice(data);
+            png.extend_from_slice(&[0; 4]);
+        }
+
+        let mut ihdr = Vec::new();
+        ihdr.extend_from_slice(&width.to_be_bytes());
+        ihdr.extend_from_slice(&height.to_be_bytes());
+        ihdr.extend_from_slice(&[bit_depth, color_type, 0, 0, u8::from(interlaced)]);
+        let mut encoder = ZlibEncoder::new(Vec::new(), Compression::default());
+        encoder.write_all(scanlines).unwrap();
+        let idat = encoder.finish().unwrap();
+
+        let mut png = PNG_SIGNATURE.to_vec();
+        chunk(&mut png, b"IHDR", &ihdr);
+        chunk(&mut png, b"tEXt", b"Comment\0made by hand");
+        for (kind, data) in chunks {
+            chunk(&mut png, kind, data);
+        }
+        let (first, second) = idat.split_at(idat.len() / 2);
+        chunk(&mut png, b"IDAT", first);
+        chunk(&mut png, b"IDAT", second);
+        chunk(&mut png, b"IEND", &[]);
+        png
+    }
+
+    /// Joins unfiltered scanlines, giving each filter type 0.
+    fn unfiltered(rows: &[&[u8]]) -> Vec<u8> {
+        rows.iter()
+            .flat_map(|row| [&[0][..], row].concat())
+            .collect()
+    }
+
+    fn pixels(icon: &Icon) -> Vec<[u8; 4]> {
+        icon.rgba
+            .chunks(4)
+            .map(|pixel| pixel.try_into().unwrap())
+            .collect()
+    }
+
+    #[test]
+    fn decodes_a_palette_image() {
+        // 2-bit indices [0, 1, 2] and [3, 2, 1]; tRNS only covers the first
+        // two entries, so the rest are opaque
+        let png = png(
+            (3, 2),
+            2,
+            PALETTE,
+            false,
+            &[
+                (b"PLTE", &[255, 0, 0, 0, 255, 0, 0, 0, 255, 9, 9, 9]),
+                (b"tRNS", &[0, 128]),
+            ],
+            &unfiltered(&[&[0b00_01_10_00], &[0b11_10_01_00]]),
+        );
+        let icon = Icon::from_png(png).unwrap();
+        assert_eq!((icon.width, icon.height), (3, 2));
+        assert_eq!(
+            pixels(&icon),
+            [
+                [255, 0, 0, 0],
+                [0, 255, 0, 128],
+                [0, 0, 255, 255],
+                [9, 9, 9, 255],
+                [0, 0, 255, 255],
+                [0, 255, 0, 128],
+            ]
+        );
+    }
+
+    #[test]
+    fn decodes_grayscale_with_a_transparent_level() {
+        let png8 = png(
+            (4, 1),
+            8,
+            GRAY,
+            false,
+            &[(b"tRNS", &[0, 7])],
+            &unfiltered(&[&[0, 7, 128, 255]]),
+        );
+        let icon = Icon::from_png(png8).unwrap();
+        assert_eq!(
+            pixels(&icon),
+            [
+                [0, 0, 0, 255],
+                [7, 7, 7, 0],
+                [128, 128, 128, 255],
+                [255, 255, 255, 255]
+            ]
+        );
+
+        // below 8 bits, samples are scaled up but tRNS still matches the
+        // original value
+        let png4 = png(
+            (3, 1),
+            4,
+            GRAY,
+            false,
+            &[(b"tRNS", &[0, 5])],
+            &unfiltered(&[&[0x05, 0xf0]]),
+        );
+        let icon = Icon::from_png(png4).unwrap();
+        assert_eq!(
+            pixels(&icon),
+            [[0, 0, 0, 255], [85, 85, 85, 0], [255, 255, 255, 255]]
+        );
+    }
+
+    #[test]
+    fn decodes_16_bit_samples() {
+        let png = png(
+            (2, 1),
+            16,
+            RGB,
+            false,
+            &[(b"tRNS", &[0x00, 0x01, 0x00, 0x02, 0x00, 0x03])],
+            &unfiltered(&[&[
+                0x12, 0x34, 0xab, 0xcd, 0xff, 0x00, //
+                0x00, 0x01, 0x00, 0x02, 0x00, 0x03,
+            ]]),
+        );
+        let icon = Icon::from_png(png).unwrap();
+        // the high byte is kept, and tRNS is compared at 16 bits, so
+        // (1, 2, 3) is transparent even though it comes out as black
+        assert_eq!(pixels(&icon), [[0x12, 0xab, 0xff, 255], [0, 0, 0, 0]]);
+    }
+
+    /// A `width` by `height` RGBA test pattern, as rows of pixels.
+    fn pattern(width: usize, height: usize) -> Vec<Vec<u8>> {
+        (0..height)
+            .map(|y| {
+                (0..width)
+                    .flat_map(|x| [(x * 29) as u8, (y * 31) as u8, (x * y) as u8, 200 + x as u8])
+                    .collect()
+            })
+            .collect()
+    }
+
+    /// Applies PNG filter type `filter` to `line`, given the line above.
+    fn filter(filter: u8, line: &[u8], prev: Option<&[u8]>, bpp: usize) -> Vec<u8> {
+        let mut out = vec![filter];
+        for i in 0..line.len() {
+            let a = if i >= bpp { line[i - bpp] } else { 0 };
+            let b = prev.map_or(0, |prev| prev[i]);
+            let c = match prev {
+                Some(prev) if i >= bpp => prev[i - bpp],
+                _ => 0,
+            };
+            out.push(line[i].wrapping_sub(match filter {
+                0 => 0,
+                1 => a,
+                2 => b,
+                3 => ((u16::from(a) + u16::from(b)) / 2) as u8,
+                _ => paeth(a, b, c),
+            }));
+        }
+        out
+    }
+
+    #[test]
+    fn undoes_every_filter_type() {
+        let rows = pattern(5, 4);
+        let expected = Icon::from_png(png(
+            (5, 4),
+            8,
+            RGBA,
+            false,
+            &[],
+            &unfiltered(&rows.iter().map(Vec::as_slice).collect::<Vec<_>>()),
+        ))
+        .unwrap();
+        // each filter on every line, then a different filter on each line
+        let filters = (0..5).map(|f| [f; 4]).chain([[4, 3, 2, 1], [1, 4, 0, 3]]);
+        for filters in filters {
+            let scanlines = rows
+                .iter()
+                .enumerate()
+                .flat_map(|(y, row)| {
+                    let prev = y.checked_sub(1).map(|y| rows[y].as_slice());
+                    filter(filters[y], row, prev, 4)
+                })
+                .collect::<Vec<_>>();
+            let icon = Icon::from_png(png((5, 4), 8, RGBA, false, &[], &scanlines)).unwrap();
+            assert_eq!(icon.rgba, expected.rgba, "filters {filters:?}");
+        }
+    }
+
+    #[test]
+    fn decodes_a_small_interlaced_image() {
+        // in a 3x3 image, passes 2 and 3 are empty and the rest cover
+        // (0, 0); (2, 0); (0, 2) (2, 2); (1, 0) / (1, 2); (0, 1) (1, 1) (2, 1)
+        let scanlines = unfiltered(&[&[0], &[2], &[6, 8], &[1], &[7], &[3, 4, 5]]);
+        let icon = Icon::from_png(png((3, 3), 8, GRAY, true, &[], &scanlines)).unwrap();
+        let gray = pixels(&icon).iter().map(|p| p[0]).collect::<Vec<_>>();
+        assert_eq!(gray, [0, 1, 2, 3, 4, 5, 6, 7, 8]);
+    }
+
+    #[test]
+    fn interlaced_images_match_their_non_interlaced_version() {
+        // which Adam7 pass each pixel of an 8x8 tile belongs to
+        const PASSES: [[u8; 8]; 8] = [
+            [1, 6, 4, 6, 2, 6, 4, 6],
+            [7, 7, 7, 7, 7, 7, 7, 7],
+            [5, 6, 5, 6, 5, 6, 5, 6],
+            [7, 7, 7, 7, 7, 7, 7, 7],
+            [3, 6, 4, 6, 3, 6, 4, 6],
+            [7, 7, 7, 7, 7, 7, 7, 7],
+            [5, 6, 5, 6, 5, 6, 5, 6],
+            [7, 7, 7, 7, 7, 7, 7, 7],
+        ];
+        for (width, height) in [(1, 1), (5, 3), (9, 13), (16, 16)] {
+            let rows = pattern(width, height);
+            let plain = Icon::from_png(png(
+                (width as u32, height as u32),
+                8,
+                RGBA,
+                false,
+                &[],
+                &unfiltered(&rows.iter().map(Vec::as_slice).collect::<Vec<_>>()),
+            ))
+            .unwrap();
+
+            let mut scanlines = Vec::new();
+            for pass in 1..=7 {
+                let mut prev: Option<Vec<u8>> = None;
+                for (y, row) in rows.iter().enumerate() {
+                    let line = row
+                        .chunks(4)
+                        .enumerate()
+                        .filter(|&(x, _)| PASSES[y % 8][x % 8] == pass)
+                        .flat_map(|(_, pixel)| pixel.to_vec())
+                        .collect::<Vec<_>>();
+                    if !line.is_empty() {
+                        // filter against the previous line of this pass
+                        scanlines.extend(filter(4, &line, prev.as_deref(), 4));
+                        prev = Some(line);
+                    }
+                }
+            }
+            let interlaced = Icon::from_png(png(
+                (width as u32, height as u32),
+                8,
+                RGBA,
+                true,
+                &[],
+                &scanlines,
+            ))
+            .unwrap();
+            assert_eq!(interlaced.rgba, plain.rgba, "{width}x{height}");
+        }
+    }
+
+    #[test]
+    fn decodes_base64_and_data_urls() {
+        let png = png((1, 1), 8, RGB, false, &[], &unfiltered(&[&[1, 2, 3]]));
+        let encoded = base64::encode(&png);
+        let icon = Icon::from_base64(&encoded).unwrap();
+        assert_eq!(icon.pixel(0, 0), [1, 2, 3, 255]);
+        assert_eq!(icon.png, png);
+        let url = format!("data:image/png;base64,{encoded}");
+        assert_eq!(Icon::from_data_url(&url).unwrap(), icon);
+        assert!(matches!(
+            Icon::from_data_url(&format!("data:image/jpeg;base64,{encoded}")),
+            Err(PngError::NotPng)
+        ));
+    }
+
+    #[test]
+    fn decodes_any_data_url_to_bytes() {
+        assert_eq!(
+            decode_data_url("data:image/png;base64,aGk=").as_deref(),
+            Some(&b"hi"[..])
+        );
+        assert_eq!(
+            decode_data_url("data:image/jpeg;base64,aGk=").as_deref(),
+            Some(&b"hi"[..])
+        );
+        for bad in ["aGk=", "data:image/png,hi", "data:image/png;base64,!"] {
+            assert_eq!(decode_data_url(bad), None, "{bad}");
+        }
+    }
+
+    #[test]
+    fn rejects_bad_pngs() {
+        let good = png(
+            (2, 2),
+            8,
+            GRAY,
+            false,
+            &[],
+            &unfiltered(&[&[1, 2], &[3, 4]]),
+        );
+        assert!(Icon::from_png(good.clone()).is_ok());
+
+        assert!(matches!(
+            Icon::from_png(good[1..].to_vec()),
+            Err(PngError::NotPng)
+        ));
+        // cut off partway through, and with the image data a row short
+        assert!(matches!(
+            Icon::from_png(good[..good.len() - 20].to_vec()),
+            Err(PngError::Truncated)
+        ));
+        let short = png((2, 2), 8, GRAY, false, &[], &unfiltered(&[&[1, 2]]));
+        assert!(matches!(Icon::from_png(short), Err(PngError::Truncated)));
+
+        let huge = png((4096, 1), 8, GRAY, false, &[], &[]);
+        assert!(matches!(
+            Icon::from_png(huge),
+            Err(PngError::TooLarge(4096, 1))
+        ));
+        let bad_filter = png((2, 1), 8, GRAY, false, &[], &[5, 1, 2]);
+        assert!(matches!(
+            Icon::from_png(bad_filter),
+            Err(PngError::Invalid("bad filter type"))
+        ));
+        let no_palette = png((1, 1), 8, PALETTE, false, &[], &unfiltered(&[&[0]]));
+        assert!(matches!(
+            Icon::from_png(no_palette),
+            Err(PngError::Invalid("missing palette"))
+        ));
+        let bad_depth = png((1, 1), 4, RGB, false, &[], &unfiltered(&[&[0]]));
+        assert!(matches!(
+            Icon::from_png(bad_depth),
+            Err(PngError::Invalid("bad bit depth"))
+        ));
+    }
+}
@@ -0,0 +1,331 @@
+//! Finding the game directories that third-party launchers keep their
+//! instances in, so that we can pick up each instance's `servers.dat`.
+
+use std::fmt;
+use std::fs;
+use std::path::{Path, PathBuf};
+
+use serde::Serialize;
+
+use crate::minecraft_dir;
+
+/// A launcher that we know how to find instances for.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "lowercase")]
+pub enum Launcher {
+    Vanilla,
+    Prism,
+    MultiMc,
+    AtLauncher,
+    CurseForge,
+    GdLauncher,
+    Modrinth,
+    Technic,
+}
+
+impl fmt::Display for Launcher {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.write_str(match self {
+            Launcher::Vanilla => "Vanilla",
+            Launcher::Prism => "Prism Launcher",
+            Launcher::MultiMc => "MultiMC",
+            Launcher::AtLauncher => "ATLauncher",
+            Launcher::CurseForge => "CurseForge",
+            Launcher::GdLauncher => "GDLauncher",
+            Launcher::Modrinth => "Modrinth App",
+            Launcher::Technic => "Technic",
+        })
+    }
+}
+
+/// A game directory belonging to some launcher.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+pub struct Instance {
+    pub launcher: Launcher,
+    pub name: String,
+    /// The directory the game runs in, which is where `servers.dat` lives.
+    pub game_dir: PathBuf,
+}
+
+impl Instance {
+    pub fn servers_dat(&self) -> PathBuf {
+        self.game_dir.join("servers.dat")
+    }
+}
+
+impl fmt::Display for Instance {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{} ({})", self.name, self.launcher)
+    }
+}
+
+/// Finds the instances of every launcher we know about, starting with the
+/// vanilla launcher's `.minecraft`. Launchers that aren't installed are
+/// skipped, as are instances whose game directory doesn't exist yet.
+pub fn discover_instances() -> Vec<Instance> {
+    let mut instances = Vec::new();
+    if let Some(game_dir) = minecraft_dir() {
+        instances.push(Instance {
+            launcher: Launcher::Vanilla,
+            name: "Minecraft".to_owned(),
+            game_dir,
+        });
+    }
+
+    let home = dirs_next::home_dir();
+    let data = dirs_next::data_dir();
+    let home_path = |path: &str| home.as_ref().map(|home| home.join(path));
+    let data_path = |path: &str| data.as_ref().map(|data| data.join(path));
+
+    // Prism and MultiMC keep the game in a .minecraft (or, in older
+    // versions, minecraft) folder inside the instance, and the name the
+    // user gave it in instance.cfg
+    let prism_roots = [
+        data_path("PrismLauncher"),
+        home_path(".var/app/org.prismlauncher.PrismLauncher/data/PrismLauncher"),
+        data_path("PolyMC"),
+    ];
+    for root in prism_roots.into_iter().flatten() {
+        find_multimc_style(&mut instances, Launcher::Prism, &root.join("instances"));
+    }
+    let multimc_roots = [data_path("multimc"), home_path("MultiMC")];
+    for root in multimc_roots.into_iter().flatten() {
+        find_multimc_style(&mut instances, Launcher::MultiMc, &root.join("instances"));
+    }
+
+    // the rest run the game straight from the instance folder
+    let flat = [
+        (Launcher::AtLauncher, data_path("ATLauncher/instances")),
+        (Launcher::AtLauncher, data_path("atlauncher/instances")),
+        (
+            Launcher::CurseForge,
+            home_path("curseforge/minecraft/Instances"),
+        ),
+        (
+            Launcher::CurseForge,
+            home_path("Documents/curseforge/minecraft/Instances"),
+        ),
+        (Launcher::GdLauncher, data_path("gdlauncher_next/instances")),
+        (Launcher::Modrinth, data_path("ModrinthApp/profiles")),
+        (
+            Launcher::Modrinth,
+            data_path("com.modrinth.theseus/profiles"),
+        ),
+        (Launcher::Technic, home_path(".technic/modpacks")),
+        (Launcher::Technic, data_path(".technic/modpacks")),
+        (Launcher::Technic, data_path("technic/modpacks")),
+    ];
+    for (launcher, dir) in flat {
+        if let Some(dir) = dir {
+            find_flat(&mut instances, launcher, &dir, None);
+        }
+    }
+    // GDLauncher's rewrite keeps the game one level further down
+    if let Some(dir) = data_path("gdlauncher_carbon/data/instances") {
+        find_flat(&mut instances, Launcher::GdLauncher, &dir, Some("instance"));
+    }
+
+    // the same folder can turn up twice, e.g. when the data dir is the home
+    // dir's .local/share
+    let mut seen = Vec::new();
+    instances.retain(|instance| {
+        let path = fs::canonicalize(&instance.game_dir).unwrap_or(instance.game_dir.clone());
+        if seen.contains(&path) {
+            false
+        } else {
+            seen.push(path);
+            true
+        }
+    });
+    instances
+}
+
+/// Lists the subdirectories of `dir`, sorted by name.
+fn subdirs(dir: &Path) -> Vec<PathBuf> {
+    let Ok(entries) = fs::read_dir(dir) else {
+        return Vec::new();
+    };
+    let mut dirs = entries
+        .filter_map(|entry| entry.ok())
+        .map(|entry| entry.path())
+        .filter(|path| path.is_dir())
+        .collect::<Vec<_>>();
+    dirs.sort();
+    dirs
+}
+
+fn dir_name(path: &Path) -> String {
+    path.file_name()
+        .map(|name| name.to_string_lossy().into_owned())
+        .unwrap_or_default()
+}
+
+fn find_multimc_style(instances: &mut Vec<Instance>, launcher: Launcher, dir: &Path) {
+    for path in subdirs(dir) {
+        let Some(game_dir) = [".minecraft", "minecraft"]
+            .into_iter()
+            .map(|name| path.join(name))
+            .find(|dir| dir.is_dir())
+        else {
+            continue;
+        };
+        let name = fs::read_to_string(path.join("instance.cfg"))
+            .ok()
+            .and_then(|cfg| {
+                cfg.lines()
+                    .find_map(|line| line.strip_prefix("name="))
+                    .map(|name| name.trim().to_owned())
+            })
+            .unwrap_or_else(|| dir_name(&path));
+        instances.push(Instance {
+            launcher,
+            name,
+            game_dir,
+        });
+    }
+}
+
+fn find_flat(instances: &mut Vec<Instance>, launcher: Launcher, dir: &Path, sub: Option<&str>) {
+    for path in subdirs(dir) {
+        let game_dir = match sub {
+            Some(sub) => path.join(sub),
+            None => path.clone(),
+        };
+        if game_dir.is_dir() {
+            instances.push(Instance {
+                launcher,
+                name: dir_name(&path),
+                game_dir,
+            });
+        }
+    }
+}
+
+/// Finds an instance by name, ignoring case. An exact match wins over a
+/// case-insensitive one.
+pub fn find_instance<'a>(instances: &'a [Instance], name: &str) -> Option<&'a Instance> {
+    instances
+        .iter()
+        .find(|instance| instance.name == name)
+        .or_else(|| {
+            instances
+                .iter()
+                .find(|instance| instance.name.eq_ignore_ascii_case(name))
+        })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// A fresh directory under the system's temp dir, removed on drop.
+    struct TempDir(PathBuf);
+
+    impl TempDir {
+        fn new(name: &str) -> Self {
+            let dir = std::env::temp_dir().join(format!("{name}-{}", std::process::id()));
+            let _ = fs::remove_dir_all(&dir);
+            fs::create_dir_all(&dir).unwrap();
+            TempDir(dir)
+        }
+    }
+
+    impl Drop for TempDir {
+        fn drop(&mut self) {
+            let _ = fs::remove_dir_all(&self.0);
+        }
+    }
+
+    fn instance(name: &str, launcher: Launcher, game_dir: PathBuf) -> Instance {
+        Instance {
+            launcher,
+            name: name.to_owned(),
+            game_dir,
+        }
+    }
+
+    #[test]
+    fn finds_multimc_style_instances() {
+        let tmp = TempDir::new("multimc-instances");
+        let dir = &tmp.0;
+        fs::create_dir_all(dir.join("fabric/.minecraft")).unwrap();
+        fs::write(
+            dir.join("fabric/instance.cfg"),
+            "InstanceType=OneSix\nname=Fabric 1.20 \n",
+        )
+        .unwrap();
+        // older versions, and an instance.cfg without a name
+        fs::create_dir_all(dir.join("old/minecraft")).unwrap();
+        fs::write(dir.join("old/instance.cfg"), "InstanceType=OneSix\n").unwrap();
+        // never launched, so there's no game directory yet
+        fs::create_dir_all(dir.join("new")).unwrap();
+        fs::write(dir.join("stray file"), "").unwrap();
+
+        let mut instances = Vec::new();
+        find_multimc_style(&mut instances, Launcher::Prism, dir);
+        assert_eq!(
+            instances,
+            [
+                instance(
+                    "Fabric 1.20",
+                    Launcher::Prism,
+                    dir.join("fabric/.minecraft")
+                ),
+                instance("old", Launcher::Prism, dir.join("old/minecraft")),
+            ]
+        );
+
+        find_multimc_style(&mut instances, Launcher::MultiMc, &dir.join("missing"));
+        assert_eq!(instances.len(), 2);
+    }
+
+    #[test]
+    fn finds_flat_instances() {
+        let tmp = TempDir::new("flat-instances");
+        let dir = &tmp.0;
+        fs::create_dir_all(dir.join("B Pack/instance")).unwrap();
+        fs::create_dir_all(dir.join("A Pack")).unwrap();
+
+        let mut instances = Vec::new();
+        find_flat(&mut instances, Launcher::CurseForge, dir, None);
+        assert_eq!(
+            instances,
+            [
+                instance("A Pack", Launcher::CurseForge, dir.join("A Pack")),
+                instance("B Pack", Launcher::CurseForge, dir.join("B Pack")),
+            ]
+        );
+
+        let mut instances = Vec::new();
+        find_flat(&mut instances, Launcher::GdLauncher, dir, Some("instance"));
+        assert_eq!(
+            instances,
+            [instance(
+                "B Pack",
+                Launcher::GdLauncher,
+                dir.join("B Pack/instance")
+            )]
+        );
+    }
+
+    #[test]
+    fn finds_instances_by_name() {
+        let instances = [
+            instance("Survival", Launcher::Vanilla, PathBuf::from("a")),
+            instance("survival", Launcher::Prism, PathBuf::from("b")),
+            instance("Creative", Launcher::Prism, PathBuf::from("c")),
+        ];
+        let find = |name| find_instance(&instances, name).map(|i| &i.game_dir);
+        assert_eq!(find("survival"), Some(&PathBuf::from("b")));
+        assert_eq!(find("Survival"), Some(&PathBuf::from("a")));
+        assert_eq!(find("CREATIVE"), Some(&PathBuf::from("c")));
+        assert_eq!(find("Hardcore"), None);
+    }
+
+    #[test]
+    fn displays_instances() {
+        let modded = instance("Modded", Launcher::MultiMc, PathBuf::from("/games/modded"));
+        assert_eq!(modded.to_string(), "Modded (MultiMC)");
+        assert_eq!(modded.servers_dat(), Path::new("/games/modded/servers.dat"));
+    }
+}
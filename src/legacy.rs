@@ -0,0 +1,202 @@
+//! The [legacy server list ping](https://wiki.vg/Server_List_Ping#1.6) that
+//! servers before 1.7 understand, back to beta 1.8.
+
+use std::net::SocketAddr;
+use std::time::Duration;
+
+use tokio::io::{AsyncReadExt, AsyncWriteExt};
+use tokio::net::TcpStream;
+
+use crate::net::{connect_tcp, with_timeout};
+use crate::{Error, Result};
+
+/// The protocol version we claim to speak in the `MC|PingHost` message,
+/// which is 1.6.4's.
+const PROTOCOL_VERSION: u8 = 78;
+
+/// What a legacy server tells us about itself.
+#[derive(Debug)]
+pub struct LegacyStatus {
+    /// The protocol and version name, which servers before 1.4 don't send.
+    pub version: Option<(i32, String)>,
+    /// The MOTD, possibly with `§` formatting codes.
+    pub motd: String,
+    pub online: u32,
+    pub max: u32,
+}
+
+/// A connection to a server that speaks the legacy ping.
+pub struct LegacyConnection {
+    stream: TcpStream,
+    host: String,
+    port: u16,
+    timeout: Duration,
+}
+
+impl LegacyConnection {
+    /// Opens a TCP connection to the server at `addr`, which will tell it
+    /// that we're looking for `host`. `timeout` is for connecting, and for
+    /// reading the status unless changed with
+    /// [`set_read_timeout`](Self::set_read_timeout).
+    pub async fn connect(addr: SocketAddr, host: &str, timeout: Duration) -> Result<Self> {
+        let stream = connect_tcp(addr, timeout).await?;
+        Ok(LegacyConnection {
+            stream,
+            host: host.to_owned(),
+            port: addr.port(),
+            timeout,
+        })
+    }
+
+    /// Sets how long to wait for the server to answer.
+    pub fn set_read_timeout(&mut self, timeout: Duration) {
+        self.timeout = timeout;
+    }
+
+    /// Sends the 1.6 ping and reads the server's kick packet with its
+    /// status. Older servers ignore everything after the `0xFE` and answer
+    /// in their own format, which we also understand.
+    pub async fn status(mut self) -> Result<LegacyStatus> {
+        let mut host_data = Vec::new();
+        host_data.push(PROTOCOL_VERSION);
+        write_utf16(&mut host_data, &self.host);
+        host_data.extend_from_slice(&i32::from(self.port).to_be_bytes());
+
+        let mut packet = vec![0xfe, 0x01, 0xfa];
+        write_utf16(&mut packet, "MC|PingHost");
+        packet.extend_from_slice(&(host_data.len() as u16).to_be_bytes());
+        packet.extend_from_slice(&host_data);
+
+        let stream = &mut self.stream;
+        let response = with_timeout(self.timeout, async {
+            stream.write_all(&packet).await?;
+            if stream.read_u8().await? != 0xff {
+                return Err(Error::InvalidPacket("expected a kick packet"));
+            }
+            let len = stream.read_u16().await?;
+            let mut data = vec![0; usize::from(len) * 2];
+            stream.read_exact(&mut data).await?;
+            Ok(data)
+        })
+        .await?;
+
+        let response = char::decode_utf16(
+            response
+                .chunks_exact(2)
+                .map(|c| u16::from_be_bytes([c[0], c[1]])),
+        )
+        .collect::<Result<String, _>>()
+        .map_err(|_| Error::InvalidPacket("kick message is not valid UTF-16"))?;
+
+        parse_response(&response).ok_or(Error::InvalidPacket("malformed legacy ping response"))
+    }
+}
+
+fn parse_response(response: &str) -> Option<LegacyStatus> {
+    if let Some(fields) = response.strip_prefix("§1\0") {
+        // 1.4+: §1\0protocol\0version\0motd\0online\0max
+        let mut fields = fields.split('\0');
+        let protocol = fields.next()?.parse().ok()?;
+        let version = fields.next()?.to_owned();
+        let motd = fields.next()?.to_owned();
+        let online = fields.next()?.parse().ok()?;
+        let max = fields.next()?.parse().ok()?;
+        Some(LegacyStatus {
+            version: Some((protocol, version)),
+            motd,
+            online,
+            max,
+        })
+    } else {
+        // beta 1.8 - 1.3: motd§online§max, where the motd can't contain §
+        let mut fields = response.rsplitn(3, '§');
+        let max = fields.next()?.parse().ok()?;
+        let online = fields.next()?.parse().ok()?;
+        let motd = fields.next()?.to_owned();
+        Some(LegacyStatus {
+            version: None,
+            motd,
+            online,
+            max,
+        })
+    }
+}
+
+/// Writes a string the way the old protocol does: a big-endian `u16` count
+/// of UTF-16 code units, followed by the code units.
+fn write_utf16(buf: &mut Vec<u8>, s: &str) {
+    let units = s.encode_utf16().collect::<Vec<_>>();
+    buf.extend_from_slice(&(units.len() as u16).to_be_bytes());
+    for unit in units {
+        buf.extend_from_slice(&unit.to_be_bytes());
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use tokio::net::TcpListener;
+
+    use super::*;
+
+    #[test]
+    fn parses_responses() {
+        let status =
+            parse_response("§1\x0078\x001.6.4\x00A §aMinecraft Server\x003\x0020").unwrap();
+        assert_eq!(status.version, Some((78, "1.6.4".to_owned())));
+        assert_eq!(status.motd, "A §aMinecraft Server");
+        assert_eq!((status.online, status.max), (3, 20));
+
+        let status = parse_response("A Minecraft Server§0§20").unwrap();
+        assert_eq!(status.version, None);
+        assert_eq!(status.motd, "A Minecraft Server");
+        assert_eq!((status.online, status.max), (0, 20));
+
+        for bad in [
+            "",
+            "§1\x0078\x001.6.4\x00motd\x003",
+            "motd§x§20",
+            "§1\x00x\x00\x00\x000\x000",
+        ] {
+            assert!(parse_response(bad).is_none(), "{bad:?}");
+        }
+    }
+
+    #[test]
+    fn writes_utf16() {
+        let mut buf = Vec::new();
+        write_utf16(&mut buf, "MC|é");
+        assert_eq!(buf, b"\x00\x04\x00M\x00C\x00|\x00\xe9");
+    }
+
+    #[tokio::test]
+    async fn gets_the_status() {
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let port = listener.local_addr().unwrap().port();
+        tokio::spawn(async move {
+            let (mut stream, _) = listener.accept().await.unwrap();
+            let mut expected = vec![0xfe, 0x01, 0xfa];
+            write_utf16(&mut expected, "MC|PingHost");
+            expected.extend_from_slice(&[0x00, 0x19, PROTOCOL_VERSION]);
+            write_utf16(&mut expected, "localhost");
+            expected.extend_from_slice(&i32::from(port).to_be_bytes());
+            let mut request = vec![0; expected.len()];
+            stream.read_exact(&mut request).await.unwrap();
+            assert_eq!(request, expected);
+
+            let mut kick = vec![0xff];
+            write_utf16(&mut kick, "§1\x0078\x001.6.4\x00Hello\x001\x0020");
+            stream.write_all(&kick).await.unwrap();
+        });
+
+        let conn = LegacyConnection::connect(
+            ([127, 0, 0, 1], port).into(),
+            "localhost",
+            Duration::from_secs(5),
+        )
+        .await
+        .unwrap();
+        let status = conn.status().await.unwrap();
+        assert_eq!(status.motd, "Hello");
+        assert_eq!((status.online, status.max), (1, 20));
+    }
+}
@@ -0,0 +1,41 @@
+//! Check the status of Minecraft servers: who's online, the MOTD, version and
+//! latency. This is the library behind the `mcserverstatus` command; it can
+//! also read the list of saved servers from a `servers.dat` file, and find
+//! the instances of popular launchers that keep one.
+//!
+//! ```no_run
+//! # async fn example() -> Result<(), Box<dyn std::error::Error>> {
+//! use mcserverstatus::{query_status, QueryOptions, ServerAddress};
+//!
+//! let addr: ServerAddress = "mc.hypixel.net".parse()?;
+//! let status = query_status(&addr, &QueryOptions::default()).await?;
+//! println!("{}/{} online", status.online, status.max);
+//! # Ok(())
+//! # }
+//! ```
+
+mod address;
+pub mod base64;
+pub mod bedrock;
+mod diagnose;
+pub mod dns;
+mod error;
+pub mod icon;
+mod instances;
+pub mod legacy;
+pub mod motd;
+mod net;
+pub mod ping;
+pub mod query;
+mod servers_dat;
+mod status;
+
+pub use address::{AddressError, AddressFamily, ServerAddress, DEFAULT_PORT};
+pub use diagnose::{diagnose, Step, StepKind};
+pub use error::{Error, Result};
+pub use instances::{discover_instances, find_instance, Instance, Launcher};
+pub use servers_dat::{minecraft_dir, Server, ServersDat};
+pub use status::{
+    query_status, query_status_with_progress, Latency, Phase, Player, Protocol, QueryOptions,
+    ServerStatus, Version, RETRY_DELAY,
+};
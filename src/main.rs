@@ -1,47 +1,76 @@
-use std::fs::File;
+use std::fmt;
 use std::future::Future;
-use std::io::{self, BufReader};
-use std::path::PathBuf;
-use std::process::{ExitCode, Termination};
-use std::time::Duration;
+use std::io::{self, Write};
+use std::net::{IpAddr, SocketAddr};
+use std::path::{Path, PathBuf};
+use std::process::ExitCode;
+use std::sync::{Arc, Once};
+use std::time::{Duration, SystemTime};
 
 use anyhow::Context;
-use clap::{AppSettings, ArgGroup, Parser};
-use dialoguer::{theme::ColorfulTheme, Select};
+use clap::{AppSettings, ArgEnum, ArgGroup, CommandFactory, Parser, Subcommand};
+use dialoguer::{theme::ColorfulTheme, MultiSelect, Select};
 use itertools::Itertools;
-use serde::Deserialize;
+use mcserverstatus::dns::Resolver;
+use mcserverstatus::{
+    base64, diagnose, discover_instances, find_instance, icon, query_status_with_progress,
+    AddressFamily, Instance, Latency, Phase, Protocol, QueryOptions, Server, ServerAddress,
+    ServerStatus, ServersDat, Step, StepKind,
+};
+use serde::Serialize;
+use tokio::sync::Semaphore;
 
-#[derive(Deserialize)]
-struct ServersDat {
-    servers: Vec<Server>,
-}
+mod check;
+mod config;
+mod exit;
+mod graphics;
+#[cfg(feature = "history")]
+mod history;
+mod metrics;
+mod picker;
+#[cfg(feature = "history")]
+mod sqlite;
+mod state;
+mod tui;
 
-#[derive(Deserialize)]
-struct Server {
-    // #[serde_as(as = "Base64")]
-    // icon: Vec<u8>
-    ip: String,
-    name: String,
-}
+use check::CheckArgs;
+use config::Config;
+use exit::{ErrorKind, ErrorOutput};
+use graphics::IconMode;
+#[cfg(feature = "history")]
+use history::{HistoryArgs, RecordArgs};
+use metrics::ServeArgs;
+use state::{LastSeen, State};
+use tui::TuiArgs;
 
-impl ToString for Server {
-    fn to_string(&self) -> String {
-        format!("{} (ip: {})", self.name, self.ip)
-    }
+#[derive(Debug, Clone, Copy, PartialEq, Eq, ArgEnum)]
+enum OutputFormat {
+    Text,
+    Json,
 }
 
 #[derive(Parser)]
-#[clap(author, version, about, long_about = None)]
+#[clap(author, version, about, long_about = None, after_help = exit::EXIT_CODES_HELP)]
 #[clap(setting(AppSettings::DeriveDisplayOrder))]
 #[clap(group(
     ArgGroup::new("server-choice").args(&["instance", "server", "servers-file"])
 ))]
 struct Args {
-    /// Path to the folder for your minecraft instance [default: the standard .minecraft folder]
-    #[clap(short, long, parse(from_os_str))]
+    #[clap(subcommand)]
+    command: Option<Command>,
+
+    /// A server alias from the config file, a server address, or part of
+    /// the name of a server in servers.dat to pick from
+    #[clap(value_name = "ALIAS|SERVER|NAME", conflicts_with = "server")]
+    target: Option<String>,
+
+    /// Path to the folder for your minecraft instance, or the name of a
+    /// launcher instance (see --list-instances) [default: the config's
+    /// instance, or pick from the instances that have saved servers]
+    #[clap(short, long, value_name = "PATH|NAME", parse(from_os_str))]
     instance: Option<PathBuf>,
 
-    /// IP for the minecraft server to query
+    /// IP for the minecraft server to query, or an alias from the config
     #[clap(short, long)]
     server: Option<String>,
 
@@ -49,150 +78,1175 @@ struct Args {
     #[clap(short = 'f', long, parse(from_os_str))]
     servers_file: Option<PathBuf>,
 
-    /// Connection timeout in seconds
-    #[clap(long, short, default_value = "2.0")]
-    timeout: f64,
+    /// Timeout in seconds for connecting and for each response from the
+    /// server [default: 2.0]
+    #[clap(long, short, global = true, value_name = "SECS", value_parser = parse_secs)]
+    timeout: Option<Duration>,
+
+    /// How long to wait for the connection to be accepted, in seconds
+    /// [default: --timeout]
+    #[clap(long, global = true, value_name = "SECS", value_parser = parse_secs)]
+    connect_timeout: Option<Duration>,
+
+    /// How long to wait for each response, in seconds [default: --timeout]
+    #[clap(long, global = true, value_name = "SECS", value_parser = parse_secs)]
+    read_timeout: Option<Duration>,
+
+    /// Give up on the whole query, retries included, after SECS seconds
+    #[clap(long, global = true, value_name = "SECS", value_parser = parse_secs)]
+    deadline: Option<Duration>,
+
+    /// Try again up to N times when the server can't be reached or times
+    /// out, waiting 0.5s before the first retry and twice as long each time
+    #[clap(long, global = true, value_name = "N", default_value = "0")]
+    retries: u32,
+
+    /// How many times to ping the server when measuring latency
+    #[clap(
+        long,
+        global = true,
+        default_value = "1",
+        value_parser = clap::value_parser!(u32).range(1..)
+    )]
+    pings: u32,
+
+    /// Use the legacy server list ping for servers older than 1.7, instead
+    /// of only falling back to it when the modern one fails
+    #[clap(long, global = true)]
+    legacy: bool,
+
+    /// Query a Bedrock Edition server (default port 19132) instead of a Java
+    /// Edition one
+    #[clap(long, global = true, conflicts_with = "legacy")]
+    bedrock: bool,
+
+    /// Get the full player list, server software and plugins through the
+    /// GameSpy4 query protocol (needs enable-query=true on the server)
+    #[clap(short, long)]
+    query: bool,
+
+    /// The UDP port to use for --query [default: the server's port]
+    #[clap(long, requires = "query")]
+    query_port: Option<u16>,
+
+    /// DNS server for looking up the server's SRV record, as IP or IP:PORT
+    /// [default: the system's, from /etc/resolv.conf or the Windows registry]
+    #[clap(long, global = true, value_name = "ADDR", value_parser = parse_dns_server)]
+    dns_server: Option<SocketAddr>,
+
+    /// Only connect over IPv4
+    #[clap(short = '4', long, global = true, conflicts_with = "ipv6")]
+    ipv4: bool,
+
+    /// Only connect over IPv6
+    #[clap(short = '6', long, global = true)]
+    ipv6: bool,
+
+    /// Ping every server in servers.dat and print a summary table
+    #[clap(short, long, conflicts_with_all = &["server", "target"])]
+    all: bool,
+
+    /// Tick any number of servers from servers.dat, and ping them all at
+    /// once for a summary table like --all
+    #[clap(short, long, conflicts_with_all = &["server", "all"])]
+    multi: bool,
+
+    /// Query the server you picked from servers.dat last time again,
+    /// without asking
+    #[clap(short, long, conflicts_with_all = &["server-choice", "target", "all", "multi"])]
+    last: bool,
+
+    /// Keep polling the server every INTERVAL seconds and report players
+    /// joining and leaving (with UTC timestamps)
+    #[clap(
+        short,
+        long,
+        value_name = "INTERVAL",
+        value_parser = parse_secs,
+        conflicts_with_all = &["all", "multi"]
+    )]
+    watch: Option<Duration>,
+
+    /// Write the server's icon to PATH as a PNG, falling back to the one
+    /// cached in servers.dat if the server doesn't send one
+    #[clap(
+        long,
+        value_name = "PATH",
+        parse(from_os_str),
+        conflicts_with_all = &["all", "multi", "watch"]
+    )]
+    save_icon: Option<PathBuf>,
+
+    /// Go through the query one step at a time, showing how long each step
+    /// took and what it found, to see where it goes wrong
+    #[clap(
+        long,
+        conflicts_with_all = &["all", "multi", "watch", "save-icon", "list-instances"]
+    )]
+    diagnose: bool,
+
+    /// List the instances found for the vanilla launcher, Prism/MultiMC,
+    /// ATLauncher, CurseForge, GDLauncher, Modrinth App and Technic
+    #[clap(
+        long,
+        conflicts_with_all = &["server-choice", "target", "all", "multi", "last", "watch"]
+    )]
+    list_instances: bool,
+
+    /// How to print the server's status [default: text]
+    #[clap(long, arg_enum)]
+    format: Option<OutputFormat>,
+
+    /// How to draw server icons
+    #[clap(long, arg_enum, value_name = "MODE", default_value = "auto")]
+    icons: IconMode,
+
+    /// Path to the config file [default: mcserverstatus/config.toml in
+    /// ~/.config on Linux, ~/Library/Application Support on macOS, or
+    /// %APPDATA% on Windows]
+    #[clap(long, global = true, value_name = "PATH", parse(from_os_str))]
+    config: Option<PathBuf>,
 }
 
-fn get_minecraft_dir() -> anyhow::Result<PathBuf> {
-    let mc_dir = if cfg!(any(windows, target_os = "macos")) {
-        dirs_next::data_dir()
-    } else {
-        dirs_next::home_dir()
-    };
-    let mc_dir = mc_dir.and_then(|mut mc_dir| {
-        mc_dir.push(if cfg!(target_os = "macos") {
-            "minecraft"
+#[derive(Subcommand)]
+enum Command {
+    /// Check a server for monitoring systems, as a Nagios/Icinga plugin:
+    /// exits 0, 1, 2 or 3 for OK, WARNING, CRITICAL or UNKNOWN
+    Check(CheckArgs),
+    /// Serve Prometheus metrics about servers, at /metrics for a fixed list
+    /// and at /probe?target=ADDR for any server
+    ServeMetrics(ServeArgs),
+    /// Show a full-screen dashboard of every server in servers.dat, which
+    /// refreshes itself
+    Tui(TuiArgs),
+    /// Keep polling a server and add every result to a SQLite database in
+    /// the data directory
+    #[cfg(feature = "history")]
+    Record(RecordArgs),
+    /// Summarize the recorded results: uptime, average and peak players,
+    /// latency and the busiest hour of the day
+    #[cfg(feature = "history")]
+    History(HistoryArgs),
+    /// Not in this build, which doesn't have history support
+    #[cfg(not(feature = "history"))]
+    #[clap(setting(AppSettings::AllowHyphenValues))]
+    Record {
+        #[clap(hide = true)]
+        args: Vec<String>,
+    },
+    /// Not in this build, which doesn't have history support
+    #[cfg(not(feature = "history"))]
+    #[clap(setting(AppSettings::AllowHyphenValues))]
+    History {
+        #[clap(hide = true)]
+        args: Vec<String>,
+    },
+}
+
+/// Formats a number of milliseconds, keeping a decimal place for the
+/// sub-10ms times you get on a LAN.
+struct Millis(f64);
+
+impl fmt::Display for Millis {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        if self.0 < 10.0 {
+            write!(f, "{:.1}", self.0)
         } else {
-            ".minecraft"
-        });
-        mc_dir.is_dir().then(|| mc_dir)
-    });
-    mc_dir.context(
-        "Couldn't resolve .minecraft directory, please check \
-         that it exists or pass the path explicitly.",
-    )
+            write!(f, "{:.0}", self.0)
+        }
+    }
+}
+
+/// Formats the latency summary for text output.
+struct DisplayLatency<'a>(&'a Latency);
+
+impl fmt::Display for DisplayLatency<'_> {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        if self.0.pings == 1 {
+            write!(f, "{}ms", Millis(self.0.avg_ms))
+        } else {
+            write!(
+                f,
+                "{}/{}/{}ms min/avg/max, {}ms jitter over {} pings",
+                Millis(self.0.min_ms),
+                Millis(self.0.avg_ms),
+                Millis(self.0.max_ms),
+                Millis(self.0.jitter_ms),
+                self.0.pings
+            )
+        }
+    }
+}
+
+/// The outcome of querying one entry in servers.dat, for `--all`.
+#[derive(Serialize)]
+struct ServerResult {
+    name: String,
+    ip: String,
+    status: Option<ServerStatus>,
+    error: Option<String>,
+    error_kind: Option<ErrorKind>,
+}
+
+fn print_status(
+    status: &ServerStatus,
+    format: OutputFormat,
+    icons: Option<IconMode>,
+) -> anyhow::Result<()> {
+    match format {
+        OutputFormat::Text => {
+            if let (Some(mode), Some(icon)) = (icons, &status.icon) {
+                print!("{}", graphics::render(icon, mode));
+            }
+            if !status.motd.0.is_empty() {
+                println!("{}", status.motd.styled());
+            }
+            println!(
+                "{}/{} online{}",
+                status.online,
+                status.max,
+                if status.players.is_empty() { "" } else { ":" }
+            );
+            if !status.players.is_empty() {
+                let players = status.players.iter().map(|player| &*player.name).join(" ");
+                let options = textwrap::Options::new(60)
+                    .initial_indent("    ")
+                    .subsequent_indent("    ");
+                for line in textwrap::wrap(&players, options) {
+                    println!("{line}");
+                }
+            }
+            println!("Latency: {}", DisplayLatency(&status.latency));
+            if let Some(stat) = &status.query {
+                if let Some(software) = &stat.software {
+                    println!("Software: {software}");
+                }
+                println!("Map: {}", stat.map);
+                if !stat.plugins.is_empty() {
+                    println!("Plugins: {}", stat.plugins.join(", "));
+                }
+            }
+        }
+        OutputFormat::Json => print_json(status)?,
+    }
+    Ok(())
+}
+
+fn print_json<T: Serialize + ?Sized>(value: &T) -> anyhow::Result<()> {
+    let stdout = io::stdout();
+    let mut stdout = stdout.lock();
+    serde_json::to_writer(&mut stdout, value)?;
+    writeln!(stdout)?;
+    Ok(())
+}
+
+/// Turns a number of seconds from the user into a [`Duration`], or `None`
+/// if it isn't positive or is too big for one (which includes infinity and
+/// NaN).
+fn secs_to_duration(secs: f64) -> Option<Duration> {
+    Duration::try_from_secs_f64(secs)
+        .ok()
+        .filter(|duration| !duration.is_zero())
+}
+
+fn parse_secs(s: &str) -> Result<Duration, String> {
+    s.parse::<f64>()
+        .ok()
+        .and_then(secs_to_duration)
+        .ok_or_else(|| format!("{s:?} is not a positive number of seconds, or is too large"))
+}
+
+fn parse_dns_server(s: &str) -> Result<SocketAddr, String> {
+    s.parse::<SocketAddr>()
+        .or_else(|_| s.parse::<IpAddr>().map(|ip| (ip, 53).into()))
+        .map_err(|_| format!("{s:?} is not an IP address"))
 }
 
 #[tokio::main]
 async fn main() -> ExitCode {
     let term = console::Term::stderr();
-    tokio::select! {
-        res = tokio::signal::ctrl_c() => res.unwrap(),
-        res = app(&term) => {
-            match res {
-                Err(e) if e.is::<CtrlC>() => {}
-                res => return res.report(),
+    let res = tokio::select! {
+        res = tokio::signal::ctrl_c() => res.map_err(anyhow::Error::from).and(Err(CtrlC.into())),
+        res = app(&term) => res,
+    };
+    match res {
+        Ok(code) => code,
+        Err(e) => {
+            let kind = ErrorKind::of(&e);
+            if kind == ErrorKind::Cancelled {
+                // ctrl-C, the spinner or prompt may have hidden the cursor
+                let _ = term.show_cursor();
+            } else {
+                eprintln!("Error: {e:?}");
             }
+            ExitCode::from(kind.exit_code())
         }
     }
-    // ctrl-C, reset term and exit with generic error code
-    let _ = term.show_cursor();
-    ExitCode::FAILURE
 }
 
 #[derive(Debug, thiserror::Error)]
 #[error("ctrl-c")]
 struct CtrlC;
 
-async fn app(term: &console::Term) -> anyhow::Result<()> {
-    let args = Args::parse();
+async fn app(term: &console::Term) -> anyhow::Result<ExitCode> {
+    let args = Args::try_parse().unwrap_or_else(|e| {
+        // monitoring systems read exit code 2 as CRITICAL, so a broken
+        // `check` command line has to be UNKNOWN instead
+        if e.use_stderr() && is_check_command() {
+            let _ = e.print();
+            std::process::exit(3);
+        }
+        e.exit()
+    });
+    let is_check = matches!(args.command, Some(Command::Check(_)));
+    let res = match Config::load(args.config.as_deref()) {
+        Ok(config) => {
+            // flags on the command line win over the config file
+            let format = args.format.or(config.format).unwrap_or(OutputFormat::Text);
+            run(args, config, format, term)
+                .await
+                .map_err(|e| (e, Some(format)))
+        }
+        Err(e) => Err((e.into(), args.format)),
+    };
+    match res {
+        Ok(code) => Ok(code),
+        // monitoring systems only understand the plugin output, so anything
+        // that stops `check` from querying the server is UNKNOWN
+        Err((e, _)) if is_check => Ok(check::unknown(&e)),
+        Err((e, format)) => Err(report_json(e, format)),
+    }
+}
+
+/// Whether the command line is for the `check` subcommand, even if it has
+/// errors. Parsing it again is the only way to tell `check` apart from a
+/// server or option value that happens to be called that.
+fn is_check_command() -> bool {
+    Args::command()
+        .ignore_errors(true)
+        .try_get_matches()
+        .is_ok_and(|matches| matches.subcommand_name() == Some("check"))
+}
+
+/// With `--format json`, also prints the error to stdout for scripts, with
+/// its [`ErrorKind`].
+fn report_json(err: anyhow::Error, format: Option<OutputFormat>) -> anyhow::Error {
+    if format == Some(OutputFormat::Json) && ErrorKind::of(&err) != ErrorKind::Cancelled {
+        let _ = print_json(&ErrorOutput::new(&err));
+    }
+    err
+}
 
-    let timeout = Duration::from_secs_f64(args.timeout);
+async fn run(
+    args: Args,
+    config: Config,
+    format: OutputFormat,
+    term: &console::Term,
+) -> anyhow::Result<ExitCode> {
+    let timeout = args
+        .timeout
+        .or(config.timeout)
+        .unwrap_or(Duration::from_secs(2));
+    let connect_timeout = args.connect_timeout.unwrap_or(timeout);
+    let read_timeout = args.read_timeout.unwrap_or(timeout);
+    // a positional argument that isn't an alias or an address is part of a
+    // server's name, to pick the server from servers.dat with
+    let (server, name_query) = match (args.server, args.target) {
+        (Some(server), _) => (Some(server), None),
+        (None, Some(target))
+            if config.aliases.contains_key(&target) || looks_like_address(&target) =>
+        {
+            (Some(target), None)
+        }
+        (None, target) => (None, target),
+    };
+    let server = match args.last {
+        true => Some(
+            State::load()
+                .last_server
+                .context("There's no last server yet, as none has been picked from servers.dat")?,
+        ),
+        false => server,
+    };
+    let instance = args.instance.or(config.instance.clone());
+    // the picker draws on `term`, but the status goes to stdout
+    let icons = args.icons.resolve(&console::Term::stdout());
+
+    let options = QueryOptions {
+        connect_timeout,
+        read_timeout,
+        deadline: args.deadline,
+        retries: args.retries,
+        pings: args.pings,
+        protocol: if args.legacy {
+            Protocol::Legacy
+        } else if args.bedrock {
+            Protocol::Bedrock
+        } else {
+            Protocol::Auto
+        },
+        full_query: args.query,
+        query_port: args.query_port,
+        dns_server: args.dns_server,
+        family: if args.ipv4 {
+            AddressFamily::V4
+        } else if args.ipv6 {
+            AddressFamily::V6
+        } else {
+            AddressFamily::Any
+        },
+    };
 
-    let server_str = if let Some(server) = args.server {
-        server
-    } else {
+    let spinner = &indicatif::ProgressBar::new_spinner();
+    spinner.set_draw_target(indicatif::ProgressDrawTarget::term(term.clone(), 15));
+
+    match &args.command {
+        Some(Command::Check(check)) => {
+            let server_str = config.resolve_alias(&check.server);
+            return Ok(check::check(check, server_str, &options).await);
+        }
+        Some(Command::ServeMetrics(serve)) => {
+            metrics::serve(serve, config, options).await?;
+            return Ok(ExitCode::SUCCESS);
+        }
+        Some(Command::Tui(tui)) => {
+            let instance = tui.instance.clone().or(config.instance.clone());
+            let servers_file = tui.servers_file.clone();
+            let prompt_term = term.clone();
+            let dat = tokio::task::spawn_blocking(move || {
+                load_servers_dat(instance, servers_file, &prompt_term)
+            })
+            .await??;
+            tui::run(tui, dat.servers, &options, term).await?;
+            return Ok(ExitCode::SUCCESS);
+        }
+        #[cfg(feature = "history")]
+        Some(Command::Record(record)) => {
+            let server_str = config.resolve_alias(&record.server);
+            history::record(record, server_str, &options, format).await?;
+            return Ok(ExitCode::SUCCESS);
+        }
+        #[cfg(feature = "history")]
+        Some(Command::History(hist)) => {
+            let server_str = hist
+                .server
+                .as_deref()
+                .map(|server| config.resolve_alias(server));
+            history::history(hist, server_str, format)?;
+            return Ok(ExitCode::SUCCESS);
+        }
+        #[cfg(not(feature = "history"))]
+        Some(Command::Record { .. } | Command::History { .. }) => {
+            anyhow::bail!(
+                "mcserverstatus was not built with history support, reinstall it with \
+                 `--features history` to use `record` and `history`"
+            );
+        }
+        None => {}
+    }
+
+    if args.list_instances {
+        let instances = tokio::task::spawn_blocking(discover_instances).await?;
+        print_instances(&instances, format)?;
+        return Ok(ExitCode::SUCCESS);
+    }
+
+    if args.all {
         let term = term.clone();
-        tokio::task::spawn_blocking(move || {
-            let servers_path = if let Some(path) = args.servers_file {
-                path
-            } else {
-                let mut path = match args.instance {
-                    Some(x) => x,
-                    None => get_minecraft_dir()?,
-                };
-                path.push("servers.dat");
-                path
-            };
+        let dat = tokio::task::spawn_blocking(move || {
+            load_servers_dat(instance, args.servers_file, &term)
+        })
+        .await??;
+
+        spinner.set_message(format!("Pinging {} servers...", dat.servers.len()));
+        let results = spin(spinner, query_all(dat.servers, &options)).await;
+        remember(results.iter().map(|res| (&*res.ip, res.status.as_ref())));
+        print_results(&results, format)?;
+        return Ok(ExitCode::SUCCESS);
+    }
 
-            let dat: ServersDat = nbt::from_reader(BufReader::new(File::open(servers_path)?))?;
+    if args.multi {
+        let term = term.clone();
+        let servers = tokio::task::spawn_blocking(move || {
+            pick_servers(
+                instance,
+                args.servers_file,
+                name_query.as_deref(),
+                args.icons,
+                &term,
+            )
+        })
+        .await??;
 
-            let theme = ColorfulTheme::default();
-            let selection = Select::with_theme(&theme)
-                .with_prompt("Which server?")
-                .items(&dat.servers)
-                .default(0)
-                .interact_on(&term);
-
-            let selection = match selection {
-                Err(e) if e.kind() == io::ErrorKind::Interrupted => {
-                    anyhow::bail!(CtrlC)
+        spinner.set_message(format!("Pinging {} servers...", servers.len()));
+        let results = spin(spinner, query_all(servers, &options)).await;
+        remember(results.iter().map(|res| (&*res.ip, res.status.as_ref())));
+        print_results(&results, format)?;
+        return Ok(ExitCode::SUCCESS);
+    }
+
+    // the icon the game cached when the server is picked from servers.dat
+    let (server_str, cached_icon) = match server {
+        Some(server) => (config.resolve_alias(&server).to_owned(), None),
+        None => {
+            let term = term.clone();
+            let picker_query = name_query.clone();
+            let picked = tokio::task::spawn_blocking(move || {
+                pick_server(
+                    instance,
+                    args.servers_file,
+                    picker_query.as_deref(),
+                    args.icons,
+                    &term,
+                )
+            })
+            .await??;
+            // nothing in servers.dat goes by that name, so it could well be
+            // a host name
+            picked.unwrap_or_else(|| (name_query.unwrap(), None))
+        }
+    };
+
+    if args.diagnose {
+        let addr = server_str.parse::<ServerAddress>()?;
+        spinner.set_message("Diagnosing...");
+        let steps = spin(spinner, diagnose(&addr, &options)).await;
+        return print_diagnosis(steps, format);
+    }
+
+    if let Some(interval) = args.watch {
+        watch(&server_str, &options, interval, format, icons, spinner).await?;
+        return Ok(ExitCode::SUCCESS);
+    }
+
+    let res = spin(spinner, query(&server_str, &options, spinner)).await;
+    remember([(&*server_str, res.as_ref().ok())]);
+    let status = res?;
+
+    if let Some(path) = &args.save_icon {
+        save_icon(path, status.favicon.as_deref(), cached_icon.as_deref())?;
+    }
+
+    print_status(&status, format, icons)?;
+    Ok(ExitCode::SUCCESS)
+}
+
+/// Something that changed between two polls in `--watch` mode.
+#[derive(Serialize)]
+#[serde(tag = "event", rename_all = "snake_case")]
+enum WatchEvent<'a> {
+    Joined {
+        player: &'a str,
+    },
+    Left {
+        player: &'a str,
+    },
+    Count {
+        online: u32,
+        max: u32,
+    },
+    Error {
+        error: String,
+        error_kind: ErrorKind,
+    },
+}
+
+#[derive(Serialize)]
+struct TimedEvent<'a> {
+    timestamp: u64,
+    #[serde(flatten)]
+    event: WatchEvent<'a>,
+}
+
+async fn watch(
+    server_str: &str,
+    options: &QueryOptions,
+    interval: Duration,
+    format: OutputFormat,
+    icons: Option<IconMode>,
+    spinner: &indicatif::ProgressBar,
+) -> anyhow::Result<()> {
+    let mut prev: Option<ServerStatus> = None;
+    let mut ticker = tokio::time::interval(interval);
+    ticker.set_missed_tick_behavior(tokio::time::MissedTickBehavior::Delay);
+    loop {
+        ticker.tick().await;
+        // `spin` finishes the spinner at the end of each poll, which would
+        // keep it hidden from then on
+        spinner.reset();
+        let res = spin(spinner, query(server_str, options, spinner)).await;
+        let now = SystemTime::now();
+
+        let mut events = Vec::new();
+        match (&res, &prev) {
+            (Ok(status), None) if format == OutputFormat::Text => {
+                print_status(status, format, icons)?
+            }
+            (Ok(status), prev) => events = changes(prev.as_ref(), status),
+            (Err(e), _) => events.push(WatchEvent::Error {
+                error: format!("{e:#}"),
+                error_kind: ErrorKind::of(e),
+            }),
+        }
+
+        for event in events {
+            match format {
+                OutputFormat::Text => {
+                    let time = format_time_of_day(now);
+                    match event {
+                        WatchEvent::Joined { player } => println!("[{time}] {player} joined"),
+                        WatchEvent::Left { player } => println!("[{time}] {player} left"),
+                        WatchEvent::Count { online, max } => {
+                            let delta = match &prev {
+                                Some(prev) => {
+                                    format!(" ({:+})", i64::from(online) - i64::from(prev.online))
+                                }
+                                None => String::new(),
+                            };
+                            println!("[{time}] {online}/{max} online{delta}")
+                        }
+                        WatchEvent::Error { error, .. } => println!("[{time}] error: {error}"),
+                    }
                 }
-                x => x?,
-            };
+                OutputFormat::Json => print_json(&TimedEvent {
+                    timestamp: unix_timestamp(now),
+                    event,
+                })?,
+            }
+        }
 
-            let choice = dat.servers.into_iter().nth(selection).unwrap().ip;
-            anyhow::Ok(choice)
-        })
-        .await??
+        // keep the last good status across errors, so that a blip doesn't
+        // make everyone "join" again once the server is back
+        if let Ok(status) = res {
+            prev = Some(status);
+        }
+    }
+}
+
+/// The players who joined or left and the change in the player count since
+/// the `prev` poll, or everyone who's online if this is the first one.
+fn changes<'a>(prev: Option<&'a ServerStatus>, status: &'a ServerStatus) -> Vec<WatchEvent<'a>> {
+    let mut events = Vec::new();
+    let was_online = |name: &str| {
+        prev.iter()
+            .flat_map(|prev| &prev.players)
+            .any(|player| player.name == name)
+    };
+    for player in &status.players {
+        if !was_online(&player.name) {
+            events.push(WatchEvent::Joined {
+                player: &player.name,
+            });
+        }
+    }
+    for player in prev.iter().flat_map(|prev| &prev.players) {
+        if !status.players.iter().any(|p| p.name == player.name) {
+            events.push(WatchEvent::Left {
+                player: &player.name,
+            });
+        }
+    }
+    if prev.map(|prev| (prev.online, prev.max)) != Some((status.online, status.max)) {
+        events.push(WatchEvent::Count {
+            online: status.online,
+            max: status.max,
+        });
+    }
+    events
+}
+
+/// Notes how the servers were doing, for the picker to show next time.
+/// That's only a convenience, so failing to is fine.
+fn remember<'a>(seen: impl IntoIterator<Item = (&'a str, Option<&'a ServerStatus>)>) {
+    let mut state = State::load();
+    let now = unix_timestamp(SystemTime::now());
+    for (addr, status) in seen {
+        state.record(addr, status, now);
+    }
+    let _ = state.save();
+}
+
+fn unix_timestamp(time: SystemTime) -> u64 {
+    time.duration_since(SystemTime::UNIX_EPOCH)
+        .unwrap_or_default()
+        .as_secs()
+}
+
+/// Formats the time of day in UTC as `HH:MM:SS`.
+fn format_time_of_day(time: SystemTime) -> String {
+    let secs = unix_timestamp(time) % 86400;
+    format!("{:02}:{:02}:{:02}", secs / 3600, secs / 60 % 60, secs % 60)
+}
+
+/// servers.dat couldn't be found or read.
+#[derive(Debug, thiserror::Error)]
+#[error("{0}")]
+struct ServersDatError(String);
+
+/// Finds and reads the servers.dat to use, asking which instance it should
+/// come from when there's more than one candidate. This blocks on the prompt,
+/// so it needs to run outside the async runtime.
+fn load_servers_dat(
+    instance: Option<PathBuf>,
+    servers_file: Option<PathBuf>,
+    term: &console::Term,
+) -> anyhow::Result<ServersDat> {
+    let path = servers_dat_path(instance, servers_file, term)?;
+    let dat = ServersDat::from_path(&path)
+        .map_err(|e| ServersDatError(format!("Couldn't read {}: {e}", path.display())))?;
+    Ok(dat)
+}
+
+fn servers_dat_path(
+    instance: Option<PathBuf>,
+    servers_file: Option<PathBuf>,
+    term: &console::Term,
+) -> anyhow::Result<PathBuf> {
+    if let Some(path) = servers_file {
+        return Ok(path);
+    }
+    let game_dir = match instance {
+        Some(instance) if instance.is_dir() => instance,
+        // a path that isn't valid UTF-8 can't be an instance's name
+        Some(instance) => match instance.to_str() {
+            Some(name) => {
+                let instances = discover_instances();
+                let found = find_instance(&instances, name).ok_or_else(|| {
+                    ServersDatError(format!(
+                        "No instance folder or launcher instance named {name:?}, \
+                         see --list-instances"
+                    ))
+                })?;
+                found.game_dir.clone()
+            }
+            None => {
+                let msg = format!("No instance folder at {}", instance.display());
+                return Err(ServersDatError(msg).into());
+            }
+        },
+        None => choose_instance(term)?,
     };
+    Ok(game_dir.join("servers.dat"))
+}
 
-    let (ip, port) = match server_str.split_once(':') {
-        Some((ip, port)) => {
-            let port = port
-                .parse::<u16>()
-                .context("Could not parse port as integer")?;
-            (ip, Some(port))
+fn choose_instance(term: &console::Term) -> anyhow::Result<PathBuf> {
+    let mut instances = discover_instances();
+    // no point offering instances that have never joined a server
+    instances.retain(|instance| instance.servers_dat().is_file());
+    if instances.len() <= 1 || !term.is_term() {
+        let instance = instances.into_iter().next().ok_or_else(|| {
+            ServersDatError(
+                "Couldn't find a .minecraft directory or launcher instance with saved \
+                 servers, please pass the path explicitly."
+                    .to_owned(),
+            )
+        })?;
+        return Ok(instance.game_dir);
+    }
+
+    let theme = ColorfulTheme::default();
+    let selection = interact(
+        Select::with_theme(&theme)
+            .with_prompt("Which instance?")
+            .items(&instances)
+            .default(0)
+            .interact_on(term),
+    )?;
+    Ok(instances.swap_remove(selection).game_dir)
+}
+
+/// Whether a positional argument is a server address, rather than part of a
+/// server's name.
+fn looks_like_address(s: &str) -> bool {
+    s.contains(['.', ':', '[']) || s == "localhost"
+}
+
+/// Picks a server from servers.dat, narrowed down to the ones that match
+/// `query` if there is one; a server with exactly that name is picked
+/// without asking. With a query, finding no matches (or no servers.dat,
+/// unless one was asked for) gives `None`. This blocks on the prompt, so it
+/// needs to run outside the async runtime.
+fn pick_server(
+    instance: Option<PathBuf>,
+    servers_file: Option<PathBuf>,
+    query: Option<&str>,
+    icons: IconMode,
+    term: &console::Term,
+) -> anyhow::Result<Option<(String, Option<String>)>> {
+    let explicit = instance.is_some() || servers_file.is_some();
+    let mut servers = match load_servers_dat(instance, servers_file, term) {
+        Ok(dat) => dat.servers,
+        Err(e) if query.is_some() && !explicit && ErrorKind::of(&e) == ErrorKind::ServersDat => {
+            return Ok(None)
         }
-        None => (&*server_str, None),
+        Err(e) => return Err(e),
     };
+    if servers.is_empty() && query.is_none() {
+        return Err(ServersDatError("There are no servers in servers.dat".to_owned()).into());
+    }
 
-    let mut ping_conf = async_minecraft_ping::ConnectionConfig::build(ip).with_timeout(timeout);
-    if let Some(port) = port {
-        ping_conf = ping_conf.with_port(port);
+    let mut state = State::load();
+    let items = server_items(&mut servers, &state, icons.resolve(term));
+    let found = picker::matches(&items, query.unwrap_or(""));
+    let exact = query.and_then(|query| named(&servers, query));
+    let selection = match (query, exact) {
+        (Some(_), _) if found.is_empty() => return Ok(None),
+        (_, Some(exact)) => exact,
+        (query, None) => {
+            let theme = ColorfulTheme::default();
+            let query = query.unwrap_or("");
+            picker::fuzzy_select(&theme, "Which server?", &items, query, term)?
+        }
+    };
+
+    let choice = servers.swap_remove(selection);
+    state.last_server = Some(choice.ip.clone());
+    // remembering the choice is only a convenience, so failing to is fine
+    let _ = state.save();
+    Ok(Some((choice.ip, choice.icon)))
+}
+
+/// The server called exactly `name`, which is safe to pick without asking.
+/// Even a lone fuzzy match isn't, as it could be some other server than the
+/// one meant.
+fn named(servers: &[Server], name: &str) -> Option<usize> {
+    servers.iter().position(|server| server.name == name)
+}
+
+/// Picks any number of servers from servers.dat, offering only the ones
+/// that match `query` if there is one. This blocks on the prompt, so it
+/// needs to run outside the async runtime.
+fn pick_servers(
+    instance: Option<PathBuf>,
+    servers_file: Option<PathBuf>,
+    query: Option<&str>,
+    icons: IconMode,
+    term: &console::Term,
+) -> anyhow::Result<Vec<Server>> {
+    let mut servers = load_servers_dat(instance, servers_file, term)?.servers;
+    let items = server_items(&mut servers, &State::load(), icons.resolve(term));
+    let found = picker::matches(&items, query.unwrap_or(""));
+    if found.is_empty() {
+        let msg = match query {
+            Some(query) => format!("No servers in servers.dat match {query:?}"),
+            None => "There are no servers in servers.dat".to_owned(),
+        };
+        return Err(ServersDatError(msg).into());
     }
 
-    let spinner = &indicatif::ProgressBar::new_spinner();
-    spinner.set_draw_target(indicatif::ProgressDrawTarget::term(term.clone(), 15));
+    let labels = found
+        .iter()
+        .map(|&i| format!("{}{}", items[i].label, items[i].hint))
+        .collect_vec();
+    let theme = ColorfulTheme::default();
+    let picked = interact(
+        MultiSelect::with_theme(&theme)
+            .with_prompt("Which servers? (space to tick, enter when done)")
+            .items(&labels)
+            .interact_on(term),
+    )?;
+    if picked.is_empty() {
+        anyhow::bail!("No servers were ticked");
+    }
+    Ok(picked
+        .into_iter()
+        .map(|n| servers[found[n]].clone())
+        .collect())
+}
 
-    let (online, max, players) = spin(&spinner, async move {
-        spinner.set_message("Connecting...");
-        let conn = ping_conf.connect().await?;
-        spinner.set_message("Fetching status...");
-        let conn = conn.status().await?;
+/// Makes the picker items for `servers`, after moving the server picked
+/// last time to the top so that it's the default, as it's likely to be
+/// picked again. Servers queried before are hinted with how they were.
+fn server_items(
+    servers: &mut [Server],
+    state: &State,
+    icons: Option<IconMode>,
+) -> Vec<picker::Item> {
+    if let Some(last) = servers
+        .iter()
+        .position(|server| state.last_server.as_ref() == Some(&server.ip))
+    {
+        servers[..=last].rotate_right(1);
+    }
 
-        let players = &conn.status.players;
-        let (online, max) = (players.online, players.max);
-        let players = players
-            .sample
-            .as_deref()
-            .filter(|v| !v.is_empty())
-            .map(|players| players.iter().map(|player| &*player.name).join(" "));
+    // like the in-game server list, show each server's icon (as far as
+    // that's possible in one line)
+    let now = unix_timestamp(SystemTime::now());
+    servers
+        .iter()
+        .map(|server| picker::Item {
+            label: match (icons, server.icon()) {
+                (Some(_), Some(icon)) => format!("{} {server}", graphics::thumbnail(&icon)),
+                (Some(_), None) => format!("     {server}"),
+                (None, _) => server.to_string(),
+            },
+            hint: match state.last_seen.get(&server.ip) {
+                Some(seen) => console::style(format!("  {}", describe_seen(seen, now)))
+                    .dim()
+                    .to_string(),
+                None => String::new(),
+            },
+            keys: vec![server.name.clone(), server.ip.clone()],
+        })
+        .collect()
+}
 
-        spinner.set_message("Pinging...");
-        conn.ping(0x8008135).await?;
+/// Describes how a server was when it was last queried, like
+/// `3/20 online, 2h ago`.
+fn describe_seen(seen: &LastSeen, now: u64) -> String {
+    let ago = match now.saturating_sub(seen.timestamp) {
+        secs @ 0..=59 => format!("{secs}s ago"),
+        secs @ 60..=3599 => format!("{}m ago", secs / 60),
+        secs @ 3600..=86399 => format!("{}h ago", secs / 3600),
+        secs => format!("{}d ago", secs / 86400),
+    };
+    match seen.up {
+        true => format!("{}/{} online, {ago}", seen.online, seen.max),
+        false => format!("down, {ago}"),
+    }
+}
+
+/// Turns ctrl-c during a prompt into [`CtrlC`].
+fn interact<T>(res: io::Result<T>) -> anyhow::Result<T> {
+    match res {
+        Err(e) if e.kind() == io::ErrorKind::Interrupted => anyhow::bail!(CtrlC),
+        res => Ok(res?),
+    }
+}
 
-        anyhow::Ok((online, max, players))
+/// How many servers `--all` pings at the same time.
+const MAX_CONCURRENT_QUERIES: usize = 16;
+
+async fn query_all(servers: Vec<Server>, options: &QueryOptions) -> Vec<ServerResult> {
+    let semaphore = Arc::new(Semaphore::new(MAX_CONCURRENT_QUERIES));
+    let tasks = servers
+        .into_iter()
+        .map(|server| {
+            let semaphore = semaphore.clone();
+            let options = options.clone();
+            tokio::spawn(async move {
+                let _permit = semaphore.acquire_owned().await.unwrap();
+                let hidden = indicatif::ProgressBar::hidden();
+                let res = query(&server.ip, &options, &hidden).await;
+                let (status, error, error_kind) = match res {
+                    Ok(status) => (Some(status), None, None),
+                    Err(e) => (None, Some(format!("{e:#}")), Some(ErrorKind::of(&e))),
+                };
+                ServerResult {
+                    name: server.name,
+                    ip: server.ip,
+                    status,
+                    error,
+                    error_kind,
+                }
+            })
+        })
+        .collect_vec();
+
+    let mut results = Vec::with_capacity(tasks.len());
+    for task in tasks {
+        results.push(task.await.unwrap());
+    }
+    results
+}
+
+async fn query(
+    server_str: &str,
+    options: &QueryOptions,
+    spinner: &indicatif::ProgressBar,
+) -> anyhow::Result<ServerStatus> {
+    let addr = server_str.parse::<ServerAddress>()?;
+    warn_if_srv_skipped(&addr, options, spinner);
+    let status = query_status_with_progress(&addr, options, |phase| match phase {
+        Phase::Resolving => spinner.set_message("Resolving..."),
+        Phase::Connecting => spinner.set_message("Connecting..."),
+        Phase::FetchingStatus => spinner.set_message("Fetching status..."),
+        Phase::Querying => spinner.set_message("Querying players..."),
+        Phase::Pinging { total: 1, .. } => spinner.set_message("Pinging..."),
+        Phase::Pinging { n, total } => spinner.set_message(format!("Pinging ({n}/{total})...")),
+        Phase::Retrying { n, total } => spinner.set_message(format!("Retrying ({n}/{total})...")),
     })
     .await?;
+    Ok(status)
+}
 
-    println!(
-        "{online}/{max} online{}",
-        if players.is_some() { ":" } else { "" }
-    );
-    if let Some(players) = players {
-        let options = textwrap::Options::new(60)
-            .initial_indent("    ")
-            .subsequent_indent("    ");
-        for line in textwrap::wrap(&players, options) {
-            println!("{line}");
+/// Warns, once, when the SRV lookup for `addr` has to be skipped for lack of
+/// a DNS server, as a server that relies on one then gets queried on the
+/// wrong port.
+fn warn_if_srv_skipped(
+    addr: &ServerAddress,
+    options: &QueryOptions,
+    spinner: &indicatif::ProgressBar,
+) {
+    static WARNED: Once = Once::new();
+    if addr.uses_srv()
+        && options.protocol != Protocol::Bedrock
+        && options.dns_server.is_none()
+        && Resolver::system().is_none()
+    {
+        WARNED.call_once(|| {
+            spinner.suspend(|| {
+                eprintln!(
+                    "Warning: couldn't find the system's DNS server, so SRV records won't be \
+                     looked up; pass --dns-server to look them up"
+                )
+            })
+        });
+    }
+}
+
+fn print_results(results: &[ServerResult], format: OutputFormat) -> anyhow::Result<()> {
+    match format {
+        OutputFormat::Text => {
+            let rows = results
+                .iter()
+                .map(|res| {
+                    let (online, latency) = match &res.status {
+                        Some(status) => (
+                            format!("{}/{}", status.online, status.max),
+                            format!("{}ms", Millis(status.latency.avg_ms)),
+                        ),
+                        None => ("-".to_owned(), "-".to_owned()),
+                    };
+                    let error = res.error.clone().unwrap_or_default();
+                    [res.name.clone(), res.ip.clone(), online, latency, error]
+                })
+                .collect_vec();
+            print_table(["NAME", "IP", "ONLINE", "LATENCY", "ERROR"], &rows);
+        }
+        OutputFormat::Json => print_json(results)?,
+    }
+    Ok(())
+}
+
+/// One step of `--diagnose`, for JSON output.
+#[derive(Serialize)]
+struct StepOutput {
+    step: StepKind,
+    duration_ms: f64,
+    detail: String,
+    error: Option<String>,
+    error_kind: Option<ErrorKind>,
+}
+
+/// Prints how each step of `--diagnose` went. A failed step exits with the
+/// code for its error, like it would without `--diagnose`.
+fn print_diagnosis(steps: Vec<Step>, format: OutputFormat) -> anyhow::Result<ExitCode> {
+    let mut code = ExitCode::SUCCESS;
+    let steps = steps
+        .into_iter()
+        .map(|step| {
+            let error = step.error.map(anyhow::Error::from);
+            if let Some(e) = &error {
+                code = ExitCode::from(ErrorKind::of(e).exit_code());
+            }
+            StepOutput {
+                step: step.kind,
+                duration_ms: step.duration.as_secs_f64() * 1000.0,
+                detail: step.detail,
+                error_kind: error.as_ref().map(ErrorKind::of),
+                error: error.map(|e| format!("{e:#}")),
+            }
+        })
+        .collect_vec();
+
+    match format {
+        OutputFormat::Text => {
+            let rows = steps
+                .iter()
+                .map(|step| {
+                    let (result, detail) = match &step.error {
+                        Some(error) => (
+                            console::style("FAILED").red().bold().to_string(),
+                            console::style(error).red().to_string(),
+                        ),
+                        None => (
+                            console::style("ok").green().to_string(),
+                            step.detail.clone(),
+                        ),
+                    };
+                    [
+                        step.step.to_string(),
+                        result,
+                        format!("{}ms", Millis(step.duration_ms)),
+                        detail,
+                    ]
+                })
+                .collect_vec();
+            print_table(["STEP", "RESULT", "TIME", "DETAIL"], &rows);
         }
+        OutputFormat::Json => print_json(&steps)?,
     }
+    Ok(code)
+}
 
+fn print_instances(instances: &[Instance], format: OutputFormat) -> anyhow::Result<()> {
+    match format {
+        OutputFormat::Text => {
+            let rows = instances
+                .iter()
+                .map(|instance| {
+                    [
+                        instance.name.clone(),
+                        instance.launcher.to_string(),
+                        instance.game_dir.display().to_string(),
+                    ]
+                })
+                .collect_vec();
+            print_table(["NAME", "LAUNCHER", "PATH"], &rows);
+        }
+        OutputFormat::Json => print_json(instances)?,
+    }
     Ok(())
 }
 
+/// Prints rows as left-aligned columns under a header.
+fn print_table<const N: usize>(header: [&str; N], rows: &[[String; N]]) {
+    for line in format_table(header, rows) {
+        println!("{line}");
+    }
+}
+
+/// Lays out rows as left-aligned columns under a header, returning the
+/// header line followed by a line for each row.
+fn format_table<const N: usize>(header: [&str; N], rows: &[[String; N]]) -> Vec<String> {
+    let header = header.map(String::from);
+    let mut widths = header.clone().map(|col| console::measure_text_width(&col));
+    for row in rows {
+        for (width, col) in widths.iter_mut().zip(row) {
+            *width = (*width).max(console::measure_text_width(col));
+        }
+    }
+    std::iter::once(&header)
+        .chain(rows)
+        .map(|row| {
+            let line = row
+                .iter()
+                .zip(widths)
+                .map(|(col, width)| console::pad_str(col, width, console::Alignment::Left, None))
+                .join("  ");
+            line.trim_end().to_owned()
+        })
+        .collect()
+}
+
+/// Writes the icon for `--save-icon`: the `data:` URL the server sent, or
+/// else the base64 PNG cached in servers.dat. It's written even if it isn't
+/// a PNG we can decode, since other programs may still open it.
+fn save_icon(path: &Path, favicon: Option<&str>, cached: Option<&str>) -> anyhow::Result<()> {
+    let png = match (favicon, cached) {
+        (Some(url), _) => {
+            icon::decode_data_url(url).context("The server's icon isn't valid base64")?
+        }
+        (None, Some(cached)) => {
+            base64::decode(cached).context("The icon in servers.dat isn't valid base64")?
+        }
+        (None, None) => anyhow::bail!("The server didn't send an icon"),
+    };
+    std::fs::write(path, png)
+        .with_context(|| format!("Couldn't write the icon to {}", path.display()))
+}
+
 async fn spin<T, F: Future<Output = T>>(spinner: &indicatif::ProgressBar, fut: F) -> T {
     let mut int = tokio::time::interval(Duration::from_millis(100));
     tokio::pin!(fut);
@@ -206,3 +1260,264 @@ async fn spin<T, F: Future<Output = T>>(spinner: &indicatif::ProgressBar, fut: F
         }
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use mcserverstatus::motd::Text;
+    use mcserverstatus::{Player, Version};
+
+    use super::*;
+
+    #[tokio::test]
+    async fn query_all_keeps_the_servers_in_order() {
+        // a port that nothing is listening on, so connecting fails quickly
+        let closed = std::net::TcpListener::bind("127.0.0.1:0")
+            .unwrap()
+            .local_addr()
+            .unwrap();
+        let servers = (0..20)
+            .map(|i| Server {
+                ip: closed.to_string(),
+                name: format!("server {i}"),
+                icon: None,
+            })
+            .collect();
+        let options = QueryOptions {
+            connect_timeout: Duration::from_secs(5),
+            ..QueryOptions::default()
+        };
+        let results = query_all(servers, &options).await;
+        assert_eq!(results.len(), 20);
+        for (i, res) in results.iter().enumerate() {
+            assert_eq!(res.name, format!("server {i}"));
+            assert!(res.status.is_none());
+            assert!(res.error.is_some());
+        }
+    }
+
+    #[test]
+    fn displays_latency() {
+        let latency = Latency::from_samples(&[10, 30, 20, 20].map(Duration::from_millis));
+        assert_eq!(
+            DisplayLatency(&latency).to_string(),
+            "10/20/30ms min/avg/max, 10ms jitter over 4 pings"
+        );
+        let latency = Latency::from_samples(&[Duration::from_micros(42400)]);
+        assert_eq!(DisplayLatency(&latency).to_string(), "42ms");
+    }
+
+    #[test]
+    fn formats_milliseconds() {
+        for (ms, shown) in [(0.25, "0.2"), (9.94, "9.9"), (10.0, "10"), (123.6, "124")] {
+            assert_eq!(Millis(ms).to_string(), shown);
+        }
+    }
+
+    fn status(online: u32, max: u32, players: &[&str]) -> ServerStatus {
+        ServerStatus {
+            online,
+            max,
+            players: players
+                .iter()
+                .map(|&name| Player {
+                    name: name.to_owned(),
+                    id: None,
+                })
+                .collect(),
+            version: Version {
+                name: "1.19.2".to_owned(),
+                protocol: 760,
+            },
+            motd: Text::default(),
+            favicon: None,
+            icon: None,
+            latency: Latency::from_samples(&[Duration::from_millis(1)]),
+            bedrock: None,
+            query: None,
+        }
+    }
+
+    fn events(events: Vec<WatchEvent>) -> serde_json::Value {
+        serde_json::to_value(events).unwrap()
+    }
+
+    #[test]
+    fn reports_joins_leaves_and_counts() {
+        let first = status(2, 20, &["Alice", "Bob"]);
+        assert_eq!(
+            events(changes(None, &first)),
+            serde_json::json!([
+                {"event": "joined", "player": "Alice"},
+                {"event": "joined", "player": "Bob"},
+                {"event": "count", "online": 2, "max": 20},
+            ])
+        );
+
+        let second = status(2, 20, &["Bob", "Carol"]);
+        assert_eq!(
+            events(changes(Some(&first), &second)),
+            serde_json::json!([
+                {"event": "joined", "player": "Carol"},
+                {"event": "left", "player": "Alice"},
+            ])
+        );
+
+        // the count can change without the sample doing so on big servers
+        let third = status(40, 20, &["Bob", "Carol"]);
+        assert_eq!(
+            events(changes(Some(&second), &third)),
+            serde_json::json!([{"event": "count", "online": 40, "max": 20}])
+        );
+        assert_eq!(events(changes(Some(&third), &third)), serde_json::json!([]));
+    }
+
+    #[test]
+    fn timestamps_events() {
+        let time = SystemTime::UNIX_EPOCH + Duration::from_secs(86400 * 365 + 3723);
+        assert_eq!(format_time_of_day(time), "01:02:03");
+        assert_eq!(format_time_of_day(SystemTime::UNIX_EPOCH), "00:00:00");
+        let event = TimedEvent {
+            timestamp: unix_timestamp(time),
+            event: WatchEvent::Error {
+                error: "timed out".to_owned(),
+                error_kind: ErrorKind::Timeout,
+            },
+        };
+        assert_eq!(
+            serde_json::to_value(event).unwrap(),
+            serde_json::json!({
+                "timestamp": 31539723,
+                "event": "error",
+                "error": "timed out",
+                "error_kind": "timeout",
+            })
+        );
+    }
+
+    #[test]
+    fn saves_the_icon() {
+        let path = std::env::temp_dir().join(format!("icon-{}.png", std::process::id()));
+        let saved = |favicon, cached| {
+            save_icon(&path, favicon, cached)?;
+            let png = std::fs::read(&path)?;
+            std::fs::remove_file(&path)?;
+            anyhow::Ok(png)
+        };
+        // "\x89PNG..." in base64, which isn't a PNG that can be decoded
+        let url = "data:image/png;base64,iVBORy4uLg==";
+        assert_eq!(saved(Some(url), None).unwrap(), b"\x89PNG...");
+        assert_eq!(saved(Some(url), Some("b3RoZXI=")).unwrap(), b"\x89PNG...");
+        assert_eq!(saved(None, Some("b3RoZXI=")).unwrap(), b"other");
+
+        let e = saved(None, None).unwrap_err();
+        assert_eq!(e.to_string(), "The server didn't send an icon");
+        let e = saved(Some("data:image/png;base64,!!"), None).unwrap_err();
+        assert_eq!(e.to_string(), "The server's icon isn't valid base64");
+        assert!(!path.exists());
+        let e = save_icon(Path::new("/nonexistent/icon.png"), Some(url), None).unwrap_err();
+        assert_eq!(
+            e.to_string(),
+            "Couldn't write the icon to /nonexistent/icon.png"
+        );
+    }
+
+    #[test]
+    fn parses_seconds() {
+        assert_eq!(parse_secs("2"), Ok(Duration::from_secs(2)));
+        assert_eq!(parse_secs("0.25"), Ok(Duration::from_millis(250)));
+        for bad in ["0", "-1", "soon", "inf", "NaN", "", "1e30"] {
+            assert_eq!(
+                parse_secs(bad),
+                Err(format!(
+                    "{bad:?} is not a positive number of seconds, or is too large"
+                ))
+            );
+        }
+    }
+
+    #[test]
+    fn puts_the_last_server_first() {
+        let mut servers = ["One", "Two", "Three"]
+            .map(|name| Server {
+                ip: format!("{}.example.net", name.to_lowercase()),
+                name: name.to_owned(),
+                icon: None,
+            })
+            .to_vec();
+        let mut state = State {
+            last_server: Some("three.example.net".to_owned()),
+            ..State::default()
+        };
+        state.record("one.example.net", None, 1000);
+        let items = server_items(&mut servers, &state, None);
+        let names = servers.iter().map(|server| &server.name[..]).collect_vec();
+        assert_eq!(names, ["Three", "One", "Two"]);
+        assert_eq!(items[0].label, "Three (ip: three.example.net)");
+        assert_eq!(items[0].keys, ["Three", "three.example.net"]);
+        assert_eq!(items[0].hint, "");
+        assert!(console::strip_ansi_codes(&items[1].hint).starts_with("  down, "));
+        let items = server_items(&mut servers, &State::default(), Some(IconMode::Blocks));
+        assert_eq!(items[2].label, "     Two (ip: two.example.net)");
+    }
+
+    #[test]
+    fn describes_how_servers_were_last_seen() {
+        let mut state = State::default();
+        state.record("up.example.net", Some(&status(3, 20, &[])), 10_000);
+        state.record("down.example.net", None, 10_000);
+        let up = &state.last_seen["up.example.net"];
+        let down = &state.last_seen["down.example.net"];
+        assert_eq!(describe_seen(up, 10_000), "3/20 online, 0s ago");
+        assert_eq!(describe_seen(up, 10_059), "3/20 online, 59s ago");
+        assert_eq!(describe_seen(up, 10_060), "3/20 online, 1m ago");
+        assert_eq!(describe_seen(up, 13_599), "3/20 online, 59m ago");
+        assert_eq!(describe_seen(up, 13_600), "3/20 online, 1h ago");
+        assert_eq!(describe_seen(down, 96_399), "down, 23h ago");
+        assert_eq!(describe_seen(down, 96_400), "down, 1d ago");
+        // a clock that went backwards
+        assert_eq!(describe_seen(down, 5_000), "down, 0s ago");
+    }
+
+    #[test]
+    fn picks_only_exact_names_without_asking() {
+        let servers = ["Survival", "Survival 2", "Creative"]
+            .map(|name| Server {
+                ip: "mc.example.net".to_owned(),
+                name: name.to_owned(),
+                icon: None,
+            })
+            .to_vec();
+        assert_eq!(named(&servers, "Survival"), Some(0));
+        assert_eq!(named(&servers, "Creative"), Some(2));
+        for query in ["Surv", "survival", "Creat", "mc.example.net", ""] {
+            assert_eq!(named(&servers, query), None, "{query}");
+        }
+    }
+
+    #[test]
+    fn tells_addresses_from_names() {
+        for addr in [
+            "mc.example.net",
+            "localhost",
+            "127.0.0.1:25565",
+            "[::1]",
+            "::1",
+        ] {
+            assert!(looks_like_address(addr), "{addr}");
+        }
+        for name in ["Survival", "my server", "hypixel"] {
+            assert!(!looks_like_address(name), "{name}");
+        }
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn never_looks_up_non_utf8_instances_by_name() {
+        use std::os::unix::ffi::OsStrExt;
+
+        let instance = PathBuf::from(std::ffi::OsStr::from_bytes(b"modded-\xff"));
+        let e = servers_dat_path(Some(instance), None, &console::Term::stderr()).unwrap_err();
+        assert_eq!(ErrorKind::of(&e), ErrorKind::ServersDat);
+        assert_eq!(e.to_string(), "No instance folder at modded-\u{fffd}");
+    }
+}
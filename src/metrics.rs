@@ -0,0 +1,501 @@
+//! The `serve-metrics` subcommand: a Prometheus exporter. `/metrics` reports
+//! on a fixed set of servers, and `/probe?target=...` on whichever server
+//! Prometheus asks for, like blackbox_exporter's multi-target pattern.
+//!
+//! There's just enough HTTP here for Prometheus and a curious browser:
+//! `GET` requests, one per connection.
+
+use std::fmt::Write as _;
+use std::net::SocketAddr;
+use std::sync::Arc;
+use std::time::{Duration, Instant};
+
+use anyhow::Context;
+use itertools::Itertools;
+use mcserverstatus::{QueryOptions, ServerStatus};
+use tokio::io::{AsyncReadExt, AsyncWriteExt};
+use tokio::net::{TcpListener, TcpStream};
+
+use crate::config::Config;
+use crate::query;
+
+/// Requests are a line and a few headers; anything longer isn't Prometheus.
+const MAX_REQUEST_LEN: usize = 8 * 1024;
+
+/// How long a client gets to send its request.
+const REQUEST_TIMEOUT: Duration = Duration::from_secs(10);
+
+#[derive(clap::Args)]
+pub struct ServeArgs {
+    /// Servers (or aliases) to report on at /metrics [default: every alias
+    /// in the config file]
+    #[clap(value_name = "ALIAS|SERVER")]
+    targets: Vec<String>,
+
+    /// The address to serve metrics on
+    #[clap(long, value_name = "ADDR", default_value = "127.0.0.1:9565")]
+    listen: SocketAddr,
+}
+
+struct Exporter {
+    /// The servers for `/metrics`, as the name to label them with and the
+    /// address to query.
+    targets: Vec<(String, String)>,
+    config: Config,
+    options: QueryOptions,
+}
+
+pub async fn serve(args: &ServeArgs, config: Config, options: QueryOptions) -> anyhow::Result<()> {
+    let targets = if args.targets.is_empty() {
+        config
+            .aliases
+            .iter()
+            .map(|(name, addr)| (name.clone(), addr.clone()))
+            .collect()
+    } else {
+        args.targets
+            .iter()
+            .map(|target| (target.clone(), config.resolve_alias(target).to_owned()))
+            .collect()
+    };
+    let exporter = Arc::new(Exporter {
+        targets,
+        config,
+        options,
+    });
+
+    let listener = TcpListener::bind(args.listen)
+        .await
+        .with_context(|| format!("Couldn't listen on {}", args.listen))?;
+    eprintln!("Serving metrics on http://{}/metrics", args.listen);
+    loop {
+        let (stream, _) = listener.accept().await?;
+        let exporter = exporter.clone();
+        tokio::spawn(async move {
+            // there's nobody to tell about a client hanging up on us
+            let _ = exporter.handle(stream).await;
+        });
+    }
+}
+
+impl Exporter {
+    async fn handle(&self, mut stream: TcpStream) -> anyhow::Result<()> {
+        let request = tokio::time::timeout(REQUEST_TIMEOUT, read_request(&mut stream)).await??;
+        let response = match request.split_ascii_whitespace().collect_tuple() {
+            Some(("GET", target, _version)) => self.route(target).await,
+            Some(_) => Response::error(405, "Method Not Allowed"),
+            None => Response::error(400, "Bad Request"),
+        };
+        stream.write_all(&response.into_bytes()).await?;
+        stream.shutdown().await?;
+        Ok(())
+    }
+
+    async fn route(&self, target: &str) -> Response {
+        let (path, query) = target.split_once('?').unwrap_or((target, ""));
+        match path {
+            "/" => Response::ok(
+                "text/html; charset=utf-8",
+                "<h1>mcserverstatus exporter</h1>\n\
+                 <p><a href=\"/metrics\">Metrics</a></p>\n"
+                    .to_owned(),
+            ),
+            "/metrics" => {
+                let tasks = self
+                    .targets
+                    .iter()
+                    .cloned()
+                    .map(|(name, addr)| {
+                        let options = self.options.clone();
+                        tokio::spawn(async move { (name, probe(&addr, &options).await) })
+                    })
+                    .collect_vec();
+                let mut probes = Vec::with_capacity(tasks.len());
+                for task in tasks {
+                    probes.push(task.await.unwrap());
+                }
+                Response::metrics(render(&probes))
+            }
+            "/probe" => {
+                let target = query.split('&').find_map(|param| {
+                    let (key, value) = param.split_once('=')?;
+                    (key == "target").then(|| percent_decode(value))
+                });
+                match target {
+                    Some(target) if !target.is_empty() => {
+                        let addr = self.config.resolve_alias(&target);
+                        let probe = probe(addr, &self.options).await;
+                        // the target label comes from Prometheus' relabeling
+                        Response::metrics(render(&[(String::new(), probe)]))
+                    }
+                    _ => Response {
+                        body: "Missing ?target= parameter\n".to_owned(),
+                        ..Response::error(400, "Bad Request")
+                    },
+                }
+            }
+            _ => Response::error(404, "Not Found"),
+        }
+    }
+}
+
+struct Probe {
+    status: Option<ServerStatus>,
+    duration: Duration,
+}
+
+async fn probe(addr: &str, options: &QueryOptions) -> Probe {
+    let start = Instant::now();
+    let hidden = indicatif::ProgressBar::hidden();
+    let status = query(addr, options, &hidden).await.ok();
+    Probe {
+        status,
+        duration: start.elapsed(),
+    }
+}
+
+/// Renders the probes in the Prometheus text format. An empty name leaves
+/// out the `target` label.
+fn render(probes: &[(String, Probe)]) -> String {
+    type Sample = fn(&Probe) -> Option<(Vec<(&'static str, String)>, f64)>;
+    let families: [(&str, &str, Sample); 6] = [
+        ("minecraft_up", "Whether the server answered", |p| {
+            Some((vec![], if p.status.is_some() { 1.0 } else { 0.0 }))
+        }),
+        ("minecraft_players_online", "Players online", |p| {
+            let status = p.status.as_ref()?;
+            Some((vec![], f64::from(status.online)))
+        }),
+        ("minecraft_players_max", "Player slots", |p| {
+            let status = p.status.as_ref()?;
+            Some((vec![], f64::from(status.max)))
+        }),
+        (
+            "minecraft_latency_seconds",
+            "Average round-trip time of the pings",
+            |p| {
+                let status = p.status.as_ref()?;
+                Some((vec![], status.latency.avg_ms / 1000.0))
+            },
+        ),
+        (
+            "minecraft_protocol_version",
+            "Protocol version the server speaks, with its version name",
+            |p| {
+                let status = p.status.as_ref()?;
+                let labels = vec![("version", status.version.name.clone())];
+                Some((labels, f64::from(status.version.protocol)))
+            },
+        ),
+        (
+            "minecraft_scrape_duration_seconds",
+            "How long querying the server took",
+            |p| Some((vec![], p.duration.as_secs_f64())),
+        ),
+    ];
+
+    let mut out = String::new();
+    for (name, help, sample) in families {
+        writeln!(out, "# HELP {name} {help}").unwrap();
+        writeln!(out, "# TYPE {name} gauge").unwrap();
+        for (target, probe) in probes {
+            let Some((mut labels, value)) = sample(probe) else {
+                continue;
+            };
+            if !target.is_empty() {
+                labels.insert(0, ("target", target.clone()));
+            }
+            let labels = labels
+                .iter()
+                .map(|(key, value)| format!("{key}=\"{}\"", escape_label(value)))
+                .join(",");
+            if labels.is_empty() {
+                writeln!(out, "{name} {value}").unwrap();
+            } else {
+                writeln!(out, "{name}{{{labels}}} {value}").unwrap();
+            }
+        }
+    }
+    out
+}
+
+fn escape_label(value: &str) -> String {
+    value
+        .replace('\\', "\\\\")
+        .replace('"', "\\\"")
+        .replace('\n', "\\n")
+}
+
+/// Decodes `%XX` escapes and `+` in a query string value.
+fn percent_decode(s: &str) -> String {
+    let mut out = Vec::with_capacity(s.len());
+    let mut bytes = s.bytes();
+    while let Some(b) = bytes.next() {
+        match b {
+            b'+' => out.push(b' '),
+            b'%' => {
+                let hex = [bytes.next(), bytes.next()];
+                let decoded = match hex {
+                    [Some(hi), Some(lo)] => std::str::from_utf8(&[hi, lo])
+                        .ok()
+                        .and_then(|hex| u8::from_str_radix(hex, 16).ok()),
+                    _ => None,
+                };
+                // leave malformed escapes as they were
+                match decoded {
+                    Some(byte) => out.push(byte),
+                    None => {
+                        out.push(b'%');
+                        out.extend(hex.into_iter().flatten());
+                    }
+                }
+            }
+            b => out.push(b),
+        }
+    }
+    String::from_utf8_lossy(&out).into_owned()
+}
+
+/// Reads up to the end of the request headers, returning the request line.
+async fn read_request(stream: &mut TcpStream) -> anyhow::Result<String> {
+    let mut buf = Vec::new();
+    let mut chunk = [0; 1024];
+    while !buf.windows(4).any(|w| w == b"\r\n\r\n") {
+        let n = stream.read(&mut chunk).await?;
+        if n == 0 {
+            anyhow::bail!("connection closed mid-request");
+        }
+        buf.extend_from_slice(&chunk[..n]);
+        if buf.len() > MAX_REQUEST_LEN {
+            anyhow::bail!("request is too long");
+        }
+    }
+    let line = buf.split(|&b| b == b'\n').next().unwrap_or_default();
+    Ok(String::from_utf8_lossy(line).trim_end().to_owned())
+}
+
+struct Response {
+    status: u16,
+    reason: &'static str,
+    content_type: &'static str,
+    body: String,
+}
+
+impl Response {
+    fn ok(content_type: &'static str, body: String) -> Self {
+        Response {
+            status: 200,
+            reason: "OK",
+            content_type,
+            body,
+        }
+    }
+
+    fn metrics(body: String) -> Self {
+        Response::ok("text/plain; version=0.0.4; charset=utf-8", body)
+    }
+
+    fn error(status: u16, reason: &'static str) -> Self {
+        Response {
+            status,
+            reason,
+            content_type: "text/plain; charset=utf-8",
+            body: format!("{reason}\n"),
+        }
+    }
+
+    fn into_bytes(self) -> Vec<u8> {
+        let mut out = format!(
+            "HTTP/1.1 {} {}\r\n\
+             Content-Type: {}\r\n\
+             Content-Length: {}\r\n\
+             Connection: close\r\n\r\n",
+            self.status,
+            self.reason,
+            self.content_type,
+            self.body.len()
+        )
+        .into_bytes();
+        out.extend_from_slice(self.body.as_bytes());
+        out
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use mcserverstatus::motd::Text;
+    use mcserverstatus::{Latency, Version};
+
+    use super::*;
+
+    fn exporter() -> Exporter {
+        Exporter {
+            targets: Vec::new(),
+            config: Config::default(),
+            options: QueryOptions::default(),
+        }
+    }
+
+    fn up() -> Probe {
+        Probe {
+            status: Some(ServerStatus {
+                online: 3,
+                max: 20,
+                players: Vec::new(),
+                version: Version {
+                    name: "Paper \"1.20.1\"".to_owned(),
+                    protocol: 763,
+                },
+                motd: Text::default(),
+                favicon: None,
+                icon: None,
+                latency: Latency::from_samples(&[Duration::from_millis(25)]),
+                bedrock: None,
+                query: None,
+            }),
+            duration: Duration::from_millis(40),
+        }
+    }
+
+    fn down() -> Probe {
+        Probe {
+            status: None,
+            duration: Duration::from_millis(2000),
+        }
+    }
+
+    #[test]
+    fn renders_up_and_down_servers() {
+        let probes = [
+            ("survival".to_owned(), up()),
+            ("creative".to_owned(), down()),
+        ];
+        assert_eq!(
+            render(&probes),
+            r#"# HELP minecraft_up Whether the server answered
+# TYPE minecraft_up gauge
+minecraft_up{target="survival"} 1
+minecraft_up{target="creative"} 0
+# HELP minecraft_players_online Players online
+# TYPE minecraft_players_online gauge
+minecraft_players_online{target="survival"} 3
+# HELP minecraft_players_max Player slots
+# TYPE minecraft_players_max gauge
+minecraft_players_max{target="survival"} 20
+# HELP minecraft_latency_seconds Average round-trip time of the pings
+# TYPE minecraft_latency_seconds gauge
+minecraft_latency_seconds{target="survival"} 0.025
+# HELP minecraft_protocol_version Protocol version the server speaks, with its version name
+# TYPE minecraft_protocol_version gauge
+minecraft_protocol_version{target="survival",version="Paper \"1.20.1\""} 763
+# HELP minecraft_scrape_duration_seconds How long querying the server took
+# TYPE minecraft_scrape_duration_seconds gauge
+minecraft_scrape_duration_seconds{target="survival"} 0.04
+minecraft_scrape_duration_seconds{target="creative"} 2
+"#
+        );
+    }
+
+    #[test]
+    fn renders_a_probe_without_the_target_label() {
+        let out = render(&[(String::new(), down())]);
+        assert!(out.contains("\nminecraft_up 0\n"), "{out}");
+        assert!(
+            out.contains("\nminecraft_scrape_duration_seconds 2\n"),
+            "{out}"
+        );
+        assert!(!out.contains("\nminecraft_players_online "), "{out}");
+    }
+
+    #[test]
+    fn escapes_label_values() {
+        assert_eq!(escape_label("plain"), "plain");
+        assert_eq!(escape_label(r#"say "hi""#), r#"say \"hi\""#);
+        assert_eq!(escape_label(r"C:\server"), r"C:\\server");
+        assert_eq!(escape_label("two\nlines"), r"two\nlines");
+        assert_eq!(escape_label("\\\"\n"), r#"\\\"\n"#);
+    }
+
+    #[test]
+    fn decodes_query_values() {
+        for (encoded, decoded) in [
+            ("mc.example.net%3A25566", "mc.example.net:25566"),
+            ("%5B%3A%3A1%5d", "[::1]"),
+            ("my+server", "my server"),
+            ("%C3%A9", "é"),
+            ("%2B", "+"),
+            // malformed escapes are left as they were
+            ("100%", "100%"),
+            ("%4", "%4"),
+            ("%zz", "%zz"),
+            ("%g1x", "%g1x"),
+            ("%%41", "%%41"),
+            ("%FF", "\u{fffd}"),
+        ] {
+            assert_eq!(percent_decode(encoded), decoded, "{encoded}");
+        }
+    }
+
+    #[tokio::test]
+    async fn probe_needs_a_target() {
+        for target in ["/probe", "/probe?target=", "/probe?module=minecraft"] {
+            let response = exporter().route(target).await;
+            assert_eq!(response.status, 400, "{target}");
+            assert_eq!(response.body, "Missing ?target= parameter\n");
+        }
+    }
+
+    #[tokio::test]
+    async fn routes_requests() {
+        let exporter = exporter();
+        assert_eq!(exporter.route("/").await.status, 200);
+        assert_eq!(exporter.route("/nope").await.status, 404);
+
+        // no targets is still a valid scrape
+        let response = exporter.route("/metrics").await;
+        assert_eq!(response.status, 200);
+        assert_eq!(
+            response.content_type,
+            "text/plain; version=0.0.4; charset=utf-8"
+        );
+        assert!(response.body.starts_with("# HELP minecraft_up "));
+    }
+
+    #[tokio::test]
+    async fn answers_over_http() {
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let addr = listener.local_addr().unwrap();
+        tokio::spawn(async move {
+            let exporter = exporter();
+            for _ in 0..2 {
+                let (stream, _) = listener.accept().await.unwrap();
+                exporter.handle(stream).await.unwrap();
+            }
+        });
+
+        for (request, expected) in [
+            (
+                "GET /probe HTTP/1.1\r\nHost: localhost\r\n\r\n",
+                "HTTP/1.1 400 Bad Request\r\n\
+                 Content-Type: text/plain; charset=utf-8\r\n\
+                 Content-Length: 27\r\n\
+                 Connection: close\r\n\r\n\
+                 Missing ?target= parameter\n",
+            ),
+            (
+                "POST /metrics HTTP/1.1\r\n\r\n",
+                "HTTP/1.1 405 Method Not Allowed\r\n\
+                 Content-Type: text/plain; charset=utf-8\r\n\
+                 Content-Length: 19\r\n\
+                 Connection: close\r\n\r\n\
+                 Method Not Allowed\n",
+            ),
+        ] {
+            let mut stream = TcpStream::connect(addr).await.unwrap();
+            stream.write_all(request.as_bytes()).await.unwrap();
+            let mut response = String::new();
+            stream.read_to_string(&mut response).await.unwrap();
+            assert_eq!(response, expected);
+        }
+    }
+}
@@ -0,0 +1,421 @@
+//! Parsing and rendering of Minecraft's formatted text, as used in server
+//! MOTDs: `§` formatting codes in plain strings and JSON chat components.
+
+use std::fmt;
+
+use serde::{Serialize, Serializer};
+use serde_json::Value;
+
+/// A run of text that shares the same formatting.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Span {
+    pub text: String,
+    pub style: TextStyle,
+}
+
+#[derive(Debug, Clone, Copy, Default, PartialEq)]
+pub struct TextStyle {
+    pub color: Option<Rgb>,
+    pub bold: bool,
+    pub italic: bool,
+    pub underlined: bool,
+    pub strikethrough: bool,
+    pub obfuscated: bool,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Rgb(pub u8, pub u8, pub u8);
+
+/// The 16 named colors, in `§0`..=`§f` order.
+const NAMED_COLORS: [(&str, Rgb); 16] = [
+    ("black", Rgb(0x00, 0x00, 0x00)),
+    ("dark_blue", Rgb(0x00, 0x00, 0xaa)),
+    ("dark_green", Rgb(0x00, 0xaa, 0x00)),
+    ("dark_aqua", Rgb(0x00, 0xaa, 0xaa)),
+    ("dark_red", Rgb(0xaa, 0x00, 0x00)),
+    ("dark_purple", Rgb(0xaa, 0x00, 0xaa)),
+    ("gold", Rgb(0xff, 0xaa, 0x00)),
+    ("gray", Rgb(0xaa, 0xaa, 0xaa)),
+    ("dark_gray", Rgb(0x55, 0x55, 0x55)),
+    ("blue", Rgb(0x55, 0x55, 0xff)),
+    ("green", Rgb(0x55, 0xff, 0x55)),
+    ("aqua", Rgb(0x55, 0xff, 0xff)),
+    ("red", Rgb(0xff, 0x55, 0x55)),
+    ("light_purple", Rgb(0xff, 0x55, 0xff)),
+    ("yellow", Rgb(0xff, 0xff, 0x55)),
+    ("white", Rgb(0xff, 0xff, 0xff)),
+];
+
+impl Rgb {
+    /// Parses a color as it appears in a chat component's `color` field:
+    /// either one of the named colors or `#rrggbb`.
+    fn parse(s: &str) -> Option<Self> {
+        if let Some(hex) = s.strip_prefix('#') {
+            // from_str_radix would also take a sign
+            if hex.len() != 6 || !hex.bytes().all(|b| b.is_ascii_hexdigit()) {
+                return None;
+            }
+            let rgb = u32::from_str_radix(hex, 16).ok()?;
+            let [_, r, g, b] = rgb.to_be_bytes();
+            return Some(Rgb(r, g, b));
+        }
+        NAMED_COLORS
+            .iter()
+            .find(|(name, _)| *name == s)
+            .map(|&(_, rgb)| rgb)
+    }
+
+    /// The closest color in the xterm 256-color palette.
+    pub fn to_ansi256(self) -> u8 {
+        const LEVELS: [u8; 6] = [0, 95, 135, 175, 215, 255];
+        let nearest_level = |c: u8| {
+            (0..6)
+                .min_by_key(|&i| (i32::from(LEVELS[i]) - i32::from(c)).abs())
+                .unwrap()
+        };
+        let dist = |Rgb(r, g, b): Rgb| {
+            let d = |a: u8, b: u8| (i32::from(a) - i32::from(b)).pow(2);
+            d(r, self.0) + d(g, self.1) + d(b, self.2)
+        };
+
+        let (r, g, b) = (
+            nearest_level(self.0),
+            nearest_level(self.1),
+            nearest_level(self.2),
+        );
+        let cube = Rgb(LEVELS[r], LEVELS[g], LEVELS[b]);
+        let cube_index = 16 + 36 * r + 6 * g + b;
+
+        let avg = (u32::from(self.0) + u32::from(self.1) + u32::from(self.2)) / 3;
+        let gray_step = (avg.saturating_sub(3) / 10).min(23) as u8;
+        let gray_level = 8 + 10 * gray_step;
+        let gray = Rgb(gray_level, gray_level, gray_level);
+
+        if dist(gray) < dist(cube) {
+            232 + gray_step
+        } else {
+            cube_index as u8
+        }
+    }
+}
+
+/// Formatted text, as a flat list of styled spans.
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct Text(pub Vec<Span>);
+
+impl Text {
+    /// Parses a server description, which is either a plain string
+    /// (possibly containing `§` codes) or a JSON chat component.
+    pub fn from_json(value: &Value) -> Self {
+        let mut text = Text::default();
+        text.push_component(value, TextStyle::default());
+        text
+    }
+
+    /// Parses a string containing `§` formatting codes.
+    pub fn from_legacy(s: &str) -> Self {
+        let mut text = Text::default();
+        text.push_legacy(s, TextStyle::default());
+        text
+    }
+
+    fn push_component(&mut self, value: &Value, parent: TextStyle) {
+        match value {
+            Value::String(s) => self.push_legacy(s, parent),
+            // the first element of an array is the parent of the rest
+            Value::Array(parts) => {
+                if let Some((first, rest)) = parts.split_first() {
+                    let style = component_style(first, parent);
+                    self.push_component(first, parent);
+                    for part in rest {
+                        self.push_component(part, style);
+                    }
+                }
+            }
+            Value::Object(obj) => {
+                let style = component_style(value, parent);
+                if let Some(Value::String(s)) = obj.get("text") {
+                    self.push_legacy(s, style);
+                }
+                if let Some(Value::Array(extra)) = obj.get("extra") {
+                    for part in extra {
+                        self.push_component(part, style);
+                    }
+                }
+            }
+            Value::Number(n) => self.push_legacy(&n.to_string(), parent),
+            Value::Bool(b) => self.push_legacy(&b.to_string(), parent),
+            Value::Null => {}
+        }
+    }
+
+    fn push_legacy(&mut self, s: &str, base: TextStyle) {
+        let mut style = base;
+        let mut chars = s.chars();
+        let mut run = String::new();
+        while let Some(c) = chars.next() {
+            if c != '§' {
+                run.push(c);
+                continue;
+            }
+            let Some(code) = chars.next() else { break };
+            self.push_span(std::mem::take(&mut run), style);
+            let code = code.to_ascii_lowercase();
+            match code {
+                '0'..='9' | 'a'..='f' => {
+                    // a color code also resets any formatting before it
+                    let index = code.to_digit(16).unwrap() as usize;
+                    style = TextStyle {
+                        color: Some(NAMED_COLORS[index].1),
+                        ..TextStyle::default()
+                    };
+                }
+                'k' => style.obfuscated = true,
+                'l' => style.bold = true,
+                'm' => style.strikethrough = true,
+                'n' => style.underlined = true,
+                'o' => style.italic = true,
+                'r' => style = base,
+                _ => {}
+            }
+        }
+        self.push_span(run, style);
+    }
+
+    fn push_span(&mut self, text: String, style: TextStyle) {
+        if text.is_empty() {
+            return;
+        }
+        match self.0.last_mut() {
+            Some(last) if last.style == style => last.text.push_str(&text),
+            _ => self.0.push(Span { text, style }),
+        }
+    }
+
+    /// The text with all formatting removed.
+    pub fn plain(&self) -> String {
+        self.0.iter().map(|span| &*span.text).collect()
+    }
+
+    /// Renders the text with terminal colors. `console` drops the styling
+    /// by itself when colors are disabled.
+    pub fn styled(&self) -> Styled<'_> {
+        Styled(self)
+    }
+}
+
+/// Serializes as the plain text, for machine-readable output.
+impl Serialize for Text {
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        serializer.serialize_str(&self.plain())
+    }
+}
+
+fn component_style(value: &Value, parent: TextStyle) -> TextStyle {
+    let mut style = parent;
+    let Value::Object(obj) = value else {
+        return style;
+    };
+    if let Some(color) = obj.get("color").and_then(Value::as_str) {
+        if color == "reset" {
+            style.color = None;
+        } else if let Some(rgb) = Rgb::parse(color) {
+            style.color = Some(rgb);
+        }
+    }
+    let flag =
+        |name: &str, current: bool| obj.get(name).and_then(Value::as_bool).unwrap_or(current);
+    style.bold = flag("bold", style.bold);
+    style.italic = flag("italic", style.italic);
+    style.underlined = flag("underlined", style.underlined);
+    style.strikethrough = flag("strikethrough", style.strikethrough);
+    style.obfuscated = flag("obfuscated", style.obfuscated);
+    style
+}
+
+pub struct Styled<'a>(&'a Text);
+
+impl fmt::Display for Styled<'_> {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        for span in &self.0 .0 {
+            let mut style = console::Style::new();
+            if let Some(color) = span.style.color {
+                style = style.color256(color.to_ansi256());
+            }
+            if span.style.bold {
+                style = style.bold();
+            }
+            if span.style.italic {
+                style = style.italic();
+            }
+            if span.style.underlined {
+                style = style.underlined();
+            }
+            if span.style.obfuscated {
+                style = style.blink();
+            }
+            write!(f, "{}", style.apply_to(&span.text))?;
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use serde_json::json;
+
+    use super::*;
+
+    fn span(text: &str, style: TextStyle) -> Span {
+        Span {
+            text: text.to_owned(),
+            style,
+        }
+    }
+
+    fn color(name: &str) -> TextStyle {
+        TextStyle {
+            color: Rgb::parse(name),
+            ..TextStyle::default()
+        }
+    }
+
+    #[test]
+    fn parses_formatting_codes() {
+        let text = Text::from_json(&json!("§aGreen §lbold§r plain §Lbold§4red"));
+        let bold = TextStyle {
+            bold: true,
+            ..TextStyle::default()
+        };
+        assert_eq!(
+            text.0,
+            [
+                span("Green ", color("green")),
+                span(
+                    "bold",
+                    TextStyle {
+                        bold: true,
+                        ..color("green")
+                    }
+                ),
+                span(" plain ", TextStyle::default()),
+                span("bold", bold),
+                // a color code resets the formatting
+                span("red", color("dark_red")),
+            ]
+        );
+        assert_eq!(text.plain(), "Green bold plain boldred");
+    }
+
+    #[test]
+    fn parses_legacy_strings() {
+        let text = Text::from_legacy("A §6Minecraft§r Server");
+        assert_eq!(
+            text.0,
+            [
+                span("A ", TextStyle::default()),
+                span("Minecraft", color("gold")),
+                span(" Server", TextStyle::default()),
+            ]
+        );
+        // a plain string component is the same thing
+        assert_eq!(Text::from_json(&json!("A §6Minecraft§r Server")), text);
+    }
+
+    #[test]
+    fn ignores_unknown_and_dangling_codes() {
+        let text = Text::from_json(&json!("a§zb§"));
+        assert_eq!(text.0, [span("ab", TextStyle::default())]);
+        assert_eq!(Text::from_json(&json!("")).0, []);
+        assert_eq!(Text::from_json(&json!(null)).0, []);
+    }
+
+    #[test]
+    fn parses_chat_components() {
+        let text = Text::from_json(&json!({
+            "text": "",
+            "extra": [
+                {
+                    "text": "Hello ",
+                    "color": "gold",
+                    "bold": true,
+                    "extra": [
+                        {"text": "hex", "color": "#12aB34"},
+                        {"text": " not bold", "bold": false},
+                        {"text": " bad color", "color": "#12345"},
+                        {"text": " reset", "color": "reset"},
+                    ],
+                },
+                " and §ba string",
+                ["", {"text": " array", "italic": true}, 7],
+            ],
+        }));
+        let gold_bold = TextStyle {
+            bold: true,
+            ..color("gold")
+        };
+        assert_eq!(
+            text.0,
+            [
+                span("Hello ", gold_bold),
+                span(
+                    "hex",
+                    TextStyle {
+                        color: Some(Rgb(0x12, 0xab, 0x34)),
+                        ..gold_bold
+                    }
+                ),
+                span(" not bold", color("gold")),
+                span(" bad color", gold_bold),
+                span(
+                    " reset",
+                    TextStyle {
+                        bold: true,
+                        ..TextStyle::default()
+                    }
+                ),
+                span(" and ", TextStyle::default()),
+                span("a string", color("aqua")),
+                span(
+                    " array",
+                    TextStyle {
+                        italic: true,
+                        ..TextStyle::default()
+                    }
+                ),
+                span("7", TextStyle::default()),
+            ]
+        );
+        assert_eq!(
+            text.plain(),
+            "Hello hex not bold bad color reset and a string array7"
+        );
+        assert_eq!(serde_json::to_value(&text).unwrap(), json!(text.plain()));
+    }
+
+    #[test]
+    fn parses_colors() {
+        assert_eq!(Rgb::parse("dark_purple"), Some(Rgb(0xaa, 0x00, 0xaa)));
+        assert_eq!(Rgb::parse("#FFaa00"), Some(Rgb(0xff, 0xaa, 0x00)));
+        for bad in [
+            "purple", "#fff", "#1234567", "#ggggggg", "ffaa00", "#+12345",
+        ] {
+            assert_eq!(Rgb::parse(bad), None, "{bad}");
+        }
+    }
+
+    #[test]
+    fn maps_colors_to_the_256_color_palette() {
+        for (rgb, ansi) in [
+            (Rgb(0x00, 0x00, 0x00), 16),
+            (Rgb(0xff, 0xff, 0xff), 231),
+            (Rgb(0xff, 0x55, 0x55), 203),
+            (Rgb(0x00, 0x00, 0xaa), 19),
+            // grays are closer to the gray ramp than to the color cube
+            (Rgb(0x55, 0x55, 0x55), 240),
+            (Rgb(0xaa, 0xaa, 0xaa), 248),
+        ] {
+            assert_eq!(rgb.to_ansi256(), ansi, "{rgb:?}");
+        }
+    }
+}
@@ -0,0 +1,99 @@
+//! Helpers for resolving hosts and opening connections with a timeout, and
+//! other bits of networking shared between the protocols.
+
+use std::future::Future;
+use std::io;
+use std::net::SocketAddr;
+use std::time::Duration;
+
+use tokio::net::{TcpStream, UdpSocket};
+
+use crate::{AddressFamily, Error, Result};
+
+/// Looks up the addresses for `host`, keeping only the ones in `family`.
+pub(crate) async fn resolve(
+    host: &str,
+    port: u16,
+    family: AddressFamily,
+) -> Result<Vec<SocketAddr>> {
+    let addrs = tokio::net::lookup_host((host, port))
+        .await
+        .map_err(Error::Resolve)?
+        .filter(|addr| match family {
+            AddressFamily::Any => true,
+            AddressFamily::V4 => addr.is_ipv4(),
+            AddressFamily::V6 => addr.is_ipv6(),
+        })
+        .collect::<Vec<_>>();
+    if addrs.is_empty() {
+        let msg = match family {
+            AddressFamily::Any => "no addresses found",
+            AddressFamily::V4 => "no IPv4 addresses found",
+            AddressFamily::V6 => "no IPv6 addresses found",
+        };
+        return Err(Error::Resolve(io::Error::new(io::ErrorKind::NotFound, msg)));
+    }
+    Ok(addrs)
+}
+
+pub(crate) async fn connect_tcp(addr: SocketAddr, timeout: Duration) -> Result<TcpStream> {
+    let stream = tokio::time::timeout(timeout, TcpStream::connect(addr))
+        .await
+        .unwrap_or_else(|_| Err(io::ErrorKind::TimedOut.into()))
+        .map_err(Error::Connect)?;
+    stream.set_nodelay(true)?;
+    Ok(stream)
+}
+
+/// Returns a UDP socket connected to `addr`.
+pub(crate) async fn connect_udp(addr: SocketAddr) -> Result<UdpSocket> {
+    let bind_addr: SocketAddr = match addr {
+        SocketAddr::V4(_) => ([0, 0, 0, 0], 0).into(),
+        SocketAddr::V6(_) => ([0u16; 8], 0).into(),
+    };
+    let socket = UdpSocket::bind(bind_addr).await?;
+    socket.connect(addr).await.map_err(Error::Connect)?;
+    Ok(socket)
+}
+
+pub(crate) async fn with_timeout<T>(
+    timeout: Duration,
+    fut: impl Future<Output = Result<T>>,
+) -> Result<T> {
+    tokio::time::timeout(timeout, fut)
+        .await
+        .unwrap_or(Err(Error::Timeout))
+}
+
+/// A random number that's good enough for ping tokens and client GUIDs,
+/// without pulling in a random number generator for it.
+pub(crate) fn random_u64() -> u64 {
+    use std::collections::hash_map::RandomState;
+    use std::hash::{BuildHasher, Hasher};
+    RandomState::new().build_hasher().finish()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[tokio::test]
+    async fn resolves_only_the_requested_family() {
+        let addrs = resolve("127.0.0.1", 25565, AddressFamily::Any)
+            .await
+            .unwrap();
+        assert_eq!(addrs, [SocketAddr::from(([127, 0, 0, 1], 25565))]);
+        let addrs = resolve("127.0.0.1", 1, AddressFamily::V4).await.unwrap();
+        assert_eq!(addrs, [SocketAddr::from(([127, 0, 0, 1], 1))]);
+
+        let err = resolve("127.0.0.1", 1, AddressFamily::V6)
+            .await
+            .unwrap_err();
+        assert_eq!(
+            std::error::Error::source(&err).unwrap().to_string(),
+            "no IPv6 addresses found"
+        );
+        let err = resolve("::1", 1, AddressFamily::V4).await.unwrap_err();
+        assert!(matches!(err, Error::Resolve(_)), "{err:?}");
+    }
+}
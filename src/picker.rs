@@ -0,0 +1,226 @@
+//! A select prompt that narrows the list down as you type, for servers.dat
+//! files with more servers than fit on the screen. It looks like dialoguer's
+//! prompts, since it borrows their theme.
+
+use std::io;
+
+use console::{Key, Term};
+use dialoguer::theme::Theme;
+
+use crate::CtrlC;
+
+/// Something to pick: how it's shown, and what the query is matched against.
+pub struct Item {
+    pub label: String,
+    /// Shown after the label in the list, but not once the item is picked.
+    pub hint: String,
+    pub keys: Vec<String>,
+}
+
+/// Scores how well `query` matches `haystack`, or returns `None` if it
+/// doesn't match at all. The query's characters have to appear in order,
+/// ignoring case; runs of consecutive characters and characters at the
+/// start of a word score higher, and skipping over characters scores lower.
+pub fn fuzzy_score(query: &str, haystack: &str) -> Option<i32> {
+    let haystack = haystack.chars().collect::<Vec<_>>();
+    let mut score = 0;
+    let mut pos = 0;
+    let mut prev = None;
+    for q in query.chars() {
+        let i = (pos..haystack.len()).find(|&i| eq_ignore_case(haystack[i], q))?;
+        score += 1;
+        if prev.is_some_and(|prev| prev + 1 == i) {
+            score += 4;
+        }
+        if i == 0 || !haystack[i - 1].is_alphanumeric() {
+            score += 3;
+        }
+        score -= (i - pos).min(3) as i32;
+        prev = Some(i);
+        pos = i + 1;
+    }
+    Some(score)
+}
+
+fn eq_ignore_case(a: char, b: char) -> bool {
+    a == b || a.to_lowercase().eq(b.to_lowercase())
+}
+
+/// The indexes of the items that match `query`, best match first. Items
+/// that match equally well keep their order.
+pub fn matches(items: &[Item], query: &str) -> Vec<usize> {
+    let mut scored = items
+        .iter()
+        .enumerate()
+        .filter_map(|(i, item)| {
+            let score = item
+                .keys
+                .iter()
+                .filter_map(|key| fuzzy_score(query, key))
+                .max()?;
+            Some((i, score))
+        })
+        .collect::<Vec<_>>();
+    scored.sort_by_key(|&(_, score)| -score);
+    scored.into_iter().map(|(i, _)| i).collect()
+}
+
+/// Asks which item to pick, starting with `query` already typed, and
+/// returns its index. Ctrl-c turns into [`CtrlC`].
+pub fn fuzzy_select(
+    theme: &dyn Theme,
+    prompt: &str,
+    items: &[Item],
+    query: &str,
+    term: &Term,
+) -> anyhow::Result<usize> {
+    if !term.is_term() {
+        anyhow::bail!("Can't ask which server without a terminal, please pass one with --server");
+    }
+    let mut query = query.to_owned();
+    let mut selected = 0;
+    let mut scroll = 0;
+    let mut drawn = 0;
+
+    term.hide_cursor()?;
+    loop {
+        let found = matches(items, &query);
+        selected = selected.min(found.len().saturating_sub(1));
+
+        let (height, width) = term.size();
+        // leave room for the prompt, and a line so the terminal doesn't
+        // scroll
+        let capacity = usize::from(height).saturating_sub(2).max(1);
+        if selected < scroll {
+            scroll = selected;
+        } else if selected >= scroll + capacity {
+            scroll = selected + 1 - capacity;
+        }
+
+        let mut lines = Vec::new();
+        let mut line = String::new();
+        theme.format_input_prompt(&mut line, prompt, None)?;
+        line.push_str(&query);
+        lines.push(line);
+        for (n, &i) in found.iter().enumerate().skip(scroll).take(capacity) {
+            let mut line = String::new();
+            let text = format!("{}{}", items[i].label, items[i].hint);
+            theme.format_select_prompt_item(&mut line, &text, n == selected)?;
+            lines.push(line);
+        }
+        if found.is_empty() {
+            lines.push(console::style("  No matches").dim().to_string());
+        }
+
+        term.clear_last_lines(drawn)?;
+        for line in &lines {
+            // a line that wraps would throw off clearing it next time
+            term.write_line(&console::truncate_str(
+                line,
+                usize::from(width).saturating_sub(1),
+                "…",
+            ))?;
+        }
+        term.flush()?;
+        drawn = lines.len();
+
+        let key = match term.read_key() {
+            Err(e) if e.kind() == io::ErrorKind::Interrupted => anyhow::bail!(CtrlC),
+            key => key?,
+        };
+        match key {
+            Key::Enter if !found.is_empty() => {
+                term.clear_last_lines(drawn)?;
+                term.show_cursor()?;
+                let choice = found[selected];
+                let mut line = String::new();
+                theme.format_select_prompt_selection(&mut line, prompt, &items[choice].label)?;
+                term.write_line(&line)?;
+                return Ok(choice);
+            }
+            Key::ArrowUp | Key::BackTab => selected = selected.saturating_sub(1),
+            Key::ArrowDown | Key::Tab => selected += 1,
+            Key::PageUp => selected = selected.saturating_sub(capacity),
+            Key::PageDown => selected += capacity,
+            Key::Backspace => {
+                query.pop();
+                selected = 0;
+            }
+            Key::Escape => {
+                query.clear();
+                selected = 0;
+            }
+            Key::Char(c) if !c.is_control() => {
+                query.push(c);
+                selected = 0;
+            }
+            _ => {}
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn items(names: &[&str]) -> Vec<Item> {
+        names
+            .iter()
+            .map(|&name| Item {
+                label: name.to_owned(),
+                hint: String::new(),
+                keys: vec![name.to_owned()],
+            })
+            .collect()
+    }
+
+    #[test]
+    fn scores_fuzzy_matches() {
+        assert_eq!(fuzzy_score("", "anything"), Some(0));
+        assert_eq!(fuzzy_score("x", ""), None);
+        assert_eq!(fuzzy_score("hyp", "Hypixel"), Some(14));
+        assert_eq!(fuzzy_score("HYP", "hypixel"), Some(14));
+        // out of order
+        assert_eq!(fuzzy_score("yh", "Hypixel"), None);
+        assert_eq!(fuzzy_score("hypixels", "Hypixel"), None);
+
+        // consecutive characters beat scattered ones
+        assert!(fuzzy_score("pix", "Hypixel") > fuzzy_score("pxl", "Hypixel"));
+        // the start of a word beats the middle of one
+        assert!(fuzzy_score("s", "My Server") > fuzzy_score("s", "Mansion"));
+        assert!(fuzzy_score("ms", "mc.my-server.net") > fuzzy_score("ms", "mc.mansion.net"));
+        // skipping further costs more, up to a point
+        assert!(fuzzy_score("e", "Hye") > fuzzy_score("e", "Hypixe"));
+        assert_eq!(fuzzy_score("e", "Hypixe"), fuzzy_score("e", "Hypixxxe"));
+    }
+
+    #[test]
+    fn orders_matches_best_first() {
+        let items = items(&["Mansion", "Survival", "My Server", "Creative", "Server 2"]);
+        // "Server 2" starts with it, "My Server" has it further in
+        assert_eq!(matches(&items, "serv"), [4, 2]);
+        assert_eq!(matches(&items, "srv"), [1, 4, 2]);
+        assert_eq!(matches(&items, "zzz"), [] as [usize; 0]);
+        // equally good matches keep their order
+        assert_eq!(matches(&items, ""), [0, 1, 2, 3, 4]);
+        assert_eq!(matches(&items, "v"), [1, 2, 3, 4]);
+    }
+
+    #[test]
+    fn matches_any_key() {
+        let items = vec![
+            Item {
+                label: "Home".to_owned(),
+                hint: String::new(),
+                keys: vec!["Home".to_owned(), "mc.example.net".to_owned()],
+            },
+            Item {
+                label: "Example".to_owned(),
+                hint: String::new(),
+                keys: vec!["Example".to_owned(), "192.168.1.20".to_owned()],
+            },
+        ];
+        assert_eq!(matches(&items, "example"), [1, 0]);
+        assert_eq!(matches(&items, "192"), [1]);
+    }
+}
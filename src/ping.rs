@@ -0,0 +1,389 @@
+//! A client for the [Server List Ping](https://wiki.vg/Server_List_Ping)
+//! protocol that modern (1.7+) Java Edition servers speak.
+
+use std::net::SocketAddr;
+use std::time::Duration;
+
+use serde::Deserialize;
+use tokio::io::{AsyncReadExt, AsyncWriteExt};
+use tokio::net::TcpStream;
+
+use crate::net::{connect_tcp, with_timeout};
+use crate::{Error, Result};
+
+/// The protocol version we claim to speak in the handshake. Servers answer
+/// status requests regardless, but some proxies pick the version they
+/// report based on it.
+const PROTOCOL_VERSION: i32 = 760;
+
+/// Upper bound on the size of a packet we're willing to read. Status
+/// responses carry the favicon and, on modded servers, the mod list, so
+/// this is quite generous.
+const MAX_PACKET_LEN: usize = 8 * 1024 * 1024;
+
+/// The JSON body of a status response.
+#[derive(Debug, Deserialize)]
+pub struct StatusResponse {
+    pub version: StatusVersion,
+    pub players: StatusPlayers,
+    /// The MOTD, either a plain string or a JSON chat component.
+    #[serde(default)]
+    pub description: serde_json::Value,
+    /// A `data:image/png;base64,...` URL for the server's icon.
+    pub favicon: Option<String>,
+}
+
+#[derive(Debug, Deserialize)]
+pub struct StatusVersion {
+    pub name: String,
+    pub protocol: i32,
+}
+
+#[derive(Debug, Deserialize)]
+pub struct StatusPlayers {
+    pub max: u32,
+    pub online: u32,
+    pub sample: Option<Vec<StatusPlayer>>,
+}
+
+#[derive(Debug, Deserialize)]
+pub struct StatusPlayer {
+    pub name: String,
+    pub id: String,
+}
+
+/// A connection to a server in the status state.
+pub struct Connection {
+    stream: TcpStream,
+    host: String,
+    port: u16,
+    timeout: Duration,
+    handshake_sent: bool,
+}
+
+impl Connection {
+    /// Opens a TCP connection to the server at `addr`. `host` is what gets
+    /// sent in the handshake, so it should be the name the user typed rather
+    /// than the resolved address. `timeout` is for connecting, and for each
+    /// read and write unless changed with
+    /// [`set_read_timeout`](Self::set_read_timeout).
+    pub async fn connect(addr: SocketAddr, host: &str, timeout: Duration) -> Result<Self> {
+        let stream = connect_tcp(addr, timeout).await?;
+        Ok(Connection {
+            stream,
+            host: host.to_owned(),
+            port: addr.port(),
+            timeout,
+            handshake_sent: false,
+        })
+    }
+
+    /// Sets how long to wait on each read and write.
+    pub fn set_read_timeout(&mut self, timeout: Duration) {
+        self.timeout = timeout;
+    }
+
+    /// Sends the handshake that switches the connection to the status
+    /// state. [`status`](Self::status) and [`ping`](Self::ping) do this
+    /// themselves if it hasn't been done yet.
+    pub async fn handshake(&mut self) -> Result<()> {
+        if self.handshake_sent {
+            return Ok(());
+        }
+        let mut packet = Vec::new();
+        write_varint(&mut packet, PROTOCOL_VERSION);
+        write_string(&mut packet, &self.host);
+        packet.extend_from_slice(&self.port.to_be_bytes());
+        // next state: status
+        write_varint(&mut packet, 1);
+        self.write_packet(0x00, &packet).await?;
+        self.handshake_sent = true;
+        Ok(())
+    }
+
+    /// Requests the server's status.
+    pub async fn status(&mut self) -> Result<StatusResponse> {
+        let json = self.status_json().await?;
+        Ok(serde_json::from_str(&json)?)
+    }
+
+    /// Requests the server's status, returning the JSON as the server sent
+    /// it.
+    pub async fn status_json(&mut self) -> Result<String> {
+        self.handshake().await?;
+        self.write_packet(0x00, &[]).await?;
+        let body = self.read_packet(0x00).await?;
+        let mut body = &body[..];
+        Ok(read_string(&mut body)?.to_owned())
+    }
+
+    /// Sends a ping and waits for the matching pong. Vanilla servers close
+    /// the connection after answering, so this consumes it.
+    pub async fn ping(mut self, payload: u64) -> Result<()> {
+        self.handshake().await?;
+        self.write_packet(0x01, &payload.to_be_bytes()).await?;
+        let body = self.read_packet(0x01).await?;
+        let actual = body
+            .get(..8)
+            .ok_or(Error::InvalidPacket("pong is too short"))?;
+        let actual = u64::from_be_bytes(actual.try_into().unwrap());
+        if actual != payload {
+            return Err(Error::MismatchedPayload {
+                expected: payload,
+                actual,
+            });
+        }
+        Ok(())
+    }
+
+    async fn write_packet(&mut self, id: i32, data: &[u8]) -> Result<()> {
+        let mut body = Vec::with_capacity(data.len() + 1);
+        write_varint(&mut body, id);
+        body.extend_from_slice(data);
+        let mut packet = Vec::with_capacity(body.len() + 5);
+        write_varint(&mut packet, body.len() as i32);
+        packet.extend_from_slice(&body);
+
+        let stream = &mut self.stream;
+        with_timeout(self.timeout, async {
+            stream.write_all(&packet).await?;
+            Ok(())
+        })
+        .await
+    }
+
+    /// Reads a packet and returns its body after checking its ID.
+    async fn read_packet(&mut self, expected_id: i32) -> Result<Vec<u8>> {
+        let stream = &mut self.stream;
+        let body = with_timeout(self.timeout, async {
+            let len = read_varint_async(stream).await?;
+            let len = usize::try_from(len)
+                .ok()
+                .filter(|&len| len <= MAX_PACKET_LEN)
+                .ok_or(Error::InvalidPacket("bad packet length"))?;
+            let mut body = vec![0; len];
+            stream.read_exact(&mut body).await?;
+            Ok(body)
+        })
+        .await?;
+
+        let mut rest = &body[..];
+        let id = read_varint(&mut rest)?;
+        if id != expected_id {
+            return Err(Error::InvalidPacket("unexpected packet id"));
+        }
+        let offset = body.len() - rest.len();
+        Ok(body[offset..].to_vec())
+    }
+}
+
+fn write_varint(buf: &mut Vec<u8>, value: i32) {
+    let mut value = value as u32;
+    loop {
+        let byte = (value & 0x7f) as u8;
+        value >>= 7;
+        if value == 0 {
+            buf.push(byte);
+            return;
+        }
+        buf.push(byte | 0x80);
+    }
+}
+
+fn write_string(buf: &mut Vec<u8>, s: &str) {
+    write_varint(buf, s.len() as i32);
+    buf.extend_from_slice(s.as_bytes());
+}
+
+fn read_varint(buf: &mut &[u8]) -> Result<i32> {
+    let mut value = 0u32;
+    for i in 0..5 {
+        let (&byte, rest) = buf
+            .split_first()
+            .ok_or(Error::InvalidPacket("truncated varint"))?;
+        *buf = rest;
+        value |= u32::from(byte & 0x7f) << (7 * i);
+        if byte & 0x80 == 0 {
+            return Ok(value as i32);
+        }
+    }
+    Err(Error::InvalidPacket("varint is too long"))
+}
+
+async fn read_varint_async(stream: &mut TcpStream) -> Result<i32> {
+    let mut value = 0u32;
+    for i in 0..5 {
+        let byte = stream.read_u8().await?;
+        value |= u32::from(byte & 0x7f) << (7 * i);
+        if byte & 0x80 == 0 {
+            return Ok(value as i32);
+        }
+    }
+    Err(Error::InvalidPacket("varint is too long"))
+}
+
+fn read_string<'a>(buf: &mut &'a [u8]) -> Result<&'a str> {
+    let len = read_varint(buf)?;
+    let len = usize::try_from(len)
+        .ok()
+        .filter(|&len| len <= buf.len())
+        .ok_or(Error::InvalidPacket("bad string length"))?;
+    let (s, rest) = buf.split_at(len);
+    *buf = rest;
+    std::str::from_utf8(s).map_err(|_| Error::InvalidPacket("string is not valid UTF-8"))
+}
+
+#[cfg(test)]
+mod tests {
+    use tokio::net::TcpListener;
+
+    use super::*;
+
+    /// Varints from the protocol documentation.
+    const VARINTS: [(i32, &[u8]); 10] = [
+        (0, &[0x00]),
+        (1, &[0x01]),
+        (127, &[0x7f]),
+        (128, &[0x80, 0x01]),
+        (255, &[0xff, 0x01]),
+        (25565, &[0xdd, 0xc7, 0x01]),
+        (2097151, &[0xff, 0xff, 0x7f]),
+        (i32::MAX, &[0xff, 0xff, 0xff, 0xff, 0x07]),
+        (-1, &[0xff, 0xff, 0xff, 0xff, 0x0f]),
+        (i32::MIN, &[0x80, 0x80, 0x80, 0x80, 0x08]),
+    ];
+
+    #[test]
+    fn writes_and_reads_varints() {
+        for (value, bytes) in VARINTS {
+            let mut buf = Vec::new();
+            write_varint(&mut buf, value);
+            assert_eq!(buf, bytes, "{value}");
+
+            buf.push(0xaa);
+            let mut rest = &buf[..];
+            assert_eq!(read_varint(&mut rest).unwrap(), value);
+            assert_eq!(rest, [0xaa]);
+        }
+    }
+
+    #[test]
+    fn rejects_bad_varints() {
+        for bytes in [&[][..], &[0x80], &[0xff, 0xff, 0xff, 0xff, 0xff, 0x01]] {
+            assert!(read_varint(&mut &bytes[..]).is_err(), "{bytes:?}");
+        }
+    }
+
+    #[test]
+    fn writes_and_reads_strings() {
+        let mut buf = Vec::new();
+        write_string(&mut buf, "héllo");
+        assert_eq!(buf, b"\x06h\xc3\xa9llo");
+        buf.extend_from_slice(b"rest");
+        let mut rest = &buf[..];
+        assert_eq!(read_string(&mut rest).unwrap(), "héllo");
+        assert_eq!(rest, b"rest");
+
+        for bad in [&b"\x05abc"[..], b"\x02\xff\xfe", b"\xff"] {
+            assert!(read_string(&mut &bad[..]).is_err(), "{bad:?}");
+        }
+    }
+
+    const STATUS: &str = r#"{
+        "version": {"name": "1.19.2", "protocol": 760},
+        "players": {"max": 20, "online": 1, "sample": [{"name": "Alice", "id": "4566e69f-c907-48ee-8d71-d7ba5aa00d20"}]},
+        "description": {"text": "Hello"}
+    }"#;
+
+    /// Reads a packet the way a server would, returning its ID and body.
+    async fn read_packet(stream: &mut TcpStream) -> (i32, Vec<u8>) {
+        let len = read_varint_async(stream).await.unwrap();
+        let mut packet = vec![0; len as usize];
+        stream.read_exact(&mut packet).await.unwrap();
+        let mut body = &packet[..];
+        let id = read_varint(&mut body).unwrap();
+        (id, body.to_vec())
+    }
+
+    async fn write_packet(stream: &mut TcpStream, id: i32, data: &[u8]) {
+        let mut body = Vec::new();
+        write_varint(&mut body, id);
+        body.extend_from_slice(data);
+        let mut packet = Vec::new();
+        write_varint(&mut packet, body.len() as i32);
+        packet.extend_from_slice(&body);
+        stream.write_all(&packet).await.unwrap();
+    }
+
+    /// Starts a server on localhost that answers a status request, then
+    /// answers a ping with `pong(payload)`.
+    async fn server(pong: fn(u64) -> u64) -> u16 {
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let port = listener.local_addr().unwrap().port();
+        tokio::spawn(async move {
+            let (mut stream, _) = listener.accept().await.unwrap();
+
+            let (id, handshake) = read_packet(&mut stream).await;
+            assert_eq!(id, 0x00);
+            let mut rest = &handshake[..];
+            assert_eq!(read_varint(&mut rest).unwrap(), PROTOCOL_VERSION);
+            assert_eq!(read_string(&mut rest).unwrap(), "localhost");
+            assert_eq!(rest, [&port.to_be_bytes()[..], &[1]].concat());
+
+            assert_eq!(read_packet(&mut stream).await, (0x00, vec![]));
+            let mut response = Vec::new();
+            write_string(&mut response, STATUS);
+            write_packet(&mut stream, 0x00, &response).await;
+
+            let (id, payload) = read_packet(&mut stream).await;
+            assert_eq!(id, 0x01);
+            let payload = u64::from_be_bytes(payload.try_into().unwrap());
+            write_packet(&mut stream, 0x01, &pong(payload).to_be_bytes()).await;
+        });
+        port
+    }
+
+    #[tokio::test]
+    async fn gets_the_status_and_pings() {
+        let port = server(|payload| payload).await;
+        let mut conn = Connection::connect(
+            ([127, 0, 0, 1], port).into(),
+            "localhost",
+            Duration::from_secs(5),
+        )
+        .await
+        .unwrap();
+        let status = conn.status().await.unwrap();
+        assert_eq!(status.version.name, "1.19.2");
+        assert_eq!(status.version.protocol, 760);
+        assert_eq!((status.players.online, status.players.max), (1, 20));
+        assert_eq!(status.players.sample.unwrap()[0].name, "Alice");
+        assert_eq!(status.description, serde_json::json!({"text": "Hello"}));
+        assert_eq!(status.favicon, None);
+        conn.ping(0x8008135).await.unwrap();
+    }
+
+    #[tokio::test]
+    async fn rejects_a_mismatched_pong() {
+        let port = server(|payload| payload + 1).await;
+        let mut conn = Connection::connect(
+            ([127, 0, 0, 1], port).into(),
+            "localhost",
+            Duration::from_secs(5),
+        )
+        .await
+        .unwrap();
+        conn.status().await.unwrap();
+        let err = conn.ping(1).await.unwrap_err();
+        assert!(
+            matches!(
+                err,
+                Error::MismatchedPayload {
+                    expected: 1,
+                    actual: 2
+                }
+            ),
+            "{err:?}"
+        );
+    }
+}
@@ -0,0 +1,277 @@
+//! The GameSpy4 [Query](https://wiki.vg/Query) protocol, which servers with
+//! `enable-query=true` answer over UDP. Unlike the server list ping, it
+//! returns every online player rather than a sample.
+
+use std::net::SocketAddr;
+use std::time::Duration;
+
+use serde::Serialize;
+use tokio::net::UdpSocket;
+
+use crate::net::{connect_udp, with_timeout};
+use crate::{Error, Result};
+
+const MAGIC: [u8; 2] = [0xfe, 0xfd];
+const TYPE_HANDSHAKE: u8 = 0x09;
+const TYPE_STAT: u8 = 0x00;
+/// The padding before the key/value section of a full stat response.
+const KV_PADDING: &[u8] = b"splitnum\0\x80\0";
+/// The padding between the key/value section and the player list.
+const PLAYERS_PADDING: &[u8] = b"\x01player_\0\0";
+
+/// The response to a full stat request.
+#[derive(Debug, Clone, Serialize)]
+pub struct FullStat {
+    pub motd: String,
+    pub game_type: String,
+    pub version: String,
+    /// The server software, e.g. `CraftBukkit on Bukkit 1.2.5-R4.0`, if it
+    /// reports one.
+    pub software: Option<String>,
+    pub plugins: Vec<String>,
+    pub map: String,
+    pub online: u32,
+    pub max: u32,
+    pub host_port: Option<u16>,
+    pub host_ip: Option<String>,
+    /// Everyone who's online.
+    pub players: Vec<String>,
+}
+
+/// Does the handshake and asks for the full stat.
+pub async fn full_stat(addr: SocketAddr, timeout: Duration) -> Result<FullStat> {
+    let socket = connect_udp(addr).await?;
+
+    // the server only looks at the low 4 bits of each byte
+    let session_id = std::process::id() as i32 & 0x0f0f_0f0f;
+
+    let response = request(&socket, TYPE_HANDSHAKE, session_id, &[], timeout).await?;
+    let token = std::str::from_utf8(null_terminated(&response)?.0)
+        .ok()
+        .and_then(|s| s.parse::<i32>().ok())
+        .ok_or(Error::InvalidPacket("bad challenge token"))?;
+
+    let mut payload = token.to_be_bytes().to_vec();
+    // asking for the full stat rather than the basic one
+    payload.extend_from_slice(&[0; 4]);
+    let response = request(&socket, TYPE_STAT, session_id, &payload, timeout).await?;
+    parse_full_stat(&response)
+}
+
+/// Sends a request and returns the body of the matching response.
+async fn request(
+    socket: &UdpSocket,
+    kind: u8,
+    session_id: i32,
+    payload: &[u8],
+    timeout: Duration,
+) -> Result<Vec<u8>> {
+    let mut packet = MAGIC.to_vec();
+    packet.push(kind);
+    packet.extend_from_slice(&session_id.to_be_bytes());
+    packet.extend_from_slice(payload);
+    socket.send(&packet).await?;
+
+    let mut buf = vec![0; 16 * 1024];
+    with_timeout(timeout, async {
+        loop {
+            let len = socket.recv(&mut buf).await?;
+            if len >= 5 && buf[0] == kind && buf[1..5] == session_id.to_be_bytes() {
+                return Ok(buf[5..len].to_vec());
+            }
+        }
+    })
+    .await
+}
+
+fn parse_full_stat(data: &[u8]) -> Result<FullStat> {
+    let mut data = data
+        .strip_prefix(KV_PADDING)
+        .ok_or(Error::InvalidPacket("malformed full stat response"))?;
+
+    let mut stat = FullStat {
+        motd: String::new(),
+        game_type: String::new(),
+        version: String::new(),
+        software: None,
+        plugins: Vec::new(),
+        map: String::new(),
+        online: 0,
+        max: 0,
+        host_port: None,
+        host_ip: None,
+        players: Vec::new(),
+    };
+    loop {
+        let (key, rest) = null_terminated(data)?;
+        if key.is_empty() {
+            data = rest;
+            break;
+        }
+        let (value, rest) = null_terminated(rest)?;
+        data = rest;
+        // the MOTD is sent as latin-1 by vanilla, but everything else in
+        // practice is UTF-8
+        let value = String::from_utf8_lossy(value).into_owned();
+        match key {
+            b"hostname" => stat.motd = value,
+            b"gametype" => stat.game_type = value,
+            b"version" => stat.version = value,
+            b"plugins" => {
+                // "<software>: <plugin>; <plugin>", or just "<software>"
+                let (software, plugins) = value.split_once(": ").unwrap_or((&value, ""));
+                if !software.is_empty() {
+                    stat.software = Some(software.to_owned());
+                }
+                stat.plugins = plugins
+                    .split("; ")
+                    .filter(|p| !p.is_empty())
+                    .map(str::to_owned)
+                    .collect();
+            }
+            b"map" => stat.map = value,
+            b"numplayers" => stat.online = value.parse().unwrap_or(0),
+            b"maxplayers" => stat.max = value.parse().unwrap_or(0),
+            b"hostport" => stat.host_port = value.parse().ok(),
+            b"hostip" => stat.host_ip = Some(value),
+            _ => {}
+        }
+    }
+
+    let mut data = data
+        .strip_prefix(PLAYERS_PADDING)
+        .ok_or(Error::InvalidPacket("malformed full stat response"))?;
+    while let Ok((name, rest)) = null_terminated(data) {
+        if name.is_empty() {
+            break;
+        }
+        stat.players
+            .push(String::from_utf8_lossy(name).into_owned());
+        data = rest;
+    }
+
+    Ok(stat)
+}
+
+/// Splits off a null-terminated string.
+fn null_terminated(data: &[u8]) -> Result<(&[u8], &[u8])> {
+    let end = data
+        .iter()
+        .position(|&b| b == 0)
+        .ok_or(Error::InvalidPacket("missing null terminator"))?;
+    Ok((&data[..end], &data[end + 1..]))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// A full stat response from a vanilla server, after the type and
+    /// session ID.
+    const VANILLA: &[u8] = b"splitnum\x00\x80\x00\
+        hostname\x00A Minecraft Server\x00gametype\x00SMP\x00game_id\x00MINECRAFT\x00\
+        version\x001.20.1\x00plugins\x00\x00map\x00world\x00numplayers\x002\x00\
+        maxplayers\x0020\x00hostport\x0025565\x00hostip\x00127.0.0.1\x00\x00\
+        \x01player_\x00\x00Alice\x00Bob\x00\x00";
+
+    /// The same from a Bukkit server with plugins.
+    const BUKKIT: &[u8] = b"splitnum\x00\x80\x00\
+        hostname\x00\xc2\xa7aBukkit \xc2\xa7lServer\x00gametype\x00SMP\x00game_id\x00MINECRAFT\x00\
+        version\x001.2.5\x00\
+        plugins\x00CraftBukkit on Bukkit 1.2.5-R4.0: WorldEdit 5.3; CommandBook 2.1\x00\
+        map\x00world\x00numplayers\x000\x00maxplayers\x00100\x00hostport\x0025566\x00\
+        hostip\x000.0.0.0\x00\x00\
+        \x01player_\x00\x00\x00";
+
+    #[test]
+    fn parses_a_vanilla_full_stat() {
+        let stat = parse_full_stat(VANILLA).unwrap();
+        assert_eq!(stat.motd, "A Minecraft Server");
+        assert_eq!(stat.game_type, "SMP");
+        assert_eq!(stat.version, "1.20.1");
+        assert_eq!(stat.software, None);
+        assert!(stat.plugins.is_empty());
+        assert_eq!(stat.map, "world");
+        assert_eq!((stat.online, stat.max), (2, 20));
+        assert_eq!(stat.host_port, Some(25565));
+        assert_eq!(stat.host_ip.as_deref(), Some("127.0.0.1"));
+        assert_eq!(stat.players, ["Alice", "Bob"]);
+    }
+
+    #[test]
+    fn parses_a_full_stat_with_plugins() {
+        let stat = parse_full_stat(BUKKIT).unwrap();
+        assert_eq!(stat.motd, "§aBukkit §lServer");
+        assert_eq!(
+            stat.software.as_deref(),
+            Some("CraftBukkit on Bukkit 1.2.5-R4.0")
+        );
+        assert_eq!(stat.plugins, ["WorldEdit 5.3", "CommandBook 2.1"]);
+        assert_eq!((stat.online, stat.max), (0, 100));
+        assert_eq!(stat.host_port, Some(25566));
+        assert!(stat.players.is_empty());
+
+        // software that doesn't list its plugins
+        let data = [
+            &b"splitnum\x00\x80\x00plugins\x00Paper on 1.20.1\x00\x00"[..],
+            PLAYERS_PADDING,
+            b"\x00",
+        ]
+        .concat();
+        let stat = parse_full_stat(&data).unwrap();
+        assert_eq!(stat.software.as_deref(), Some("Paper on 1.20.1"));
+        assert!(stat.plugins.is_empty());
+    }
+
+    #[test]
+    fn rejects_malformed_full_stats() {
+        for data in [
+            &b""[..],
+            &VANILLA[1..],
+            // cut off in the middle of the key/value section
+            &VANILLA[..40],
+            // without the padding before the players
+            &VANILLA[..VANILLA.len() - 23],
+        ] {
+            assert!(parse_full_stat(data).is_err(), "{data:?}");
+        }
+    }
+
+    #[tokio::test]
+    async fn queries_a_local_server() {
+        let socket = UdpSocket::bind("127.0.0.1:0").await.unwrap();
+        let addr = socket.local_addr().unwrap();
+        tokio::spawn(async move {
+            let mut buf = [0; 64];
+            let (len, from) = socket.recv_from(&mut buf).await.unwrap();
+            assert_eq!(len, 7);
+            assert_eq!(buf[..3], [0xfe, 0xfd, TYPE_HANDSHAKE]);
+            let session_id: [u8; 4] = buf[3..7].try_into().unwrap();
+            assert!(session_id.iter().all(|b| b & 0xf0 == 0));
+
+            // a stray packet for another session first
+            let mut reply = vec![TYPE_HANDSHAKE, 0xff, 0xff, 0xff, 0xff];
+            reply.extend_from_slice(b"1\0");
+            socket.send_to(&reply, from).await.unwrap();
+            let mut reply = vec![TYPE_HANDSHAKE];
+            reply.extend_from_slice(&session_id);
+            reply.extend_from_slice(b"-9513307\0");
+            socket.send_to(&reply, from).await.unwrap();
+
+            let (len, from) = socket.recv_from(&mut buf).await.unwrap();
+            assert_eq!(len, 15);
+            assert_eq!(buf[..3], [0xfe, 0xfd, TYPE_STAT]);
+            assert_eq!(buf[3..7], session_id);
+            assert_eq!(buf[7..11], (-9513307i32).to_be_bytes());
+            assert_eq!(buf[11..15], [0; 4]);
+            let mut reply = vec![TYPE_STAT];
+            reply.extend_from_slice(&session_id);
+            reply.extend_from_slice(VANILLA);
+            socket.send_to(&reply, from).await.unwrap();
+        });
+
+        let stat = full_stat(addr, Duration::from_secs(5)).await.unwrap();
+        assert_eq!(stat.motd, "A Minecraft Server");
+        assert_eq!(stat.players, ["Alice", "Bob"]);
+    }
+}
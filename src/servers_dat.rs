@@ -0,0 +1,108 @@
+use std::fmt;
+use std::fs::File;
+use std::io::BufReader;
+use std::path::{Path, PathBuf};
+
+use serde::Deserialize;
+
+use crate::icon::Icon;
+
+/// The list of saved servers from the multiplayer menu, as stored in
+/// `servers.dat` in the game directory.
+#[derive(Debug, Deserialize)]
+pub struct ServersDat {
+    pub servers: Vec<Server>,
+}
+
+#[derive(Debug, Clone, Deserialize)]
+pub struct Server {
+    pub ip: String,
+    pub name: String,
+    /// The server's icon from when the game last pinged it, as a base64 PNG.
+    #[serde(default)]
+    pub icon: Option<String>,
+}
+
+impl fmt::Display for Server {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{} (ip: {})", self.name, self.ip)
+    }
+}
+
+impl Server {
+    /// Decodes the cached icon, if there is one and it's a valid PNG.
+    pub fn icon(&self) -> Option<Icon> {
+        Icon::from_base64(self.icon.as_deref()?).ok()
+    }
+}
+
+impl ServersDat {
+    /// Reads a `servers.dat` file.
+    pub fn from_path(path: impl AsRef<Path>) -> Result<Self, nbt::Error> {
+        nbt::from_reader(BufReader::new(File::open(path)?))
+    }
+}
+
+/// The default game directory of the vanilla launcher, if it exists.
+pub fn minecraft_dir() -> Option<PathBuf> {
+    let mc_dir = if cfg!(any(windows, target_os = "macos")) {
+        dirs_next::data_dir()
+    } else {
+        dirs_next::home_dir()
+    };
+    mc_dir.and_then(|mut mc_dir| {
+        mc_dir.push(if cfg!(target_os = "macos") {
+            "minecraft"
+        } else {
+            ".minecraft"
+        });
+        mc_dir.is_dir().then_some(mc_dir)
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use std::collections::HashMap;
+
+    use nbt::{Blob, Value};
+
+    use super::*;
+
+    fn server(name: &str, ip: &str) -> Value {
+        Value::Compound(HashMap::from([
+            ("name".to_owned(), Value::String(name.to_owned())),
+            ("ip".to_owned(), Value::String(ip.to_owned())),
+            ("acceptTextures".to_owned(), Value::Byte(1)),
+        ]))
+    }
+
+    #[test]
+    fn reads_servers_dat() {
+        let mut blob = Blob::new();
+        blob.insert(
+            "servers",
+            Value::List(vec![
+                server("Hypixel", "mc.hypixel.net"),
+                server("Local", "localhost:25566"),
+            ]),
+        )
+        .unwrap();
+        let path = std::env::temp_dir().join(format!("servers-{}.dat", std::process::id()));
+        blob.to_writer(&mut File::create(&path).unwrap()).unwrap();
+        let dat = ServersDat::from_path(&path);
+        std::fs::remove_file(&path).unwrap();
+
+        let servers = dat.unwrap().servers;
+        assert_eq!(servers.len(), 2);
+        assert_eq!(servers[0].to_string(), "Hypixel (ip: mc.hypixel.net)");
+        assert_eq!(
+            (&*servers[1].name, &*servers[1].ip),
+            ("Local", "localhost:25566")
+        );
+    }
+
+    #[test]
+    fn fails_on_a_missing_file() {
+        assert!(ServersDat::from_path("/nonexistent/servers.dat").is_err());
+    }
+}
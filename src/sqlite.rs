@@ -0,0 +1,485 @@
+//! Just enough of a binding to the system's SQLite library for the history
+//! database: opening it, running statements with parameters, and reading
+//! the rows back.
+
+use std::ffi::{c_char, c_int, CStr, CString};
+use std::path::Path;
+use std::ptr;
+
+#[allow(non_camel_case_types)]
+mod ffi {
+    use std::ffi::{c_char, c_int, c_void};
+
+    pub enum sqlite3 {}
+    pub enum sqlite3_stmt {}
+
+    pub const SQLITE_OK: c_int = 0;
+    pub const SQLITE_ROW: c_int = 100;
+    pub const SQLITE_DONE: c_int = 101;
+    pub const SQLITE_NULL: c_int = 5;
+    pub const SQLITE_OPEN_READONLY: c_int = 0x1;
+    pub const SQLITE_OPEN_READWRITE: c_int = 0x2;
+    pub const SQLITE_OPEN_CREATE: c_int = 0x4;
+
+    pub type sqlite3_destructor_type = Option<unsafe extern "C" fn(*mut c_void)>;
+    pub type sqlite3_callback = Option<
+        unsafe extern "C" fn(*mut c_void, c_int, *mut *mut c_char, *mut *mut c_char) -> c_int,
+    >;
+
+    /// Tells SQLite to make its own copy of bound text. In C it's the
+    /// destructor `(sqlite3_destructor_type)-1`, which is never called.
+    pub fn sqlite_transient() -> sqlite3_destructor_type {
+        Some(unsafe { std::mem::transmute::<isize, unsafe extern "C" fn(*mut c_void)>(-1) })
+    }
+
+    #[link(name = "sqlite3")]
+    extern "C" {
+        pub fn sqlite3_open_v2(
+            filename: *const c_char,
+            db: *mut *mut sqlite3,
+            flags: c_int,
+            vfs: *const c_char,
+        ) -> c_int;
+        pub fn sqlite3_close(db: *mut sqlite3) -> c_int;
+        pub fn sqlite3_errmsg(db: *mut sqlite3) -> *const c_char;
+        pub fn sqlite3_busy_timeout(db: *mut sqlite3, ms: c_int) -> c_int;
+        pub fn sqlite3_last_insert_rowid(db: *mut sqlite3) -> i64;
+        pub fn sqlite3_exec(
+            db: *mut sqlite3,
+            sql: *const c_char,
+            callback: sqlite3_callback,
+            arg: *mut c_void,
+            errmsg: *mut *mut c_char,
+        ) -> c_int;
+        pub fn sqlite3_prepare_v2(
+            db: *mut sqlite3,
+            sql: *const c_char,
+            len: c_int,
+            stmt: *mut *mut sqlite3_stmt,
+            tail: *mut *const c_char,
+        ) -> c_int;
+        pub fn sqlite3_finalize(stmt: *mut sqlite3_stmt) -> c_int;
+        pub fn sqlite3_reset(stmt: *mut sqlite3_stmt) -> c_int;
+        pub fn sqlite3_clear_bindings(stmt: *mut sqlite3_stmt) -> c_int;
+        pub fn sqlite3_step(stmt: *mut sqlite3_stmt) -> c_int;
+        pub fn sqlite3_bind_null(stmt: *mut sqlite3_stmt, i: c_int) -> c_int;
+        pub fn sqlite3_bind_int64(stmt: *mut sqlite3_stmt, i: c_int, value: i64) -> c_int;
+        pub fn sqlite3_bind_double(stmt: *mut sqlite3_stmt, i: c_int, value: f64) -> c_int;
+        pub fn sqlite3_bind_text(
+            stmt: *mut sqlite3_stmt,
+            i: c_int,
+            text: *const c_char,
+            len: c_int,
+            destructor: sqlite3_destructor_type,
+        ) -> c_int;
+        pub fn sqlite3_column_type(stmt: *mut sqlite3_stmt, i: c_int) -> c_int;
+        pub fn sqlite3_column_int64(stmt: *mut sqlite3_stmt, i: c_int) -> i64;
+        pub fn sqlite3_column_double(stmt: *mut sqlite3_stmt, i: c_int) -> f64;
+        pub fn sqlite3_column_text(stmt: *mut sqlite3_stmt, i: c_int) -> *const u8;
+        pub fn sqlite3_column_bytes(stmt: *mut sqlite3_stmt, i: c_int) -> c_int;
+    }
+}
+
+/// An error from SQLite, with its message.
+#[derive(Debug, thiserror::Error)]
+#[error("SQLite error: {0}")]
+pub struct SqliteError(String);
+
+pub type Result<T, E = SqliteError> = std::result::Result<T, E>;
+
+/// A value to bind to a statement's `?` parameter.
+#[derive(Debug, Clone, PartialEq)]
+pub enum Value<'a> {
+    Null,
+    Integer(i64),
+    Real(f64),
+    Text(&'a str),
+}
+
+impl From<i64> for Value<'_> {
+    fn from(n: i64) -> Self {
+        Value::Integer(n)
+    }
+}
+
+impl From<f64> for Value<'_> {
+    fn from(n: f64) -> Self {
+        Value::Real(n)
+    }
+}
+
+impl<'a> From<&'a str> for Value<'a> {
+    fn from(s: &'a str) -> Self {
+        Value::Text(s)
+    }
+}
+
+impl<'a, T: Into<Value<'a>>> From<Option<T>> for Value<'a> {
+    fn from(value: Option<T>) -> Self {
+        value.map_or(Value::Null, Into::into)
+    }
+}
+
+pub struct Connection {
+    db: *mut ffi::sqlite3,
+}
+
+impl Connection {
+    /// Opens the database at `path`, creating it if `create` is set, and
+    /// otherwise opening it read-only.
+    pub fn open(path: &Path, create: bool) -> Result<Self> {
+        let path = path
+            .to_str()
+            .and_then(|path| CString::new(path).ok())
+            .ok_or_else(|| SqliteError("the path isn't valid UTF-8".to_owned()))?;
+        let flags = match create {
+            true => ffi::SQLITE_OPEN_READWRITE | ffi::SQLITE_OPEN_CREATE,
+            false => ffi::SQLITE_OPEN_READONLY,
+        };
+        let mut db = ptr::null_mut();
+        let rc = unsafe { ffi::sqlite3_open_v2(path.as_ptr(), &mut db, flags, ptr::null()) };
+        if db.is_null() {
+            return Err(SqliteError("out of memory".to_owned()));
+        }
+        // even a failed open gives us a connection, which still needs
+        // closing once we have the error message out of it
+        let conn = Connection { db };
+        conn.check(rc)?;
+        // wait for another `record` writing at the same time, rather than
+        // failing straight away
+        unsafe { ffi::sqlite3_busy_timeout(db, 5000) };
+        Ok(conn)
+    }
+
+    fn check(&self, rc: c_int) -> Result<()> {
+        match rc {
+            ffi::SQLITE_OK => Ok(()),
+            _ => Err(self.error()),
+        }
+    }
+
+    fn error(&self) -> SqliteError {
+        let msg = unsafe { CStr::from_ptr(ffi::sqlite3_errmsg(self.db)) };
+        SqliteError(msg.to_string_lossy().into_owned())
+    }
+
+    /// Runs a statement that doesn't return rows, like `CREATE TABLE`.
+    pub fn execute(&self, sql: &str, params: &[Value]) -> Result<()> {
+        self.prepare(sql)?.execute(params)
+    }
+
+    /// Runs several `;`-separated statements without parameters, stopping
+    /// at the first one that fails.
+    pub fn execute_batch(&self, sql: &str) -> Result<()> {
+        let sql = CString::new(sql)
+            .map_err(|_| SqliteError("the statements contain a NUL byte".to_owned()))?;
+        let rc = unsafe {
+            ffi::sqlite3_exec(
+                self.db,
+                sql.as_ptr(),
+                None,
+                ptr::null_mut(),
+                ptr::null_mut(),
+            )
+        };
+        self.check(rc)
+    }
+
+    pub fn prepare(&self, sql: &str) -> Result<Statement<'_>> {
+        let len = c_int::try_from(sql.len())
+            .map_err(|_| SqliteError("the statement is too long".to_owned()))?;
+        let mut stmt = ptr::null_mut();
+        let rc = unsafe {
+            ffi::sqlite3_prepare_v2(
+                self.db,
+                sql.as_ptr().cast::<c_char>(),
+                len,
+                &mut stmt,
+                ptr::null_mut(),
+            )
+        };
+        self.check(rc)?;
+        Ok(Statement { conn: self, stmt })
+    }
+
+    /// Starts a transaction, which is rolled back unless it's committed.
+    pub fn transaction(&self) -> Result<Transaction<'_>> {
+        self.execute("BEGIN", &[])?;
+        Ok(Transaction {
+            conn: self,
+            committed: false,
+        })
+    }
+
+    /// The rowid of the last row inserted, for an `INTEGER PRIMARY KEY`.
+    pub fn last_insert_rowid(&self) -> i64 {
+        unsafe { ffi::sqlite3_last_insert_rowid(self.db) }
+    }
+}
+
+impl Drop for Connection {
+    fn drop(&mut self) {
+        unsafe { ffi::sqlite3_close(self.db) };
+    }
+}
+
+/// An open transaction. Dropping it without committing, like on an early
+/// return with `?`, rolls it back.
+pub struct Transaction<'a> {
+    conn: &'a Connection,
+    committed: bool,
+}
+
+impl Transaction<'_> {
+    pub fn commit(mut self) -> Result<()> {
+        self.conn.execute("COMMIT", &[])?;
+        self.committed = true;
+        Ok(())
+    }
+}
+
+impl Drop for Transaction<'_> {
+    fn drop(&mut self) {
+        if !self.committed {
+            // there's nothing more to do if even this fails
+            let _ = self.conn.execute("ROLLBACK", &[]);
+        }
+    }
+}
+
+/// A prepared statement, which can be run again with other parameters.
+pub struct Statement<'a> {
+    conn: &'a Connection,
+    stmt: *mut ffi::sqlite3_stmt,
+}
+
+impl Statement<'_> {
+    fn bind(&mut self, params: &[Value]) -> Result<()> {
+        unsafe {
+            ffi::sqlite3_reset(self.stmt);
+            ffi::sqlite3_clear_bindings(self.stmt);
+        }
+        for (i, param) in params.iter().enumerate() {
+            // parameters count from 1
+            let i = i as c_int + 1;
+            let rc = unsafe {
+                match *param {
+                    Value::Null => ffi::sqlite3_bind_null(self.stmt, i),
+                    Value::Integer(n) => ffi::sqlite3_bind_int64(self.stmt, i, n),
+                    Value::Real(n) => ffi::sqlite3_bind_double(self.stmt, i, n),
+                    Value::Text(s) => ffi::sqlite3_bind_text(
+                        self.stmt,
+                        i,
+                        s.as_ptr().cast::<c_char>(),
+                        c_int::try_from(s.len())
+                            .map_err(|_| SqliteError("the text is too long".to_owned()))?,
+                        ffi::sqlite_transient(),
+                    ),
+                }
+            };
+            self.conn.check(rc)?;
+        }
+        Ok(())
+    }
+
+    /// Steps to the next row, returning whether there is one.
+    fn step(&mut self) -> Result<bool> {
+        match unsafe { ffi::sqlite3_step(self.stmt) } {
+            ffi::SQLITE_ROW => Ok(true),
+            ffi::SQLITE_DONE => Ok(false),
+            _ => Err(self.conn.error()),
+        }
+    }
+
+    /// Runs the statement with `params`, ignoring any rows it returns.
+    pub fn execute(&mut self, params: &[Value]) -> Result<()> {
+        self.bind(params)?;
+        while self.step()? {}
+        Ok(())
+    }
+
+    /// Runs the statement with `params`, turning each row it returns into a
+    /// `T` with `row`.
+    pub fn query<T>(&mut self, params: &[Value], mut row: impl FnMut(&Row) -> T) -> Result<Vec<T>> {
+        self.bind(params)?;
+        let mut rows = Vec::new();
+        while self.step()? {
+            rows.push(row(&Row(self.stmt)));
+        }
+        Ok(rows)
+    }
+}
+
+impl Drop for Statement<'_> {
+    fn drop(&mut self) {
+        unsafe { ffi::sqlite3_finalize(self.stmt) };
+    }
+}
+
+/// The row a statement is on. Columns count from 0.
+pub struct Row(*mut ffi::sqlite3_stmt);
+
+impl Row {
+    fn is_null(&self, i: c_int) -> bool {
+        unsafe { ffi::sqlite3_column_type(self.0, i) == ffi::SQLITE_NULL }
+    }
+
+    pub fn i64(&self, i: c_int) -> Option<i64> {
+        (!self.is_null(i)).then(|| unsafe { ffi::sqlite3_column_int64(self.0, i) })
+    }
+
+    pub fn f64(&self, i: c_int) -> Option<f64> {
+        (!self.is_null(i)).then(|| unsafe { ffi::sqlite3_column_double(self.0, i) })
+    }
+
+    pub fn text(&self, i: c_int) -> Option<String> {
+        if self.is_null(i) {
+            return None;
+        }
+        let text = unsafe {
+            // the text has to be fetched before its length
+            let ptr = ffi::sqlite3_column_text(self.0, i);
+            let len = ffi::sqlite3_column_bytes(self.0, i);
+            if ptr.is_null() {
+                return Some(String::new());
+            }
+            std::slice::from_raw_parts(ptr, len as usize)
+        };
+        Some(String::from_utf8_lossy(text).into_owned())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn memory() -> Connection {
+        Connection::open(Path::new(":memory:"), true).unwrap()
+    }
+
+    #[test]
+    fn round_trips_values() {
+        let db = memory();
+        db.execute_batch("CREATE TABLE t (i INTEGER, r REAL, s TEXT); CREATE INDEX t_i ON t (i)")
+            .unwrap();
+        let mut insert = db.prepare("INSERT INTO t VALUES (?, ?, ?)").unwrap();
+        insert
+            .execute(&[Value::Integer(-5), Value::Real(1.5), Value::Text("héllo")])
+            .unwrap();
+        insert
+            .execute(&[None::<i64>.into(), None::<f64>.into(), None::<&str>.into()])
+            .unwrap();
+        assert_eq!(db.last_insert_rowid(), 2);
+
+        let rows = db
+            .prepare("SELECT i, r, s FROM t ORDER BY rowid")
+            .unwrap()
+            .query(&[], |row| (row.i64(0), row.f64(1), row.text(2)))
+            .unwrap();
+        assert_eq!(
+            rows,
+            [
+                (Some(-5), Some(1.5), Some("héllo".to_owned())),
+                (None, None, None),
+            ]
+        );
+    }
+
+    #[test]
+    fn runs_batches_with_semicolons_inside_statements() {
+        let db = memory();
+        db.execute_batch(
+            "CREATE TABLE t (s TEXT);
+             CREATE TABLE log (s TEXT);
+             CREATE TRIGGER t_log AFTER INSERT ON t BEGIN
+                 INSERT INTO log VALUES ('added; ' || new.s);
+             END;
+             INSERT INTO t VALUES ('a;b');",
+        )
+        .unwrap();
+        let rows = db
+            .prepare("SELECT s FROM log")
+            .unwrap()
+            .query(&[], |row| row.text(0))
+            .unwrap();
+        assert_eq!(rows, [Some("added; a;b".to_owned())]);
+
+        let err = db
+            .execute_batch("INSERT INTO t VALUES ('c'); INSERT INTO missing VALUES (1)")
+            .err()
+            .unwrap();
+        assert!(err.to_string().contains("no such table: missing"), "{err}");
+    }
+
+    #[test]
+    fn copies_bound_text() {
+        let db = memory();
+        let mut select = db.prepare("SELECT ?").unwrap();
+        let mut text = "temporary".to_owned();
+        select.bind(&[Value::Text(&text)]).unwrap();
+        text.replace_range(.., "overwritten");
+        drop(text);
+        assert!(select.step().unwrap());
+        assert_eq!(Row(select.stmt).text(0).as_deref(), Some("temporary"));
+    }
+
+    #[test]
+    fn reuses_statements_with_new_parameters() {
+        let db = memory();
+        let mut select = db.prepare("SELECT ? + 1").unwrap();
+        for n in 0..3 {
+            let rows = select
+                .query(&[Value::Integer(n)], |row| row.i64(0))
+                .unwrap();
+            assert_eq!(rows, [Some(n + 1)]);
+        }
+    }
+
+    #[test]
+    fn reports_errors() {
+        let db = memory();
+        let err = db.execute("SELECT * FROM missing", &[]).err().unwrap();
+        assert!(err.to_string().contains("no such table: missing"), "{err}");
+
+        db.execute("CREATE TABLE t (i INTEGER NOT NULL)", &[])
+            .unwrap();
+        let err = db
+            .execute("INSERT INTO t VALUES (NULL)", &[])
+            .err()
+            .unwrap();
+        assert!(err.to_string().contains("NOT NULL"), "{err}");
+    }
+
+    #[test]
+    fn rolls_back_unless_committed() {
+        let db = memory();
+        db.execute("CREATE TABLE t (i INTEGER NOT NULL)", &[])
+            .unwrap();
+        let insert = |values: &[Option<i64>]| -> Result<()> {
+            let tx = db.transaction()?;
+            for &value in values {
+                db.execute("INSERT INTO t VALUES (?)", &[value.into()])?;
+            }
+            tx.commit()
+        };
+        insert(&[Some(1), Some(2)]).unwrap();
+        assert!(insert(&[Some(3), None]).is_err());
+        // and the connection isn't left in the failed transaction
+        insert(&[Some(4)]).unwrap();
+
+        let rows = db
+            .prepare("SELECT i FROM t ORDER BY i")
+            .unwrap()
+            .query(&[], |row| row.i64(0))
+            .unwrap();
+        assert_eq!(rows, [Some(1), Some(2), Some(4)]);
+    }
+
+    #[test]
+    fn opening_read_only_needs_an_existing_database() {
+        let path = std::env::temp_dir().join("mcserverstatus-test-missing.db");
+        let _ = std::fs::remove_file(&path);
+        assert!(Connection::open(&path, false).is_err());
+        assert!(!path.exists());
+    }
+}
@@ -0,0 +1,94 @@
+//! What we remember between runs, in `state.json` in the data directory
+//! (`~/.local/share/mcserverstatus` on Linux). Unlike the config file, this
+//! is only ever written by us.
+
+use std::collections::BTreeMap;
+use std::fs;
+use std::path::PathBuf;
+
+use anyhow::Context;
+use mcserverstatus::ServerStatus;
+use serde::{Deserialize, Serialize};
+
+#[derive(Debug, Default, Serialize, Deserialize)]
+#[serde(default)]
+pub struct State {
+    /// The address of the server last picked from servers.dat.
+    pub last_server: Option<String>,
+    /// How each server was doing when it was last queried, by address.
+    pub last_seen: BTreeMap<String, LastSeen>,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct LastSeen {
+    pub timestamp: u64,
+    /// Whether the server answered. The player counts are 0 if it didn't.
+    pub up: bool,
+    pub online: u32,
+    pub max: u32,
+}
+
+fn path() -> Option<PathBuf> {
+    dirs_next::data_dir().map(|dir| dir.join("mcserverstatus").join("state.json"))
+}
+
+impl State {
+    /// Loads the state, starting afresh if there isn't any or it can't be
+    /// read, since it's only there for convenience.
+    pub fn load() -> Self {
+        path()
+            .and_then(|path| fs::read(path).ok())
+            .and_then(|json| serde_json::from_slice(&json).ok())
+            .unwrap_or_default()
+    }
+
+    /// Notes how the server at `addr` was doing at `timestamp`, with `None`
+    /// for a server that couldn't be reached.
+    pub fn record(&mut self, addr: &str, status: Option<&ServerStatus>, timestamp: u64) {
+        let seen = LastSeen {
+            timestamp,
+            up: status.is_some(),
+            online: status.map_or(0, |status| status.online),
+            max: status.map_or(0, |status| status.max),
+        };
+        self.last_seen.insert(addr.to_owned(), seen);
+    }
+
+    pub fn save(&self) -> anyhow::Result<()> {
+        let path = path().context("Couldn't find the data directory")?;
+        if let Some(dir) = path.parent() {
+            fs::create_dir_all(dir)?;
+        }
+        // write to a temporary file first, so that a crash halfway through
+        // can't leave a truncated file behind
+        let tmp = path.with_extension("json.tmp");
+        fs::write(&tmp, serde_json::to_vec_pretty(self)?)?;
+        fs::rename(&tmp, &path)?;
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn reads_older_state() {
+        let state: State = serde_json::from_str(r#"{"last_server": "mc.example.net"}"#).unwrap();
+        assert_eq!(state.last_server.as_deref(), Some("mc.example.net"));
+        assert!(state.last_seen.is_empty());
+    }
+
+    #[test]
+    fn records_servers() {
+        let mut state = State::default();
+        state.record("mc.example.net", None, 1000);
+        state.record("mc.example.net", None, 2000);
+        let seen = &state.last_seen["mc.example.net"];
+        assert_eq!((seen.timestamp, seen.up, seen.online), (2000, false, 0));
+
+        let json = serde_json::to_string(&state).unwrap();
+        let state: State = serde_json::from_str(&json).unwrap();
+        assert_eq!(state.last_seen["mc.example.net"].timestamp, 2000);
+    }
+}
@@ -0,0 +1,711 @@
+use std::future::Future;
+use std::io;
+use std::net::SocketAddr;
+use std::time::{Duration, Instant};
+
+use itertools::Itertools;
+use serde::{Serialize, Serializer};
+
+use crate::bedrock::{self, BedrockStatus, DEFAULT_BEDROCK_PORT};
+use crate::dns::Resolver;
+use crate::icon::Icon;
+use crate::legacy::LegacyConnection;
+use crate::motd::Text;
+use crate::ping::Connection;
+use crate::query::{self, FullStat};
+use crate::{net, AddressFamily, Error, Result, ServerAddress};
+
+/// Everything we learned about a server from a status query.
+#[derive(Debug, Serialize)]
+pub struct ServerStatus {
+    pub online: u32,
+    pub max: u32,
+    /// The sample of online players the server chose to send, which is often
+    /// capped at 12 and may be empty. With [`QueryOptions::full_query`],
+    /// this is everyone who's online instead.
+    pub players: Vec<Player>,
+    pub version: Version,
+    pub motd: Text,
+    /// The icon the server sent, as a `data:image/png;base64,...` URL,
+    /// which is in `icon` if it could be decoded. In JSON, this is only
+    /// whether there is one.
+    #[serde(serialize_with = "serialize_is_some")]
+    pub favicon: Option<String>,
+    #[serde(skip)]
+    pub icon: Option<Icon>,
+    pub latency: Latency,
+    /// The extra details Bedrock Edition servers send.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub bedrock: Option<BedrockStatus>,
+    /// The result of the GameSpy4 query, if it was requested.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub query: Option<FullStat>,
+}
+
+fn serialize_is_some<T, S: Serializer>(
+    value: &Option<T>,
+    serializer: S,
+) -> Result<S::Ok, S::Error> {
+    serializer.serialize_bool(value.is_some())
+}
+
+#[derive(Debug, Clone, Serialize)]
+pub struct Player {
+    pub name: String,
+    /// The player's UUID, which the GameSpy4 query doesn't tell us.
+    pub id: Option<String>,
+}
+
+/// The server's version. Servers from before 1.4 don't report one, in which
+/// case the name is empty and the protocol is -1.
+#[derive(Debug, Serialize)]
+pub struct Version {
+    pub name: String,
+    pub protocol: i32,
+}
+
+/// Round-trip times of the pings sent to the server, in milliseconds.
+#[derive(Debug, Serialize)]
+pub struct Latency {
+    pub min_ms: f64,
+    pub avg_ms: f64,
+    pub max_ms: f64,
+    /// Mean difference between consecutive pings.
+    pub jitter_ms: f64,
+    pub pings: usize,
+}
+
+impl Latency {
+    /// Summarizes a non-empty list of ping round-trip times.
+    pub fn from_samples(samples: &[Duration]) -> Self {
+        let ms = samples
+            .iter()
+            .map(|d| d.as_secs_f64() * 1000.0)
+            .collect_vec();
+        let n = ms.len() as f64;
+        let jitter_ms = if ms.len() > 1 {
+            ms.iter()
+                .tuple_windows()
+                .map(|(a, b)| (b - a).abs())
+                .sum::<f64>()
+                / (n - 1.0)
+        } else {
+            0.0
+        };
+        Latency {
+            min_ms: ms.iter().copied().fold(f64::INFINITY, f64::min),
+            avg_ms: ms.iter().sum::<f64>() / n,
+            max_ms: ms.iter().copied().fold(0.0, f64::max),
+            jitter_ms,
+            pings: ms.len(),
+        }
+    }
+}
+
+#[derive(Debug, Clone)]
+pub struct QueryOptions {
+    /// How long to wait for a TCP connection to be accepted.
+    pub connect_timeout: Duration,
+    /// How long to wait for each response, from the server or from DNS.
+    pub read_timeout: Duration,
+    /// How long the whole query, retries included, may take.
+    pub deadline: Option<Duration>,
+    /// How many times to try again after a failure that might be temporary,
+    /// like a timeout or a refused connection. The wait between attempts
+    /// starts at [`RETRY_DELAY`] and doubles each time.
+    pub retries: u32,
+    /// How many times to ping the server when measuring latency. Must be at
+    /// least 1.
+    pub pings: u32,
+    pub protocol: Protocol,
+    /// Also use the GameSpy4 query protocol to get the full player list,
+    /// which only works for servers with `enable-query=true`.
+    pub full_query: bool,
+    /// The UDP port for the full query [default: the game port]
+    pub query_port: Option<u16>,
+    /// The DNS server to look up `_minecraft._tcp` SRV records with
+    /// [default: the system's, see [`Resolver::system`](crate::dns::Resolver::system)]
+    pub dns_server: Option<SocketAddr>,
+    /// Only connect over IPv4 or IPv6.
+    pub family: AddressFamily,
+}
+
+impl Default for QueryOptions {
+    fn default() -> Self {
+        QueryOptions {
+            connect_timeout: Duration::from_secs(2),
+            read_timeout: Duration::from_secs(2),
+            deadline: None,
+            retries: 0,
+            pings: 1,
+            protocol: Protocol::Auto,
+            full_query: false,
+            query_port: None,
+            dns_server: None,
+            family: AddressFamily::Any,
+        }
+    }
+}
+
+/// Which version of the server list ping to speak.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Protocol {
+    /// Try the modern ping, and fall back to the legacy one if the server
+    /// accepts the connection but doesn't answer properly.
+    Auto,
+    /// Only the modern (1.7+) ping.
+    Modern,
+    /// Only the legacy ping that servers before 1.7 understand.
+    Legacy,
+    /// Bedrock Edition's RakNet ping, over UDP.
+    Bedrock,
+}
+
+/// The step a query is currently at, for showing progress.
+#[derive(Debug, Clone, Copy)]
+pub enum Phase {
+    /// Looking up the server's SRV record or IP addresses.
+    Resolving,
+    Connecting,
+    FetchingStatus,
+    /// Asking for the full player list through the GameSpy4 query.
+    Querying,
+    /// Sending ping number `n` (counting from 1) out of `total`.
+    Pinging {
+        n: u32,
+        total: u32,
+    },
+    /// Waiting to make retry number `n` (counting from 1) out of `total`,
+    /// after the last attempt failed.
+    Retrying {
+        n: u32,
+        total: u32,
+    },
+}
+
+/// How long to wait before the first retry.
+pub const RETRY_DELAY: Duration = Duration::from_millis(500);
+
+/// Queries a server's status and measures its latency.
+pub async fn query_status(addr: &ServerAddress, options: &QueryOptions) -> Result<ServerStatus> {
+    query_status_with_progress(addr, options, |_| {}).await
+}
+
+/// Like [`query_status`], but calls `progress` as the query moves through
+/// each [`Phase`].
+pub async fn query_status_with_progress(
+    addr: &ServerAddress,
+    options: &QueryOptions,
+    mut progress: impl FnMut(Phase),
+) -> Result<ServerStatus> {
+    let query = async {
+        let mut delay = RETRY_DELAY;
+        let mut n = 0;
+        loop {
+            match query_once(addr, options, &mut progress).await {
+                Err(e) if n < options.retries && is_transient(&e) => {
+                    n += 1;
+                    progress(Phase::Retrying {
+                        n,
+                        total: options.retries,
+                    });
+                    tokio::time::sleep(delay).await;
+                    delay *= 2;
+                }
+                res => return res,
+            }
+        }
+    };
+    match options.deadline {
+        Some(deadline) => tokio::time::timeout(deadline, query)
+            .await
+            .unwrap_or(Err(Error::Deadline(deadline))),
+        None => query.await,
+    }
+}
+
+/// Whether trying again might get a different result.
+fn is_transient(err: &Error) -> bool {
+    matches!(
+        err,
+        Error::Resolve(_) | Error::Connect(_) | Error::Timeout | Error::Io(_)
+    )
+}
+
+async fn query_once(
+    addr: &ServerAddress,
+    options: &QueryOptions,
+    progress: &mut impl FnMut(Phase),
+) -> Result<ServerStatus> {
+    let (mut status, server_addr) = match options.protocol {
+        Protocol::Bedrock => {
+            let port = addr.port.unwrap_or(DEFAULT_BEDROCK_PORT);
+            progress(Phase::Resolving);
+            let addrs = net::resolve(&addr.host, port, options.family).await?;
+            query_bedrock(&addrs, options, progress).await?
+        }
+        protocol => {
+            let targets = resolve_srv(addr, options, progress).await;
+            let mut res = Err(Error::Resolve(io::ErrorKind::NotFound.into()));
+            for (host, port) in targets {
+                progress(Phase::Resolving);
+                res = match net::resolve(&host, port, options.family).await {
+                    Ok(addrs) => query_java(protocol, &host, &addrs, options, progress).await,
+                    Err(e) => Err(e),
+                };
+                // only move on to the next SRV target if this one is down
+                if !matches!(res, Err(Error::Resolve(_) | Error::Connect(_))) {
+                    break;
+                }
+            }
+            res?
+        }
+    };
+
+    if options.full_query {
+        progress(Phase::Querying);
+        let port = options.query_port.unwrap_or(server_addr.port());
+        let query_addr = SocketAddr::new(server_addr.ip(), port);
+        let stat = query::full_stat(query_addr, options.read_timeout)
+            .await
+            .map_err(|e| Error::Query(Box::new(e)))?;
+        // keep the UUIDs from the sample for the players that were in it
+        status.players = stat
+            .players
+            .iter()
+            .map(|name| Player {
+                name: name.clone(),
+                id: status
+                    .players
+                    .iter()
+                    .find(|p| p.name == *name)
+                    .and_then(|p| p.id.clone()),
+            })
+            .collect();
+        status.query = Some(stat);
+    }
+
+    Ok(status)
+}
+
+/// Works out which hosts and ports to try for a Java Edition server, in
+/// order. Like the game, we only look for an SRV record when no port is
+/// given, and fall back to the plain host if there isn't one or the lookup
+/// fails.
+async fn resolve_srv(
+    addr: &ServerAddress,
+    options: &QueryOptions,
+    progress: &mut impl FnMut(Phase),
+) -> Vec<(String, u16)> {
+    let fallback = vec![(addr.host.clone(), addr.port_or_default())];
+    if !addr.uses_srv() {
+        return fallback;
+    }
+    let resolver = match options.dns_server {
+        Some(server) => Resolver::new(server),
+        None => match Resolver::system() {
+            Some(resolver) => resolver,
+            None => return fallback,
+        },
+    };
+
+    progress(Phase::Resolving);
+    let name = format!("_minecraft._tcp.{}", addr.host);
+    match resolver.lookup_srv(&name, options.read_timeout).await {
+        Ok(records) if !records.is_empty() => records
+            .into_iter()
+            .map(|record| (record.target, record.port))
+            .collect(),
+        _ => fallback,
+    }
+}
+
+/// Opens a modern status connection with the timeouts from `options`.
+pub(crate) async fn connect_modern(
+    addr: SocketAddr,
+    host: &str,
+    options: &QueryOptions,
+) -> Result<Connection> {
+    let mut conn = Connection::connect(addr, host, options.connect_timeout).await?;
+    conn.set_read_timeout(options.read_timeout);
+    Ok(conn)
+}
+
+/// Opens a legacy status connection with the timeouts from `options`.
+pub(crate) async fn connect_legacy(
+    addr: SocketAddr,
+    host: &str,
+    options: &QueryOptions,
+) -> Result<LegacyConnection> {
+    let mut conn = LegacyConnection::connect(addr, host, options.connect_timeout).await?;
+    conn.set_read_timeout(options.read_timeout);
+    Ok(conn)
+}
+
+/// Connects to the first of `addrs` that accepts the connection, and
+/// returns the connection along with the address it went to.
+pub(crate) async fn connect_first<T, F: Future<Output = Result<T>>>(
+    addrs: &[SocketAddr],
+    mut connect: impl FnMut(SocketAddr) -> F,
+) -> Result<(T, SocketAddr)> {
+    let mut err = Error::Resolve(io::ErrorKind::NotFound.into());
+    for &addr in addrs {
+        match connect(addr).await {
+            Ok(conn) => return Ok((conn, addr)),
+            Err(e @ Error::Connect(_)) => err = e,
+            Err(e) => return Err(e),
+        }
+    }
+    Err(err)
+}
+
+async fn query_java(
+    protocol: Protocol,
+    host: &str,
+    addrs: &[SocketAddr],
+    options: &QueryOptions,
+    progress: &mut impl FnMut(Phase),
+) -> Result<(ServerStatus, SocketAddr)> {
+    match protocol {
+        Protocol::Modern => query_modern(host, addrs, options, progress).await,
+        Protocol::Legacy => query_legacy(host, addrs, options, progress).await,
+        Protocol::Auto => match query_modern(host, addrs, options, progress).await {
+            // servers from before 1.7 don't understand the modern handshake
+            // and mostly just sit on it, so if we got as far as connecting,
+            // try again the old way
+            Err(e) if !matches!(e, Error::Connect(_)) => {
+                query_legacy(host, addrs, options, progress)
+                    .await
+                    .map_err(|_| e)
+            }
+            res => res,
+        },
+        Protocol::Bedrock => unreachable!(),
+    }
+}
+
+async fn query_modern(
+    host: &str,
+    addrs: &[SocketAddr],
+    options: &QueryOptions,
+    progress: &mut impl FnMut(Phase),
+) -> Result<(ServerStatus, SocketAddr)> {
+    let pings = options.pings;
+
+    progress(Phase::Connecting);
+    let (mut conn, addr) = connect_first(addrs, |addr| connect_modern(addr, host, options)).await?;
+    progress(Phase::FetchingStatus);
+    let status = conn.status().await?;
+
+    let players = status
+        .players
+        .sample
+        .into_iter()
+        .flatten()
+        .map(|player| Player {
+            name: player.name,
+            id: Some(player.id),
+        })
+        .collect();
+
+    let mut samples = Vec::with_capacity(pings as usize);
+    let mut status_conn = Some(conn);
+    for n in 1..=pings.max(1) {
+        progress(Phase::Pinging { n, total: pings });
+        // the server hangs up after answering a ping, so every extra ping
+        // needs a fresh connection
+        let conn = match status_conn.take() {
+            Some(conn) => conn,
+            None => connect_modern(addr, host, options).await?,
+        };
+        let start = Instant::now();
+        conn.ping(0x8008135).await?;
+        samples.push(start.elapsed());
+    }
+
+    let status = ServerStatus {
+        online: status.players.online,
+        max: status.players.max,
+        players,
+        version: Version {
+            name: status.version.name,
+            protocol: status.version.protocol,
+        },
+        motd: Text::from_json(&status.description),
+        icon: status
+            .favicon
+            .as_deref()
+            .and_then(|url| Icon::from_data_url(url).ok()),
+        favicon: status.favicon,
+        latency: Latency::from_samples(&samples),
+        bedrock: None,
+        query: None,
+    };
+    Ok((status, addr))
+}
+
+/// Queries a pre-1.7 server. The legacy ping has no separate ping packet, so
+/// the latency is how long the server takes to answer the status request,
+/// and every ping after the first is another status request.
+async fn query_legacy(
+    host: &str,
+    addrs: &[SocketAddr],
+    options: &QueryOptions,
+    progress: &mut impl FnMut(Phase),
+) -> Result<(ServerStatus, SocketAddr)> {
+    let pings = options.pings;
+
+    let mut addrs = addrs;
+    let mut status = None;
+    let mut samples = Vec::with_capacity(pings as usize);
+    for n in 1..=pings.max(1) {
+        progress(Phase::Connecting);
+        let (conn, addr) = connect_first(addrs, |addr| connect_legacy(addr, host, options)).await?;
+        progress(match n {
+            1 => Phase::FetchingStatus,
+            _ => Phase::Pinging { n, total: pings },
+        });
+        let start = Instant::now();
+        status = Some((conn.status().await?, addr));
+        samples.push(start.elapsed());
+        // stick to the address that worked for the rest of the pings
+        let index = addrs.iter().position(|&a| a == addr).unwrap();
+        addrs = &addrs[index..=index];
+    }
+    let (status, addr) = status.unwrap();
+
+    let (protocol, name) = status.version.unwrap_or((-1, String::new()));
+    let status = ServerStatus {
+        online: status.online,
+        max: status.max,
+        players: Vec::new(),
+        version: Version { name, protocol },
+        motd: Text::from_legacy(&status.motd),
+        favicon: None,
+        icon: None,
+        latency: Latency::from_samples(&samples),
+        bedrock: None,
+        query: None,
+    };
+    Ok((status, addr))
+}
+
+/// Queries a Bedrock Edition server, where every ping also carries the
+/// status. There's no connection to tell us whether an address works, so
+/// this only uses the first one.
+async fn query_bedrock(
+    addrs: &[SocketAddr],
+    options: &QueryOptions,
+    progress: &mut impl FnMut(Phase),
+) -> Result<(ServerStatus, SocketAddr)> {
+    let QueryOptions {
+        read_timeout,
+        pings,
+        ..
+    } = *options;
+    let addr = addrs[0];
+
+    let mut status = None;
+    let mut samples = Vec::with_capacity(pings as usize);
+    for n in 1..=pings.max(1) {
+        progress(match n {
+            1 => Phase::FetchingStatus,
+            _ => Phase::Pinging { n, total: pings },
+        });
+        let (res, rtt) = bedrock::ping(addr, read_timeout).await?;
+        status = Some(res);
+        samples.push(rtt);
+    }
+    let status = status.unwrap();
+
+    let mut motd = status.motd.clone();
+    if let Some(level_name) = &status.level_name {
+        motd.push('\n');
+        motd.push_str(level_name);
+    }
+    let status = ServerStatus {
+        online: status.online,
+        max: status.max,
+        players: Vec::new(),
+        version: Version {
+            name: status.version.clone(),
+            protocol: status.protocol,
+        },
+        motd: Text::from_legacy(&motd),
+        favicon: None,
+        icon: None,
+        latency: Latency::from_samples(&samples),
+        bedrock: Some(status),
+        query: None,
+    };
+    Ok((status, addr))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn summarizes_latency_samples() {
+        let latency = Latency::from_samples(&[10, 30, 20, 20].map(Duration::from_millis));
+        assert_eq!(latency.min_ms, 10.0);
+        assert_eq!(latency.avg_ms, 20.0);
+        assert_eq!(latency.max_ms, 30.0);
+        // |30 - 10| + |20 - 30| + |20 - 20|, over the 3 gaps
+        assert_eq!(latency.jitter_ms, 10.0);
+        assert_eq!(latency.pings, 4);
+
+        let latency = Latency::from_samples(&[Duration::from_micros(42400)]);
+        assert_eq!(
+            (
+                latency.min_ms,
+                latency.avg_ms,
+                latency.max_ms,
+                latency.jitter_ms
+            ),
+            (42.4, 42.4, 42.4, 0.0)
+        );
+    }
+
+    #[test]
+    fn serializes_the_status() {
+        let status = ServerStatus {
+            online: 1,
+            max: 20,
+            players: vec![Player {
+                name: "Alice".to_owned(),
+                id: Some("4566e69f-c907-48ee-8d71-d7ba5aa00d20".to_owned()),
+            }],
+            version: Version {
+                name: "1.19.2".to_owned(),
+                protocol: 760,
+            },
+            motd: Text::from_json(&serde_json::json!("§aA §lMinecraft§r Server")),
+            favicon: None,
+            icon: None,
+            latency: Latency::from_samples(&[Duration::from_micros(12500)]),
+            bedrock: None,
+            query: None,
+        };
+        assert_eq!(
+            serde_json::to_value(&status).unwrap(),
+            serde_json::json!({
+                "online": 1,
+                "max": 20,
+                "players": [{"name": "Alice", "id": "4566e69f-c907-48ee-8d71-d7ba5aa00d20"}],
+                "version": {"name": "1.19.2", "protocol": 760},
+                "motd": "A Minecraft Server",
+                "favicon": false,
+                "latency": {
+                    "min_ms": 12.5,
+                    "avg_ms": 12.5,
+                    "max_ms": 12.5,
+                    "jitter_ms": 0.0,
+                    "pings": 1,
+                },
+            })
+        );
+    }
+
+    #[tokio::test]
+    async fn reports_connecting_before_failing() {
+        let closed = std::net::TcpListener::bind("127.0.0.1:0")
+            .unwrap()
+            .local_addr()
+            .unwrap();
+        let addr = ServerAddress::new("127.0.0.1", Some(closed.port()));
+        let mut phases = Vec::new();
+        let res =
+            query_status_with_progress(&addr, &QueryOptions::default(), |phase| phases.push(phase))
+                .await;
+        assert!(matches!(res, Err(crate::Error::Connect(_))), "{res:?}");
+        assert!(
+            matches!(phases[..], [Phase::Resolving, Phase::Connecting]),
+            "{phases:?}"
+        );
+    }
+
+    #[tokio::test]
+    async fn falls_back_to_the_legacy_ping() {
+        use tokio::io::{AsyncReadExt, AsyncWriteExt};
+
+        let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let addr = ServerAddress::new("127.0.0.1", Some(listener.local_addr().unwrap().port()));
+        tokio::spawn(async move {
+            // a pre-1.7 server hangs up on the modern handshake...
+            drop(listener.accept().await.unwrap());
+            // ...but answers the legacy ping
+            let (mut stream, _) = listener.accept().await.unwrap();
+            stream.read_u8().await.unwrap();
+            let kick = "§1\x0078\x001.6.4\x00§aHello\x002\x0010"
+                .encode_utf16()
+                .flat_map(u16::to_be_bytes);
+            let mut response = vec![0xff, 0x00, 24];
+            response.extend(kick);
+            stream.write_all(&response).await.unwrap();
+        });
+
+        let status = query_status(&addr, &QueryOptions::default()).await.unwrap();
+        assert_eq!((status.online, status.max), (2, 10));
+        assert_eq!(status.version.name, "1.6.4");
+        assert_eq!(status.version.protocol, 78);
+        assert_eq!(status.motd.plain(), "Hello");
+        assert!(status.players.is_empty());
+    }
+
+    #[tokio::test]
+    async fn retries_transient_failures() {
+        let closed = std::net::TcpListener::bind("127.0.0.1:0")
+            .unwrap()
+            .local_addr()
+            .unwrap();
+        let addr = ServerAddress::new("127.0.0.1", Some(closed.port()));
+        let options = QueryOptions {
+            retries: 2,
+            ..QueryOptions::default()
+        };
+        let mut retries = Vec::new();
+        let res = query_status_with_progress(&addr, &options, |phase| {
+            if let Phase::Retrying { n, total } = phase {
+                retries.push((n, total));
+            }
+        })
+        .await;
+        assert!(matches!(res, Err(Error::Connect(_))), "{res:?}");
+        assert_eq!(retries, [(1, 2), (2, 2)]);
+    }
+
+    #[tokio::test]
+    async fn gives_up_at_the_deadline() {
+        // accepts the connection, but never answers
+        let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let addr = ServerAddress::new("127.0.0.1", Some(listener.local_addr().unwrap().port()));
+        tokio::spawn(async move {
+            let _conn = listener.accept().await.unwrap();
+            std::future::pending::<()>().await;
+        });
+        let options = QueryOptions {
+            read_timeout: Duration::from_secs(30),
+            deadline: Some(Duration::from_millis(100)),
+            protocol: Protocol::Modern,
+            ..QueryOptions::default()
+        };
+        let res = query_status(&addr, &options).await;
+        assert!(
+            matches!(res, Err(Error::Deadline(d)) if d == Duration::from_millis(100)),
+            "{res:?}"
+        );
+    }
+
+    #[test]
+    fn only_retries_what_might_go_away() {
+        let io = || std::io::Error::from(std::io::ErrorKind::ConnectionRefused);
+        assert!(is_transient(&Error::Connect(io())));
+        assert!(is_transient(&Error::Resolve(io())));
+        assert!(is_transient(&Error::Timeout));
+        assert!(is_transient(&Error::Io(io())));
+        assert!(!is_transient(&Error::InvalidPacket("bad")));
+        assert!(!is_transient(&Error::Dns("truncated response")));
+        assert!(!is_transient(&Error::Deadline(Duration::from_secs(1))));
+    }
+}
@@ -0,0 +1,510 @@
+//! The `tui` subcommand: a full-screen dashboard of every server in
+//! servers.dat that keeps itself up to date, for leaving open on a spare
+//! monitor.
+
+use std::io;
+use std::path::PathBuf;
+use std::thread;
+use std::time::{Duration, SystemTime};
+
+use console::{Key, Term};
+use itertools::Itertools;
+use mcserverstatus::{QueryOptions, Server};
+use tokio::sync::mpsc;
+use tokio::time::MissedTickBehavior;
+
+use crate::{format_table, format_time_of_day, query_all, DisplayLatency, Millis, ServerResult};
+
+#[derive(clap::Args)]
+pub struct TuiArgs {
+    /// Path to the folder for your minecraft instance, or the name of a
+    /// launcher instance (see --list-instances) [default: the config's
+    /// instance, or pick from the instances that have saved servers]
+    #[clap(short, long, value_name = "PATH|NAME", parse(from_os_str))]
+    pub instance: Option<PathBuf>,
+
+    /// Path to the servers.dat file to show the servers from
+    #[clap(short = 'f', long, parse(from_os_str), conflicts_with = "instance")]
+    pub servers_file: Option<PathBuf>,
+
+    /// Seconds between refreshes
+    #[clap(long, value_name = "SECS", default_value = "10", value_parser = crate::parse_secs)]
+    interval: Duration,
+}
+
+const HELP: &str = "↑/↓ select  enter details  r refresh  q quit";
+
+/// Shows the dashboard until the user quits.
+pub async fn run(
+    args: &TuiArgs,
+    servers: Vec<Server>,
+    options: &QueryOptions,
+    term: &Term,
+) -> anyhow::Result<()> {
+    if !term.is_term() {
+        anyhow::bail!("The dashboard needs a terminal to draw on");
+    }
+    // the MOTD's colors go by stdout, but we draw on `term`
+    console::set_colors_enabled(term.features().colors_supported());
+    let _screen = AltScreen::enter(term)?;
+    let mut keys = read_keys(term.clone());
+
+    let (results_tx, mut results_rx) = mpsc::channel(1);
+    let mut dash = Dashboard {
+        servers,
+        results: Vec::new(),
+        refreshed: None,
+        refreshing: false,
+        selected: 0,
+        scroll: 0,
+        expanded: false,
+    };
+    let mut refresh = tokio::time::interval(args.interval);
+    refresh.set_missed_tick_behavior(MissedTickBehavior::Delay);
+    // redraw every second as well, to catch the terminal being resized
+    let mut redraw = tokio::time::interval(Duration::from_secs(1));
+
+    loop {
+        tokio::select! {
+            _ = refresh.tick(), if !dash.refreshing => dash.refresh(options, &results_tx),
+            Some(results) = results_rx.recv() => {
+                dash.results = results;
+                dash.refreshed = Some(SystemTime::now());
+                dash.refreshing = false;
+            }
+            key = keys.recv() => match key {
+                None | Some(Key::Char('q')) => return Ok(()),
+                Some(Key::Char('r')) if !dash.refreshing => {
+                    dash.refresh(options, &results_tx);
+                    refresh.reset();
+                }
+                Some(key) => dash.handle_key(key),
+            },
+            _ = redraw.tick() => {}
+        }
+        dash.draw(term)?;
+    }
+}
+
+/// Switches to the terminal's alternate screen for as long as it's alive,
+/// so that quitting leaves the scrollback the way it was. On ctrl-c, `main`
+/// drops this along with the rest of the app before restoring the cursor.
+struct AltScreen<'a>(&'a Term);
+
+impl<'a> AltScreen<'a> {
+    fn enter(term: &'a Term) -> io::Result<Self> {
+        term.write_str("\x1b[?1049h")?;
+        term.hide_cursor()?;
+        Ok(AltScreen(term))
+    }
+}
+
+impl Drop for AltScreen<'_> {
+    fn drop(&mut self) {
+        let _ = self.0.write_str("\x1b[?1049l");
+        let _ = self.0.show_cursor();
+    }
+}
+
+/// Reads keys on a thread of their own, since reading blocks. The thread
+/// stops at `q`, so that it isn't left holding the terminal in raw mode
+/// after we quit; ctrl-c stops it too, and `console` turns that into a
+/// SIGINT for `main` to handle.
+fn read_keys(term: Term) -> mpsc::UnboundedReceiver<Key> {
+    let (tx, rx) = mpsc::unbounded_channel();
+    thread::spawn(move || {
+        while let Ok(key) = term.read_key() {
+            let quit = key == Key::Char('q');
+            if tx.send(key).is_err() || quit {
+                break;
+            }
+        }
+    });
+    rx
+}
+
+struct Dashboard {
+    servers: Vec<Server>,
+    /// The results of the last refresh, in the same order as `servers`, or
+    /// empty before the first one is done.
+    results: Vec<ServerResult>,
+    refreshed: Option<SystemTime>,
+    refreshing: bool,
+    selected: usize,
+    /// The first server in view.
+    scroll: usize,
+    /// Whether the selected server's details are showing.
+    expanded: bool,
+}
+
+impl Dashboard {
+    fn refresh(&mut self, options: &QueryOptions, tx: &mpsc::Sender<Vec<ServerResult>>) {
+        self.refreshing = true;
+        let servers = self.servers.clone();
+        let options = options.clone();
+        let tx = tx.clone();
+        tokio::spawn(async move {
+            let _ = tx.send(query_all(servers, &options).await).await;
+        });
+    }
+
+    fn handle_key(&mut self, key: Key) {
+        let last = self.servers.len().saturating_sub(1);
+        match key {
+            Key::ArrowUp | Key::Char('k') => self.selected = self.selected.saturating_sub(1),
+            Key::ArrowDown | Key::Char('j') => self.selected = (self.selected + 1).min(last),
+            Key::PageUp => self.selected = self.selected.saturating_sub(10),
+            Key::PageDown => self.selected = (self.selected + 10).min(last),
+            Key::Home | Key::Char('g') => self.selected = 0,
+            Key::End | Key::Char('G') => self.selected = last,
+            Key::Enter | Key::Char(' ') => self.expanded = !self.expanded,
+            Key::Escape => self.expanded = false,
+            _ => {}
+        }
+    }
+
+    fn draw(&mut self, term: &Term) -> io::Result<()> {
+        let (height, width) = term.size();
+        let (height, width) = (usize::from(height), usize::from(width));
+
+        let up = self
+            .results
+            .iter()
+            .filter(|res| res.status.is_some())
+            .count();
+        let mut header = match self.results.is_empty() {
+            true => format!("{} servers", self.servers.len()),
+            false => format!("{} servers, {up} up", self.servers.len()),
+        };
+        if let Some(time) = self.refreshed {
+            header += &format!(", updated {} UTC", format_time_of_day(time));
+        }
+        if self.refreshing {
+            header += " (refreshing...)";
+        }
+
+        let mut details = match self.expanded {
+            true => self.details(width),
+            false => Vec::new(),
+        };
+        // leave at least half the screen for the table
+        details.truncate(height / 2);
+        // the header, column names and help take a line each
+        let table_height = height.saturating_sub(3 + details.len()).max(1);
+        if self.selected < self.scroll {
+            self.scroll = self.selected;
+        } else if self.selected >= self.scroll + table_height {
+            self.scroll = self.selected + 1 - table_height;
+        }
+
+        let mut table = format_table(["NAME", "IP", "ONLINE", "LATENCY", "MOTD"], &self.rows());
+        let columns = table.remove(0);
+        let mut lines = vec![
+            console::style(header).bold().to_string(),
+            console::style(columns).dim().to_string(),
+        ];
+        for (i, line) in table
+            .into_iter()
+            .enumerate()
+            .skip(self.scroll)
+            .take(table_height)
+        {
+            let line = fit(&line, width);
+            let failed = self.results.get(i).is_some_and(|res| res.error.is_some());
+            lines.push(match (i == self.selected, failed) {
+                (true, _) => console::style(line).reverse().to_string(),
+                (false, true) => console::style(line).red().to_string(),
+                (false, false) => line,
+            });
+        }
+        lines.resize(height.saturating_sub(1 + details.len()), String::new());
+        lines.extend(details);
+        lines.push(console::style(HELP).dim().to_string());
+
+        // draw over the last frame rather than clearing it, which flickers
+        let frame = lines
+            .iter()
+            .take(height)
+            .map(|line| fit(line, width))
+            .join("\r\n");
+        term.move_cursor_to(0, 0)?;
+        term.write_str(&frame)?;
+        term.write_str("\x1b[J")
+    }
+
+    fn rows(&self) -> Vec<[String; 5]> {
+        self.servers
+            .iter()
+            .enumerate()
+            .map(|(i, server)| {
+                let (online, latency, motd) = match self.results.get(i) {
+                    Some(ServerResult {
+                        status: Some(status),
+                        ..
+                    }) => (
+                        format!("{}/{}", status.online, status.max),
+                        format!("{}ms", Millis(status.latency.avg_ms)),
+                        status
+                            .motd
+                            .plain()
+                            .lines()
+                            .next()
+                            .unwrap_or("")
+                            .trim()
+                            .to_owned(),
+                    ),
+                    Some(res) => (
+                        "-".to_owned(),
+                        "-".to_owned(),
+                        res.error.clone().unwrap_or_default(),
+                    ),
+                    None => ("...".to_owned(), "...".to_owned(), String::new()),
+                };
+                [
+                    server.name.clone(),
+                    server.ip.clone(),
+                    online,
+                    latency,
+                    motd,
+                ]
+            })
+            .collect()
+    }
+
+    /// The lines for the selected server's full MOTD and player list.
+    fn details(&self, width: usize) -> Vec<String> {
+        let Some(server) = self.servers.get(self.selected) else {
+            return Vec::new();
+        };
+        let mut lines = vec![console::style(format!("── {server} ")).bold().to_string()];
+        match self.results.get(self.selected) {
+            Some(ServerResult {
+                status: Some(status),
+                ..
+            }) => {
+                lines.extend(status.motd.styled().to_string().lines().map(String::from));
+                if !status.version.name.is_empty() {
+                    lines.push(format!(
+                        "Version: {} (protocol {})",
+                        status.version.name, status.version.protocol
+                    ));
+                }
+                lines.push(format!("Latency: {}", DisplayLatency(&status.latency)));
+                lines.push(format!(
+                    "{}/{} online{}",
+                    status.online,
+                    status.max,
+                    if status.players.is_empty() { "" } else { ":" }
+                ));
+                let players = status.players.iter().map(|player| &*player.name).join(" ");
+                let options = textwrap::Options::new(width.saturating_sub(4).max(20))
+                    .initial_indent("    ")
+                    .subsequent_indent("    ");
+                lines.extend(
+                    textwrap::wrap(&players, options)
+                        .into_iter()
+                        .map(String::from),
+                );
+            }
+            Some(ServerResult {
+                error: Some(error), ..
+            }) => lines.push(console::style(format!("Error: {error}")).red().to_string()),
+            _ => lines.push("Waiting for the first refresh...".to_owned()),
+        }
+        lines
+    }
+}
+
+/// Pads or cuts `line` to exactly `width` columns.
+fn fit(line: &str, width: usize) -> String {
+    if console::measure_text_width(line) > width {
+        console::truncate_str(line, width, "…").into_owned()
+    } else {
+        console::pad_str(line, width, console::Alignment::Left, None).into_owned()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use mcserverstatus::motd::Text;
+    use mcserverstatus::{Latency, Player, ServerStatus, Version};
+
+    use super::*;
+
+    fn dashboard(servers: usize) -> Dashboard {
+        Dashboard {
+            servers: (0..servers)
+                .map(|i| Server {
+                    ip: format!("mc{i}.example.net"),
+                    name: format!("Server {i}"),
+                    icon: None,
+                })
+                .collect(),
+            results: Vec::new(),
+            refreshed: None,
+            refreshing: false,
+            selected: 0,
+            scroll: 0,
+            expanded: false,
+        }
+    }
+
+    fn up(online: u32, players: &[&str]) -> ServerResult {
+        ServerResult {
+            name: String::new(),
+            ip: String::new(),
+            status: Some(ServerStatus {
+                online,
+                max: 20,
+                players: players
+                    .iter()
+                    .map(|&name| Player {
+                        name: name.to_owned(),
+                        id: None,
+                    })
+                    .collect(),
+                version: Version {
+                    name: "1.20.1".to_owned(),
+                    protocol: 763,
+                },
+                motd: Text::from_legacy("§aA Minecraft Server\n§7second line"),
+                favicon: None,
+                icon: None,
+                latency: Latency::from_samples(&[Duration::from_millis(42)]),
+                bedrock: None,
+                query: None,
+            }),
+            error: None,
+            error_kind: None,
+        }
+    }
+
+    fn down(error: &str) -> ServerResult {
+        ServerResult {
+            name: String::new(),
+            ip: String::new(),
+            status: None,
+            error: Some(error.to_owned()),
+            error_kind: None,
+        }
+    }
+
+    fn plain(lines: Vec<String>) -> Vec<String> {
+        lines
+            .iter()
+            .map(|line| console::strip_ansi_codes(line).into_owned())
+            .collect()
+    }
+
+    #[test]
+    fn moves_the_selection() {
+        let mut dash = dashboard(25);
+        for (key, selected) in [
+            (Key::ArrowUp, 0),
+            (Key::ArrowDown, 1),
+            (Key::Char('j'), 2),
+            (Key::Char('k'), 1),
+            (Key::PageDown, 11),
+            (Key::PageDown, 21),
+            (Key::PageDown, 24),
+            (Key::ArrowDown, 24),
+            (Key::PageUp, 14),
+            (Key::Home, 0),
+            (Key::Char('G'), 24),
+            (Key::Char('g'), 0),
+            (Key::End, 24),
+        ] {
+            dash.handle_key(key.clone());
+            assert_eq!(dash.selected, selected, "after {key:?}");
+        }
+
+        dash.handle_key(Key::Enter);
+        assert!(dash.expanded);
+        dash.handle_key(Key::Char(' '));
+        assert!(!dash.expanded);
+        dash.handle_key(Key::Enter);
+        dash.handle_key(Key::Escape);
+        assert!(!dash.expanded);
+
+        // nothing to select
+        let mut empty = dashboard(0);
+        empty.handle_key(Key::ArrowDown);
+        empty.handle_key(Key::End);
+        assert_eq!(empty.selected, 0);
+    }
+
+    #[test]
+    fn shows_each_server_as_a_row() {
+        let mut dash = dashboard(3);
+        assert_eq!(
+            dash.rows()[0],
+            ["Server 0", "mc0.example.net", "...", "...", ""]
+        );
+
+        dash.results = vec![up(3, &[]), down("timed out waiting for the server")];
+        let rows = dash.rows();
+        assert_eq!(
+            rows[0],
+            [
+                "Server 0",
+                "mc0.example.net",
+                "3/20",
+                "42ms",
+                "A Minecraft Server"
+            ]
+        );
+        assert_eq!(
+            rows[1],
+            [
+                "Server 1",
+                "mc1.example.net",
+                "-",
+                "-",
+                "timed out waiting for the server"
+            ]
+        );
+        // a refresh can be missing servers that were added since
+        assert_eq!(rows[2][2], "...");
+    }
+
+    #[test]
+    fn shows_the_selected_servers_details() {
+        let mut dash = dashboard(3);
+        assert_eq!(
+            plain(dash.details(80)),
+            [
+                "── Server 0 (ip: mc0.example.net) ",
+                "Waiting for the first refresh..."
+            ]
+        );
+
+        dash.results = vec![up(2, &["Alice", "Bob"]), down("connection refused")];
+        assert_eq!(
+            plain(dash.details(80)),
+            [
+                "── Server 0 (ip: mc0.example.net) ",
+                "A Minecraft Server",
+                "second line",
+                "Version: 1.20.1 (protocol 763)",
+                "Latency: 42ms",
+                "2/20 online:",
+                "    Alice Bob",
+            ]
+        );
+
+        dash.selected = 1;
+        assert_eq!(plain(dash.details(80))[1..], ["Error: connection refused"]);
+    }
+
+    #[test]
+    fn fits_lines_to_the_width() {
+        assert_eq!(fit("abc", 5), "abc  ");
+        assert_eq!(fit("abcde", 5), "abcde");
+        assert_eq!(fit("abcdefgh", 5), "abcd…");
+        assert_eq!(fit("", 0), "");
+        // escape codes don't take up any room
+        let red = console::style("abc").red().force_styling(true).to_string();
+        assert_eq!(console::measure_text_width(&fit(&red, 5)), 5);
+        assert_eq!(console::measure_text_width(&fit(&red, 2)), 2);
+    }
+}